@@ -0,0 +1,191 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::rfc2136::Rfc2136Provider;
+use super::{Error, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::time::Duration;
+
+/// The provider which manages the txt record of dns-01 challenge.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Add the txt record, the name is the full name of record,
+    /// e.g. `_acme-challenge.example.com`.
+    async fn add_txt_record(&self, name: &str, value: &str) -> Result<()>;
+    /// Remove the txt record which is added for challenge.
+    async fn remove_txt_record(&self, name: &str, value: &str) -> Result<()>;
+}
+
+/// Get the txt record name of dns-01 challenge,
+/// the wildcard domain uses the same record as its base domain.
+pub fn get_challenge_record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+}
+
+/// Create a dns provider by url, `rfc2136://` for dynamic dns update,
+/// `http://` or `https://` for webhook.
+pub fn new_dns_provider(value: &str) -> Result<Box<dyn DnsProvider>> {
+    let scheme = value.split("://").next().unwrap_or_default();
+    match scheme {
+        "rfc2136" => Ok(Box::new(Rfc2136Provider::new(value)?)),
+        "http" | "https" => Ok(Box::new(WebhookProvider::new(value))),
+        _ => Err(Error::DnsProvider {
+            message: format!("dns provider({value}) is not supported"),
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookRecord<'a> {
+    action: &'a str,
+    name: &'a str,
+    value: &'a str,
+}
+
+/// The dns provider which posts the txt record to a http webhook,
+/// the body is `{"action": "add", "name": "...", "value": "..."}`
+/// and the action is `add` or `remove`.
+pub struct WebhookProvider {
+    url: String,
+    timeout: Duration,
+}
+
+impl WebhookProvider {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+    async fn send(&self, action: &str, name: &str, value: &str) -> Result<()> {
+        let resp = reqwest::Client::new()
+            .post(&self.url)
+            .timeout(self.timeout)
+            .json(&WebhookRecord {
+                action,
+                name,
+                value,
+            })
+            .send()
+            .await
+            .map_err(|e| Error::DnsProvider {
+                message: e.to_string(),
+            })?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(Error::DnsProvider {
+                message: format!("dns webhook fail, status: {status}, body: {body}"),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for WebhookProvider {
+    async fn add_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        self.send("add", name, value).await
+    }
+    async fn remove_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        self.send("remove", name, value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_challenge_record_name, new_dns_provider};
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_get_challenge_record_name() {
+        assert_eq!(
+            "_acme-challenge.pingap.io",
+            get_challenge_record_name("pingap.io")
+        );
+        assert_eq!(
+            "_acme-challenge.pingap.io",
+            get_challenge_record_name("*.pingap.io")
+        );
+        assert_eq!(
+            "Dns provider error, dns provider(ftp://127.0.0.1) is not supported",
+            new_dns_provider("ftp://127.0.0.1")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_provider() {
+        // the stand-in of dns webhook
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut bodies = vec![];
+            for status in ["200 OK", "500 Internal Server Error"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut data = vec![];
+                let mut buf = vec![0; 1024];
+                // read until the json body is received
+                while !data.ends_with(b"}") {
+                    let size = stream.read(&mut buf).await.unwrap();
+                    if size == 0 {
+                        break;
+                    }
+                    data.extend(&buf[..size]);
+                }
+                let data = std::string::String::from_utf8_lossy(&data).to_string();
+                let (head, body) = data.split_once("\r\n\r\n").unwrap_or_default();
+                bodies.push((head.to_lowercase(), body.to_string()));
+                let resp = format!("HTTP/1.1 {status}\r\nContent-Length: 4\r\n\r\nfail");
+                stream.write_all(resp.as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+
+        let provider = new_dns_provider(&format!("http://{addr}/dns")).unwrap();
+        provider
+            .add_txt_record("_acme-challenge.pingap.io", "abc")
+            .await
+            .unwrap();
+        let result = provider
+            .remove_txt_record("_acme-challenge.pingap.io", "abc")
+            .await;
+        assert_eq!(
+            "Dns provider error, dns webhook fail, status: 500 Internal Server Error, body: fail",
+            result.err().unwrap().to_string()
+        );
+
+        let bodies = server.await.unwrap();
+        for (head, _) in bodies.iter() {
+            assert_eq!(true, head.starts_with("post /dns http/1.1\r\n"));
+            assert_eq!(true, head.contains("\r\ncontent-type: application/json"));
+        }
+        assert_eq!(
+            vec![
+                r#"{"action":"add","name":"_acme-challenge.pingap.io","value":"abc"}"#.to_string(),
+                r#"{"action":"remove","name":"_acme-challenge.pingap.io","value":"abc"}"#
+                    .to_string(),
+            ],
+            bodies
+                .iter()
+                .map(|(_, body)| body.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+}
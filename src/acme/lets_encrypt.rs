@@ -12,112 +12,607 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{get_cert_info, Cert, Error, Result};
-use crate::config::get_current_config;
+use super::dns_provider::get_challenge_record_name;
+use super::storage::{get_acme_storage, new_acme_storage, AcmeStorage};
+use super::tls_alpn::set_tls_alpn_challenge;
+use super::{get_cert_info, new_dns_provider, AcmeAccount, Cert, DnsProvider, Error, Result};
+use crate::config::{get_current_config, BasicConf};
 use crate::http_extra::HttpResponse;
+use crate::proxy;
 use crate::service::{CommonServiceTask, ServiceTask};
 use crate::state::{restart_now, State};
 use crate::util;
 use crate::webhook;
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
-use base64::{engine::general_purpose::STANDARD, Engine};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
 use http::StatusCode;
 use instant_acme::{
-    Account, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder, OrderStatus,
+    Account, AccountCredentials, ChallengeType, ExternalAccountKey, HttpClient, Identifier,
+    LetsEncrypt, NewAccount, NewOrder, Order, OrderStatus,
 };
 use log::{error, info};
-use once_cell::sync::OnceCell;
+use once_cell::sync::Lazy;
 use pingora::proxy::Session;
 use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::env;
-use std::path::PathBuf;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 
-static LETS_ENCRYPT: OnceCell<Mutex<HashMap<String, String>>> = OnceCell::new();
+const ZERO_SSL_DIRECTORY: &str = "https://acme.zerossl.com/v2/DV90";
 
-fn get_lets_encrypt() -> &'static Mutex<HashMap<String, String>> {
-    LETS_ENCRYPT.get_or_init(|| Mutex::new(HashMap::new()))
-}
 struct LetsEncryptService {
+    name: String,
     domains: Vec<String>,
 }
 
-pub fn new_lets_encrypt_service(domains: Vec<String>) -> CommonServiceTask {
+/// The status of acme certificate, it's used for admin api.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct AcmeCertStatus {
+    pub name: String,
+    pub domains: Vec<String>,
+    pub not_before: i64,
+    pub not_after: i64,
+    pub renewing: bool,
+    pub renewed_at: Option<u64>,
+    pub error: Option<String>,
+}
+
+static ACME_CERT_STATUS: Lazy<std::sync::Mutex<HashMap<String, AcmeCertStatus>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+fn update_acme_cert_status(name: &str, update: impl FnOnce(&mut AcmeCertStatus)) {
+    let mut status_map = ACME_CERT_STATUS.lock().unwrap_or_else(|e| e.into_inner());
+    let status = status_map
+        .entry(name.to_string())
+        .or_insert_with(|| AcmeCertStatus {
+            name: name.to_string(),
+            ..Default::default()
+        });
+    update(status);
+}
+
+/// Get the status list of acme certificates, it's sorted by name.
+pub fn get_acme_cert_status_list() -> Vec<AcmeCertStatus> {
+    let mut list: Vec<AcmeCertStatus> = ACME_CERT_STATUS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .cloned()
+        .collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
+
+/// The guard of renewing status, the status is reset when it's dropped,
+/// so it's not kept renewing even if the renewal panics.
+struct RenewingGuard {
+    name: String,
+}
+
+impl Drop for RenewingGuard {
+    fn drop(&mut self) {
+        let panicking = std::thread::panicking();
+        update_acme_cert_status(&self.name, |status| {
+            status.renewing = false;
+            if panicking {
+                status.error = Some("renewal panicked".to_string());
+            }
+        });
+    }
+}
+
+/// Set the status of group to renewing, returns `None` if it's already renewing.
+fn try_start_renewing(name: &str) -> Option<RenewingGuard> {
+    let mut started = false;
+    update_acme_cert_status(name, |status| {
+        if !status.renewing {
+            status.renewing = true;
+            started = true;
+        }
+    });
+    if started {
+        Some(RenewingGuard {
+            name: name.to_string(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Create a lets encrypt service for the certificate group,
+/// each group has its own certificate and renewal schedule.
+pub fn new_lets_encrypt_service(name: &str, domains: Vec<String>) -> CommonServiceTask {
     let mut domains = domains;
     // sort domain order
     domains.sort();
+    update_acme_cert_status(name, |status| {
+        status.domains.clone_from(&domains);
+    });
 
     CommonServiceTask::new(
-        "Lets encrypt",
+        &format!("Lets encrypt({name})"),
         Duration::from_secs(30 * 60),
-        LetsEncryptService { domains },
+        LetsEncryptService {
+            name: name.to_string(),
+            domains,
+        },
     )
 }
 
+/// Replace the certificates of tls servers without restart,
+/// restart is only the fallback if reload fails.
+fn reload_server_certificates() {
+    let server_conf_list: Vec<proxy::ServerConf> = get_current_config().as_ref().clone().into();
+    match proxy::try_reload_certificates(&server_conf_list) {
+        Ok(servers) => {
+            info!("Reload certificate success, servers: {servers:?}");
+        }
+        Err(e) => {
+            error!("Reload certificate fail, error: {e}");
+            if let Err(e) = restart_now() {
+                error!("Restart fail, error: {e}");
+            }
+        }
+    };
+}
+
+/// Renew the cert of group with the lock of storage,
+/// the tls servers reload the certificate if success.
+async fn renew_cert(storage: &dyn AcmeStorage, name: &str, domains: &[String]) -> Result<()> {
+    let lock = storage.try_lock(name).await?.ok_or_else(|| Error::Fail {
+        message: format!("cert of {name} is renewing by other node"),
+    })?;
+    info!("Should renew cert from lets encrypt, name: {name}");
+    let result = new_lets_encrypt(storage, name, domains).await;
+    if let Err(e) = lock.unlock().await {
+        error!("Unlock cert renewal fail, name: {name}, error: {e}");
+    }
+    let cert = result?;
+    info!("Renew cert success, name: {name}");
+    update_acme_cert_status(name, |status| {
+        status.not_before = cert.not_before;
+        status.not_after = cert.not_after;
+        status.renewed_at = Some(util::now().as_secs());
+    });
+    reload_server_certificates();
+    Ok(())
+}
+
+/// Renew the cert of group and update the status of it.
+async fn renew_cert_with_status(storage: &dyn AcmeStorage, name: &str, domains: &[String]) {
+    let guard = if let Some(guard) = try_start_renewing(name) {
+        guard
+    } else {
+        info!("Cert is renewing, name: {name}");
+        return;
+    };
+    let result = renew_cert(storage, name, domains).await;
+    update_acme_cert_status(name, |status| {
+        status.error = result.as_ref().err().map(|e| e.to_string());
+    });
+    drop(guard);
+    if let Err(e) = result {
+        error!("Renew cert fail, name: {name}, error: {e}");
+    }
+}
+
+/// Trigger the renewal of the cert group in background,
+/// it's used for admin api.
+pub fn renew_lets_encrypt_cert(name: &str) -> Result<()> {
+    let status = ACME_CERT_STATUS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .ok_or_else(|| Error::CertNotFound {
+            message: format!("acme cert of {name} not found"),
+        })?;
+    if status.renewing {
+        return Err(Error::Fail {
+            message: format!("cert of {name} is renewing"),
+        });
+    }
+    let storage = get_acme_storage()?;
+    let name = name.to_string();
+    tokio::spawn(async move {
+        renew_cert_with_status(storage.as_ref(), &name, &status.domains).await;
+    });
+    Ok(())
+}
+
 #[async_trait]
 impl ServiceTask for LetsEncryptService {
     async fn run(&self) -> Option<bool> {
+        let name = &self.name;
         let domains = &self.domains;
-        let should_renew_now = if let Ok(cert) = get_lets_encrypt_cert() {
+        let storage = match get_acme_storage() {
+            Ok(storage) => storage,
+            Err(e) => {
+                error!("Create acme storage fail, name: {name}, error: {e}");
+                return None;
+            }
+        };
+        // the certificate may be renewed by other node of cluster
+        if let Err(e) = reload_cert_store(storage.as_ref()).await {
+            error!("Load cert store fail, name: {name}, error: {e}");
+        }
+        let should_renew_now = if let Ok(cert) = get_lets_encrypt_cert(name) {
+            let mut last_not_after = 0;
+            update_acme_cert_status(name, |status| {
+                last_not_after = status.not_after;
+                status.not_before = cert.not_before;
+                status.not_after = cert.not_after;
+            });
+            if last_not_after != 0 && last_not_after != cert.not_after {
+                info!("Cert is renewed by other node, name: {name}");
+                reload_server_certificates();
+            }
             !cert.valid() || domains.join(",") != cert.domains.join(",")
         } else {
             true
         };
         if should_renew_now {
-            info!("Should renew cert from lets encrypt");
-            match new_lets_encrypt(domains).await {
-                Ok(()) => {
-                    info!("Renew cert success");
-                    if let Err(e) = restart_now() {
-                        error!("Restart fail, error: {e}");
-                    }
-                }
-                Err(e) => error!("Renew cert fail, error: {e}"),
-            };
+            renew_cert_with_status(storage.as_ref(), name, domains).await;
         }
         None
     }
     fn description(&self) -> String {
-        format!("domains: {:?}", self.domains)
+        format!("name: {}, domains: {:?}", self.name, self.domains)
     }
 }
 
-fn get_lets_encrypt_cert_file() -> PathBuf {
-    if let Some(file) = &get_current_config().basic.certificate_file {
-        util::resolve_path(file).into()
+/// The store of acme, it saves the account and the certificates of all groups.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CertStore {
+    #[serde(default)]
+    account: Option<AcmeAccount>,
+    #[serde(default)]
+    certificates: HashMap<String, Cert>,
+}
+
+/// The store of single certificate, it's the format before certificate groups.
+#[derive(Debug, Deserialize)]
+struct LegacyCertStore {
+    #[serde(default)]
+    account: Option<AcmeAccount>,
+    #[serde(flatten)]
+    cert: Cert,
+}
+
+// the name of certificate migrated from the store of single certificate,
+// it's used by the group which has no certificate until the group's one is issued
+const LEGACY_CERT_NAME: &str = "(legacy)";
+
+/// Parse the cert store, the store of single certificate is migrated
+/// into `certificates` as the legacy certificate.
+fn parse_cert_store(buf: &[u8]) -> Result<CertStore> {
+    let value: serde_json::Value =
+        serde_json::from_slice(buf).map_err(|e| Error::SerdeJson { source: e })?;
+    if value.get("certificates").is_some() || value.get("pem").is_none() {
+        return serde_json::from_value(value).map_err(|e| Error::SerdeJson { source: e });
+    }
+    let legacy: LegacyCertStore =
+        serde_json::from_value(value).map_err(|e| Error::SerdeJson { source: e })?;
+    info!(
+        "Migrate the legacy acme certificate, domains: {:?}",
+        legacy.cert.domains
+    );
+    let mut certificates = HashMap::new();
+    certificates.insert(LEGACY_CERT_NAME.to_string(), legacy.cert);
+    Ok(CertStore {
+        account: legacy.account,
+        certificates,
+    })
+}
+
+// the lock for updating cert store
+static CERT_STORE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// the cache of cert store, the tls servers get certificates from it
+static CERT_STORE: Lazy<ArcSwap<CertStore>> =
+    Lazy::new(|| ArcSwap::from_pointee(CertStore::default()));
+
+/// Load the cert store from storage, returns empty store if it's not exists.
+async fn load_cert_store(storage: &dyn AcmeStorage) -> Result<CertStore> {
+    let buf = if let Some(buf) = storage.load_store().await? {
+        buf
     } else {
-        env::temp_dir().join("pingap-lets-encrypt.json")
+        return Ok(CertStore::default());
+    };
+    parse_cert_store(&buf)
+}
+
+/// Reload the cert store from storage and update the cache.
+async fn reload_cert_store(storage: &dyn AcmeStorage) -> Result<()> {
+    let store = load_cert_store(storage).await?;
+    CERT_STORE.store(Arc::new(store));
+    Ok(())
+}
+
+/// Load the cert store to cache, it should be called before the tls servers are created.
+pub fn try_init_cert_store() -> Result<()> {
+    // not the shared storage, the etcd client would be bound to the temporary runtime
+    let storage = new_acme_storage()?;
+    // run in a new thread because it's not in tokio runtime
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().map_err(|e| Error::Io { source: e })?;
+        rt.block_on(reload_cert_store(storage.as_ref()))
+    })
+    .join()
+    .map_err(|_| Error::Fail {
+        message: "load cert store panic".to_string(),
+    })?
+}
+
+/// Update the cert store with lock, the store is reloaded before update.
+async fn update_cert_store(
+    storage: &dyn AcmeStorage,
+    update: impl FnOnce(&mut CertStore),
+) -> Result<()> {
+    let _guard = CERT_STORE_LOCK.lock().await;
+    let mut store = load_cert_store(storage).await?;
+    update(&mut store);
+    let buf = serde_json::to_vec(&store).map_err(|e| Error::SerdeJson { source: e })?;
+    storage.save_store(buf).await?;
+    CERT_STORE.store(Arc::new(store));
+    Ok(())
+}
+
+/// Get the cert of group from the cache of store,
+/// the legacy cert is used if the group has no cert.
+pub fn get_lets_encrypt_cert(name: &str) -> Result<Cert> {
+    let store = CERT_STORE.load();
+    store
+        .certificates
+        .get(name)
+        .or_else(|| store.certificates.get(LEGACY_CERT_NAME))
+        .cloned()
+        .ok_or_else(|| Error::CertNotFound {
+            message: format!("cert of {name} not found"),
+        })
+}
+
+/// Get the url of acme directory, `production`(default) and `staging` are
+/// the directories of lets encrypt, `zerossl` is the directory of zerossl,
+/// otherwise the value is used as url, e.g. step-ca or pebble.
+pub fn get_acme_directory_url(value: &str) -> String {
+    match value {
+        "" | "production" => LetsEncrypt::Production.url(),
+        "staging" => LetsEncrypt::Staging.url(),
+        "zerossl" => ZERO_SSL_DIRECTORY,
+        _ => value,
+    }
+    .to_string()
+}
+
+/// Get the challenge type of acme, `http-01`(default), `dns-01` and `tls-alpn-01`
+/// are supported. If it's not set, dns-01 is used when dns provider is set.
+fn get_challenge_type(basic_conf: &BasicConf) -> ChallengeType {
+    match basic_conf
+        .lets_encrypt_challenge
+        .as_deref()
+        .unwrap_or_default()
+    {
+        "http-01" => ChallengeType::Http01,
+        "dns-01" => ChallengeType::Dns01,
+        "tls-alpn-01" => ChallengeType::TlsAlpn01,
+        _ => {
+            if basic_conf.lets_encrypt_dns_provider.is_some() {
+                ChallengeType::Dns01
+            } else {
+                ChallengeType::Http01
+            }
+        }
+    }
+}
+
+/// Whether the http-01 challenge is used, it needs the server listening on 80.
+pub fn is_http_challenge(basic_conf: &BasicConf) -> bool {
+    get_challenge_type(basic_conf) == ChallengeType::Http01
+}
+
+/// Whether the tls-alpn-01 challenge is used, it's handled by the tls server.
+pub fn is_tls_alpn_challenge(basic_conf: &BasicConf) -> bool {
+    get_challenge_type(basic_conf) == ChallengeType::TlsAlpn01
+}
+
+/// Decode the hmac key of external account binding, it's base64url encoded.
+pub fn decode_eab_hmac_key(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|e| Error::Fail {
+            message: format!("eab hmac key is invalid, {e}"),
+        })
+}
+
+/// The http client of acme based on reqwest, so the certificates
+/// of ca bundle can be trusted.
+struct AcmeHttpClient {
+    client: reqwest::Client,
+}
+
+/// Create the problem response of acme, the error of request is converted
+/// to problem, so it's returned as the api error of instant acme.
+fn new_problem_response(message: String) -> hyper::Response<hyper::Body> {
+    let body = serde_json::json!({
+        "type": "urn:ietf:params:acme:error:serverInternal",
+        "detail": message,
+        "status": 500,
+    });
+    let mut resp = hyper::Response::new(hyper::Body::from(body.to_string()));
+    *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/problem+json"),
+    );
+    resp
+}
+
+impl HttpClient for AcmeHttpClient {
+    fn request(
+        &self,
+        req: hyper::Request<hyper::Body>,
+    ) -> Pin<Box<dyn Future<Output = hyper::Result<hyper::Response<hyper::Body>>> + Send>> {
+        let client = self.client.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let result = client
+                .request(parts.method, parts.uri.to_string())
+                .headers(parts.headers)
+                .body(body)
+                .send()
+                .await;
+            let resp = match result {
+                Ok(resp) => resp,
+                Err(e) => return Ok(new_problem_response(e.to_string())),
+            };
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            let body = match resp.bytes().await {
+                Ok(body) => body,
+                Err(e) => return Ok(new_problem_response(e.to_string())),
+            };
+            let mut resp = hyper::Response::new(hyper::Body::from(body));
+            *resp.status_mut() = status;
+            *resp.headers_mut() = headers;
+            Ok(resp)
+        })
     }
 }
 
-/// Get the cert from file
-pub fn get_lets_encrypt_cert() -> Result<Cert> {
-    let path = get_lets_encrypt_cert_file();
-    if !path.exists() {
-        return Err(Error::NotFound {
-            message: "cert file not found".to_string(),
+/// Create the http client of acme which only trusts the certificates of ca bundle,
+/// it's used for the directory whose certificate is issued by private ca, e.g. pebble.
+fn new_acme_http_client(ca_file: &str) -> Result<Box<dyn HttpClient>> {
+    let file = util::resolve_path(ca_file);
+    let data = std::fs::read(&file).map_err(|e| Error::Io { source: e })?;
+    let certs = reqwest::Certificate::from_pem_bundle(&data).map_err(|e| Error::Fail {
+        message: format!("ca bundle({ca_file}) is invalid, {e}"),
+    })?;
+    if certs.is_empty() {
+        return Err(Error::Fail {
+            message: format!("no certificate is found in ca bundle({ca_file})"),
         });
     }
-    let buf = std::fs::read(&path).map_err(|e| Error::Io { source: e })?;
-    let cert: Cert = serde_json::from_slice(&buf).map_err(|e| Error::SerdeJson { source: e })?;
-    Ok(cert)
+    let mut builder = reqwest::Client::builder().tls_built_in_root_certs(false);
+    for cert in certs {
+        builder = builder.add_root_certificate(cert);
+    }
+    let client = builder.build().map_err(|e| Error::Fail {
+        message: format!("create acme http client fail, {e}"),
+    })?;
+    Ok(Box::new(AcmeHttpClient { client }))
+}
+
+/// Get the acme account, the stored account is reused if its directory is the same,
+/// otherwise a new account will be registered(with external account binding if set).
+/// The stored account which can't be restored is returned as error, it should be
+/// fixed or removed manually instead of registering a new account silently.
+/// Returns the account and the new account info for persistence.
+async fn get_acme_account(
+    basic_conf: &BasicConf,
+    stored: Option<AcmeAccount>,
+) -> Result<(Account, Option<AcmeAccount>)> {
+    let directory = get_acme_directory_url(
+        &basic_conf
+            .lets_encrypt_directory
+            .clone()
+            .unwrap_or_default(),
+    );
+    let ca_file = basic_conf.lets_encrypt_directory_ca.clone();
+    if let Some(stored) = stored.filter(|item| item.directory == directory) {
+        let credentials: AccountCredentials =
+            serde_json::from_value(stored.credentials).map_err(|e| Error::Fail {
+                message: format!("stored acme account is invalid, {e}"),
+            })?;
+        let result = if let Some(ca_file) = &ca_file {
+            Account::from_credentials_and_http(credentials, new_acme_http_client(ca_file)?).await
+        } else {
+            Account::from_credentials(credentials).await
+        };
+        let account = result.map_err(|e| Error::Fail {
+            message: format!("restore stored acme account fail, {e}"),
+        })?;
+        info!("Reuse acme account, directory: {directory}");
+        return Ok((account, None));
+    }
+
+    let contact = basic_conf
+        .lets_encrypt_contact
+        .as_ref()
+        .map(|value| {
+            if value.starts_with("mailto:") {
+                value.to_string()
+            } else {
+                format!("mailto:{value}")
+            }
+        })
+        .unwrap_or_default();
+    let contacts: Vec<&str> = if contact.is_empty() {
+        vec![]
+    } else {
+        vec![contact.as_str()]
+    };
+    let external_account = if let (Some(kid), Some(hmac_key)) = (
+        &basic_conf.lets_encrypt_eab_kid,
+        &basic_conf.lets_encrypt_eab_hmac_key,
+    ) {
+        Some(ExternalAccountKey::new(
+            kid.to_string(),
+            &decode_eab_hmac_key(hmac_key)?,
+        ))
+    } else {
+        None
+    };
+    info!("Register acme account, directory: {directory}");
+    let new_account = NewAccount {
+        contact: &contacts,
+        terms_of_service_agreed: true,
+        only_return_existing: false,
+    };
+    let result = if let Some(ca_file) = &ca_file {
+        Account::create_with_http(
+            &new_account,
+            &directory,
+            external_account.as_ref(),
+            new_acme_http_client(ca_file)?,
+        )
+        .await
+    } else {
+        Account::create(&new_account, &directory, external_account.as_ref()).await
+    };
+    let (account, credentials) = result.map_err(|e| Error::Instant { source: e })?;
+    let credentials =
+        serde_json::to_value(&credentials).map_err(|e| Error::SerdeJson { source: e })?;
+
+    Ok((
+        account,
+        Some(AcmeAccount {
+            directory,
+            credentials,
+        }),
+    ))
 }
 
 /// The proxy plugin for lets encrypt http-01.
 pub async fn handle_lets_encrypt(session: &mut Session, ctx: &mut State) -> pingora::Result<bool> {
     let path = session.req_header().uri.path();
     if path.starts_with("/.well-known/acme-challenge/") {
-        let value = {
-            let data = get_lets_encrypt().lock().await;
-            let v = data
-                .get(path)
-                .ok_or_else(|| util::new_internal_error(400, "token not found".to_string()))?;
-            v.clone()
-        };
+        // the challenge may be set by other node of cluster
+        let value = get_acme_storage()
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?
+            .get_challenge(path)
+            .await
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?
+            .ok_or_else(|| util::new_internal_error(400, "token not found".to_string()))?;
         let size = HttpResponse {
             status: StatusCode::OK,
             body: value.into(),
@@ -131,27 +626,99 @@ pub async fn handle_lets_encrypt(session: &mut Session, ctx: &mut State) -> ping
     Ok(false)
 }
 
-/// Get the new cert from lets encrypt for all domains.
+/// Remove the challenges of http-01 and tls-alpn-01, the error is only logged.
+async fn remove_challenges(storage: &dyn AcmeStorage, keys: &[String]) {
+    for key in keys {
+        if let Err(e) = storage.remove_challenge(key).await {
+            error!("Lets encrypt remove challenge fail, key: {key}, error: {e}");
+        }
+    }
+}
+
+/// Remove the txt records of dns-01 challenge, the error is only logged.
+async fn remove_dns_records(provider: &dyn DnsProvider, records: &[(String, String)]) {
+    for (name, value) in records {
+        if let Err(e) = provider.remove_txt_record(name, value).await {
+            error!("Lets encrypt remove dns record fail, name: {name}, error: {e}");
+        }
+    }
+}
+
+/// Set the challenges ready and wait for the order is ready.
+async fn wait_order_ready(order: &mut Order, challenge_urls: &[&String]) -> Result<()> {
+    for url in challenge_urls {
+        order
+            .set_challenge_ready(url)
+            .await
+            .map_err(|e| Error::Instant { source: e })?;
+    }
+
+    let mut tries = 1u8;
+    let mut delay = Duration::from_millis(250);
+
+    loop {
+        let state = order.state();
+        info!("Order state: {:?}", state.status);
+        if let OrderStatus::Invalid = state.status {
+            return Err(Error::Fail {
+                message: "order is invalid".to_string(),
+            });
+        }
+        if let OrderStatus::Ready | OrderStatus::Valid = state.status {
+            return Ok(());
+        }
+        order
+            .refresh()
+            .await
+            .map_err(|e| Error::Instant { source: e })?;
+
+        delay *= 2;
+        tries += 1;
+        match tries < 10 {
+            true => info!("Order is not ready, waiting {delay:?}"),
+            false => {
+                return Err(Error::Fail {
+                    message: "Giving up: order is not ready".to_string(),
+                });
+            }
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Get the new cert from lets encrypt for all domains of the group.
 /// The cert will be saved if success.
-async fn new_lets_encrypt(domains: &[String]) -> Result<()> {
+async fn new_lets_encrypt(
+    storage: &dyn AcmeStorage,
+    name: &str,
+    domains: &[String],
+) -> Result<Cert> {
     let mut domains: Vec<String> = domains.to_vec();
     domains.sort();
-    let path = get_lets_encrypt_cert_file();
+    let basic_conf = get_current_config().basic.clone();
+    let challenge_type = get_challenge_type(&basic_conf);
+    let dns_provider = match (&challenge_type, &basic_conf.lets_encrypt_dns_provider) {
+        (ChallengeType::Dns01, Some(value)) => Some(new_dns_provider(value)?),
+        (ChallengeType::Dns01, None) => {
+            return Err(Error::Fail {
+                message: "dns provider is not set".to_string(),
+            });
+        }
+        _ => None,
+    };
     info!(
         "Lets encrypt start generate acme, domains: {}",
         domains.join(",")
     );
-    let (account, _) = Account::create(
-        &NewAccount {
-            contact: &[],
-            terms_of_service_agreed: true,
-            only_return_existing: false,
-        },
-        LetsEncrypt::Production.url(),
-        None,
-    )
-    .await
-    .map_err(|e| Error::Instant { source: e })?;
+    let stored_account = load_cert_store(storage)
+        .await
+        .map(|store| store.account)
+        .unwrap_or_default();
+    let (account, new_account) = get_acme_account(&basic_conf, stored_account).await?;
+    // persist the new account, so it can be reused even if the order fails
+    if let Some(new_account) = new_account {
+        update_cert_store(storage, |store| store.account = Some(new_account)).await?;
+    }
 
     // let identifier = Identifier::Dns(opts.name);
     let mut order = account
@@ -165,7 +732,8 @@ async fn new_lets_encrypt(domains: &[String]) -> Result<()> {
         .map_err(|e| Error::Instant { source: e })?;
 
     let state = order.state();
-    if !matches!(state.status, OrderStatus::Pending) {
+    // the order is ready if all authorizations are valid(e.g. reused account)
+    if !matches!(state.status, OrderStatus::Pending | OrderStatus::Ready) {
         return Err(Error::Fail {
             message: format!("order is not pending, staus: {:?}", state.status),
         });
@@ -175,85 +743,106 @@ async fn new_lets_encrypt(domains: &[String]) -> Result<()> {
         .authorizations()
         .await
         .map_err(|e| Error::Instant { source: e })?;
-    let mut challenges = Vec::with_capacity(authorizations.len());
+    let mut challenge_urls = Vec::with_capacity(authorizations.len());
+    let mut dns_records = vec![];
+    let mut challenge_keys = vec![];
 
     for authz in &authorizations {
         info!("Lets encrypt authz status: {:?}", authz.status);
         match authz.status {
             instant_acme::AuthorizationStatus::Pending => {}
             instant_acme::AuthorizationStatus::Valid => continue,
-            _ => todo!(),
+            status => {
+                remove_challenges(storage, &challenge_keys).await;
+                if let Some(provider) = &dns_provider {
+                    remove_dns_records(provider.as_ref(), &dns_records).await;
+                }
+                return Err(Error::Fail {
+                    message: format!(
+                        "authorization of {:?} is not pending, status: {status:?}",
+                        authz.identifier
+                    ),
+                });
+            }
         }
 
-        let challenge = authz
-            .challenges
-            .iter()
-            .find(|c| c.r#type == ChallengeType::Http01)
-            .ok_or_else(|| Error::NotFound {
-                message: "Http01 challenge not found".to_string(),
-            })?;
+        let challenge = authz.challenges.iter().find(|c| c.r#type == challenge_type);
+        let challenge = if let Some(challenge) = challenge {
+            challenge
+        } else {
+            remove_challenges(storage, &challenge_keys).await;
+            if let Some(provider) = &dns_provider {
+                remove_dns_records(provider.as_ref(), &dns_records).await;
+            }
+            return Err(Error::NotFound {
+                message: format!("{challenge_type:?} challenge not found"),
+            });
+        };
 
         let instant_acme::Identifier::Dns(identifier) = &authz.identifier;
 
         let key_auth = order.key_authorization(challenge);
 
-        // http://<你的域名>/.well-known/acme-challenge/<TOKEN>
-        let well_known_path = format!("/.well-known/acme-challenge/{}", challenge.token);
-        info!("Lets encrypt well known path: {well_known_path}");
+        if let Some(provider) = &dns_provider {
+            // _acme-challenge.<你的域名> TXT <VALUE>
+            let name = get_challenge_record_name(identifier);
+            let value = key_auth.dns_value();
+            info!("Lets encrypt add dns record: {name}");
+            if let Err(e) = provider.add_txt_record(&name, &value).await {
+                remove_dns_records(provider.as_ref(), &dns_records).await;
+                return Err(e);
+            }
+            dns_records.push((name, value));
+        } else if challenge_type == ChallengeType::TlsAlpn01 {
+            // the certificate is served for the `acme-tls/1` protocol
+            info!("Lets encrypt set tls alpn challenge: {identifier}");
+            match set_tls_alpn_challenge(storage, identifier, key_auth.as_str()).await {
+                Ok(key) => challenge_keys.push(key),
+                Err(e) => {
+                    remove_challenges(storage, &challenge_keys).await;
+                    return Err(e);
+                }
+            }
+        } else {
+            // http://<你的域名>/.well-known/acme-challenge/<TOKEN>
+            let well_known_path = format!("/.well-known/acme-challenge/{}", challenge.token);
+            info!("Lets encrypt well known path: {well_known_path}");
 
-        let mut map = get_lets_encrypt().lock().await;
-        map.insert(well_known_path, key_auth.as_str().to_string());
+            if let Err(e) = storage
+                .set_challenge(&well_known_path, key_auth.as_str())
+                .await
+            {
+                remove_challenges(storage, &challenge_keys).await;
+                return Err(e);
+            }
+            challenge_keys.push(well_known_path);
+        }
 
-        challenges.push((identifier, &challenge.url));
+        challenge_urls.push(&challenge.url);
     }
-    for (_, url) in &challenges {
-        order
-            .set_challenge_ready(url)
-            .await
-            .map_err(|e| Error::Instant { source: e })?;
+    // wait for the txt records are propagated
+    if !dns_records.is_empty() {
+        let delay = basic_conf
+            .lets_encrypt_dns_delay
+            .unwrap_or(Duration::from_secs(30));
+        info!("Lets encrypt wait {delay:?} for dns propagation");
+        tokio::time::sleep(delay).await;
     }
 
-    let mut tries = 1u8;
-    let mut delay = Duration::from_millis(250);
-
     let detail_url = authorizations.first();
-
-    let state = loop {
-        let state = order.state();
-        info!("Order state: {:?}", state.status);
-        if let OrderStatus::Ready | OrderStatus::Invalid | OrderStatus::Valid = state.status {
-            break state;
-        }
-        order
-            .refresh()
-            .await
-            .map_err(|e| Error::Instant { source: e })?;
-
-        delay *= 2;
-        tries += 1;
-        match tries < 10 {
-            true => info!("Order is not ready, waiting {delay:?}"),
-            false => {
-                return Err(Error::Fail {
-                    message: format!(
-                        "Giving up: order is not ready. For details, see the url: {detail_url:?}"
-                    ),
-                });
-            }
-        }
-        tokio::time::sleep(delay).await;
-    };
-    if state.status == OrderStatus::Invalid {
-        return Err(Error::Fail {
-            message: format!("order is invalid, check {detail_url:?}"),
-        });
+    let result = wait_order_ready(&mut order, &challenge_urls).await;
+    if let Some(provider) = &dns_provider {
+        remove_dns_records(provider.as_ref(), &dns_records).await;
     }
-    let mut names = Vec::with_capacity(challenges.len());
-    for (identifier, _) in challenges {
-        names.push(identifier.to_owned());
+    // the challenges are useless after the order is ready or failed
+    remove_challenges(storage, &challenge_keys).await;
+    if let Err(e) = result {
+        error!("Lets encrypt order fail, for details, see the url: {detail_url:?}");
+        return Err(e);
     }
-
-    let mut params = CertificateParams::new(names.clone());
+    // the authorizations which are already valid have no challenge,
+    // so the names of csr are all domains of the order
+    let mut params = CertificateParams::new(domains.clone());
     params.distinguished_name = DistinguishedName::new();
     let cert = Certificate::from_params(params).map_err(|e| Error::Rcgen { source: e })?;
     let csr = cert
@@ -283,13 +872,6 @@ async fn new_lets_encrypt(domains: &[String]) -> Result<()> {
         not_after = info.not_after;
     }
 
-    let mut f = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&path)
-        .await
-        .map_err(|e| Error::Io { source: e })?;
     let info = Cert {
         domains: domains.to_vec(),
         not_after,
@@ -297,14 +879,176 @@ async fn new_lets_encrypt(domains: &[String]) -> Result<()> {
         pem: STANDARD.encode(cert_chain_pem.as_bytes()),
         key: STANDARD.encode(cert.serialize_private_key_pem().as_bytes()),
     };
-    let buf = serde_json::to_vec(&info).map_err(|e| Error::SerdeJson { source: e })?;
-    f.write(&buf).await.map_err(|e| Error::Io { source: e })?;
-    info!("Write cert success, path: {path:?}");
+    let saved_cert = info.clone();
+    update_cert_store(storage, |store| {
+        store.certificates.insert(name.to_string(), saved_cert);
+    })
+    .await?;
+    info!("Write cert success, name: {name}");
     webhook::send(webhook::SendNotificationParams {
         level: webhook::NotificationLevel::Info,
         category: webhook::NotificationCategory::LetsEncrypt,
-        msg: "Generate new cert from lets encrypt".to_string(),
+        msg: format!("Generate new cert from lets encrypt, name: {name}"),
     });
 
-    Ok(())
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_eab_hmac_key, get_acme_cert_status_list, get_acme_directory_url, get_challenge_type,
+        get_lets_encrypt_cert, new_acme_http_client, parse_cert_store, renew_lets_encrypt_cert,
+        try_start_renewing, update_acme_cert_status, CertStore, CERT_STORE, LEGACY_CERT_NAME,
+    };
+    use crate::config::BasicConf;
+    use instant_acme::ChallengeType;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_get_challenge_type() {
+        let mut conf = BasicConf::default();
+        assert_eq!(ChallengeType::Http01, get_challenge_type(&conf));
+        conf.lets_encrypt_dns_provider = Some("https://dns.pingap.io/acme".to_string());
+        assert_eq!(ChallengeType::Dns01, get_challenge_type(&conf));
+        conf.lets_encrypt_challenge = Some("tls-alpn-01".to_string());
+        assert_eq!(ChallengeType::TlsAlpn01, get_challenge_type(&conf));
+        conf.lets_encrypt_challenge = Some("http-01".to_string());
+        assert_eq!(ChallengeType::Http01, get_challenge_type(&conf));
+    }
+
+    #[test]
+    fn test_get_acme_directory_url() {
+        assert_eq!(
+            "https://acme-v02.api.letsencrypt.org/directory",
+            get_acme_directory_url("")
+        );
+        assert_eq!(
+            "https://acme-staging-v02.api.letsencrypt.org/directory",
+            get_acme_directory_url("staging")
+        );
+        assert_eq!(
+            "https://acme.zerossl.com/v2/DV90",
+            get_acme_directory_url("zerossl")
+        );
+        assert_eq!(
+            "https://127.0.0.1:14000/dir",
+            get_acme_directory_url("https://127.0.0.1:14000/dir")
+        );
+    }
+
+    #[test]
+    fn test_new_acme_http_client() {
+        let cert = rcgen::generate_simple_self_signed(vec!["pebble".to_string()]).unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(cert.serialize_pem().unwrap().as_bytes())
+            .unwrap();
+        assert_eq!(
+            true,
+            new_acme_http_client(&file.path().to_string_lossy()).is_ok()
+        );
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_string_lossy().to_string();
+        assert_eq!(
+            format!("Lets encrypt fail, no certificate is found in ca bundle({path})"),
+            new_acme_http_client(&path).err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_decode_eab_hmac_key() {
+        assert_eq!(b"pingap".to_vec(), decode_eab_hmac_key("cGluZ2Fw").unwrap());
+        assert_eq!(b"pi".to_vec(), decode_eab_hmac_key("cGk=").unwrap());
+        assert_eq!(true, decode_eab_hmac_key("a+b").is_err());
+    }
+
+    #[test]
+    fn test_cert_store() {
+        // the store of single certificate is migrated
+        let store = parse_cert_store(
+            br#"{"domains":["pingap.io"],"not_after":10,"not_before":1,"pem":"cGVt","key":"a2V5","account":{"directory":"https://127.0.0.1/dir","credentials":{}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            "https://127.0.0.1/dir",
+            store.account.as_ref().unwrap().directory
+        );
+        assert_eq!(1, store.certificates.len());
+        let cert = store.certificates.get(LEGACY_CERT_NAME).unwrap();
+        assert_eq!(vec!["pingap.io".to_string()], cert.domains);
+        assert_eq!(10, cert.not_after);
+        assert_eq!(b"pem".to_vec(), cert.get_cert());
+        assert_eq!(b"key".to_vec(), cert.get_key());
+
+        // it survives saving and loading again
+        let store = parse_cert_store(&serde_json::to_vec(&store).unwrap()).unwrap();
+        assert_eq!(
+            "https://127.0.0.1/dir",
+            store.account.as_ref().unwrap().directory
+        );
+        assert_eq!(
+            vec!["pingap.io".to_string()],
+            store.certificates.get(LEGACY_CERT_NAME).unwrap().domains
+        );
+        CERT_STORE.store(Arc::new(store));
+        assert_eq!(
+            vec!["pingap.io".to_string()],
+            get_lets_encrypt_cert("pingap").unwrap().domains
+        );
+        CERT_STORE.store(Arc::new(CertStore::default()));
+
+        let store = parse_cert_store(br#"{"certificates":{}}"#).unwrap();
+        assert_eq!(true, store.account.is_none());
+        assert_eq!(true, store.certificates.is_empty());
+
+        update_acme_cert_status("pingap", |status| {
+            status.domains = vec!["pingap.io".to_string()];
+            status.renewing = true;
+        });
+        update_acme_cert_status("pingap", |status| {
+            status.renewing = false;
+            status.error = Some("order is invalid".to_string());
+        });
+        let list = get_acme_cert_status_list();
+        assert_eq!(1, list.len());
+        assert_eq!("pingap", list[0].name);
+        assert_eq!(vec!["pingap.io".to_string()], list[0].domains);
+        assert_eq!(false, list[0].renewing);
+        assert_eq!(
+            "order is invalid",
+            list[0].error.clone().unwrap_or_default()
+        );
+
+        let guard = try_start_renewing("pingap");
+        assert_eq!(true, guard.is_some());
+        assert_eq!(true, try_start_renewing("pingap").is_none());
+        assert_eq!(
+            "Lets encrypt fail, cert of pingap is renewing",
+            renew_lets_encrypt_cert("pingap").err().unwrap().to_string()
+        );
+        // the status is reset when the guard is dropped
+        drop(guard);
+        assert_eq!(false, get_acme_cert_status_list()[0].renewing);
+
+        // the status is reset even if the renewal panics
+        let result = std::thread::spawn(|| {
+            let _guard = try_start_renewing("pingap");
+            panic!("renewal fail");
+        })
+        .join();
+        assert_eq!(true, result.is_err());
+        let list = get_acme_cert_status_list();
+        assert_eq!(false, list[0].renewing);
+        assert_eq!(
+            "renewal panicked",
+            list[0].error.clone().unwrap_or_default()
+        );
+        assert_eq!(
+            "Cert not found error, acme cert of github not found",
+            renew_lets_encrypt_cert("github").err().unwrap().to_string()
+        );
+    }
 }
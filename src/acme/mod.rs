@@ -25,6 +25,8 @@ pub enum Error {
     Rcgen { source: rcgen::Error },
     #[snafu(display("Challenge not found error, {message}"))]
     NotFound { message: String },
+    #[snafu(display("Cert not found error, {message}"))]
+    CertNotFound { message: String },
     #[snafu(display("Lets encrypt fail, {message}"))]
     Fail { message: String },
     #[snafu(display("Io error, {source}"))]
@@ -33,6 +35,10 @@ pub enum Error {
     SerdeJson { source: serde_json::Error },
     #[snafu(display("X509 error, {message}"))]
     X509 { message: String },
+    #[snafu(display("Dns provider error, {message}"))]
+    DnsProvider { message: String },
+    #[snafu(display("Config error, {source}"))]
+    Config { source: crate::config::Error },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -61,7 +67,15 @@ pub fn get_cert_info(data: &[u8]) -> Result<CertInfo> {
     })
 }
 
-#[derive(Debug, Deserialize, Serialize, Default)]
+/// The acme account which is reused for renewal,
+/// it is only valid for the same directory.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AcmeAccount {
+    pub directory: String,
+    pub credentials: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct Cert {
     pub domains: Vec<String>,
     pub not_after: i64,
@@ -88,10 +102,25 @@ impl Cert {
     }
 }
 
+mod dns_provider;
 mod lets_encrypt;
+mod rfc2136;
+mod self_signed;
+mod storage;
+mod tls_alpn;
 mod validity_checker;
 
-pub use lets_encrypt::{get_lets_encrypt_cert, handle_lets_encrypt, new_lets_encrypt_service};
+pub use dns_provider::{new_dns_provider, DnsProvider};
+
+pub use lets_encrypt::{
+    decode_eab_hmac_key, get_acme_cert_status_list, get_acme_directory_url, get_lets_encrypt_cert,
+    handle_lets_encrypt, is_http_challenge, is_tls_alpn_challenge, new_lets_encrypt_service,
+    renew_lets_encrypt_cert, try_init_cert_store, AcmeCertStatus,
+};
+pub use self_signed::{
+    get_self_signed_ca, get_self_signed_cert, get_self_signed_name, is_valid_domain,
+};
+pub use tls_alpn::{get_tls_alpn_challenge_cert, new_placeholder_cert};
 pub use validity_checker::new_tls_validity_service;
 
 #[cfg(test)]
@@ -0,0 +1,739 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::dns_provider::DnsProvider;
+use super::{Error, Result};
+use crate::util;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pingora::tls::memcmp;
+use pingora::tls::rand::rand_bytes;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TSIG_FUDGE: u16 = 300;
+const DNS_HEADER_SIZE: usize = 12;
+// the truncated flag of the third byte in header
+const FLAG_TRUNCATED: u8 = 0x02;
+
+const ALGORITHM_HMAC_SHA256: &str = "hmac-sha256";
+const ALGORITHM_HMAC_SHA512: &str = "hmac-sha512";
+
+#[derive(Debug, Clone)]
+struct TsigKey {
+    name: String,
+    algorithm: String,
+    secret: Vec<u8>,
+}
+
+/// The dns provider which updates the txt record by dynamic dns update(RFC2136),
+/// e.g. `rfc2136://127.0.0.1:53?zone=example.com&key_name=pingap&key_secret=base64`.
+#[derive(Debug, Clone)]
+pub struct Rfc2136Provider {
+    server: String,
+    zone: String,
+    ttl: u32,
+    tsig_key: Option<TsigKey>,
+    timeout: Duration,
+}
+
+fn dns_error(message: String) -> Error {
+    Error::DnsProvider { message }
+}
+
+/// Write the domain name in wire format, the name is lowercase(canonical).
+fn write_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(dns_error(format!("dns name({name}) is invalid")));
+        }
+        buf.push(label.len() as u8);
+        buf.extend(label.to_lowercase().as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+
+fn write_txt_record(
+    buf: &mut Vec<u8>,
+    name: &str,
+    class: u16,
+    ttl: u32,
+    value: &str,
+) -> Result<()> {
+    if value.len() > 255 {
+        return Err(dns_error("txt value is too long".to_string()));
+    }
+    write_name(buf, name)?;
+    buf.extend(TYPE_TXT.to_be_bytes());
+    buf.extend(class.to_be_bytes());
+    buf.extend(ttl.to_be_bytes());
+    buf.extend((value.len() as u16 + 1).to_be_bytes());
+    buf.push(value.len() as u8);
+    buf.extend(value.as_bytes());
+    Ok(())
+}
+
+fn get_rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "UNKNOWN",
+    }
+}
+
+fn get_tsig_error_name(error: u16) -> &'static str {
+    match error {
+        16 => "BADSIG",
+        17 => "BADKEY",
+        18 => "BADTIME",
+        22 => "BADTRUNC",
+        _ => "UNKNOWN",
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let value = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([value[0], value[1]]))
+}
+
+/// Skip the domain name in wire format, returns the offset after the name.
+fn skip_name(data: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let size = *data.get(offset)? as usize;
+        if size == 0 {
+            return Some(offset + 1);
+        }
+        // compression pointer
+        if size & 0xc0 == 0xc0 {
+            data.get(offset + 1)?;
+            return Some(offset + 2);
+        }
+        offset += 1 + size;
+    }
+}
+
+/// The tsig record of dns message, it should be the last record of message.
+#[derive(Debug)]
+struct TsigRecord<'a> {
+    // the offset of tsig record in message
+    start: usize,
+    key_name: &'a [u8],
+    algorithm: &'a [u8],
+    time_signed: &'a [u8],
+    fudge: u16,
+    mac: &'a [u8],
+    original_id: &'a [u8],
+    error: u16,
+    other: &'a [u8],
+}
+
+/// Parse the tsig record of dns message, returns none if the last record is not tsig.
+fn parse_tsig_record(data: &[u8]) -> Option<TsigRecord<'_>> {
+    if data.len() < DNS_HEADER_SIZE {
+        return None;
+    }
+    let zone_count = read_u16(data, 4)?;
+    let records = read_u16(data, 6)? as usize + read_u16(data, 8)? as usize;
+    let additional_count = read_u16(data, 10)? as usize;
+    if additional_count == 0 {
+        return None;
+    }
+    let records = records + additional_count;
+    let mut offset = DNS_HEADER_SIZE;
+    // name + type(2) + class(2)
+    for _ in 0..zone_count {
+        offset = skip_name(data, offset)? + 4;
+    }
+    for index in 0..records {
+        let start = offset;
+        offset = skip_name(data, offset)?;
+        let key_name = data.get(start..offset)?;
+        let rr_type = read_u16(data, offset)?;
+        // type(2) + class(2) + ttl(4) + rdata length(2)
+        let size = read_u16(data, offset + 8)? as usize;
+        let rdata_start = offset + 10;
+        let rdata = data.get(rdata_start..rdata_start + size)?;
+        offset = rdata_start + size;
+        if index + 1 < records {
+            continue;
+        }
+        if rr_type != TYPE_TSIG || offset != data.len() {
+            return None;
+        }
+        let name_end = skip_name(rdata, 0)?;
+        let mac_size = read_u16(rdata, name_end + 8)? as usize;
+        let mac_start = name_end + 10;
+        let mac_end = mac_start + mac_size;
+        let other_size = read_u16(rdata, mac_end + 4)? as usize;
+        return Some(TsigRecord {
+            start,
+            key_name,
+            algorithm: &rdata[..name_end],
+            time_signed: rdata.get(name_end..name_end + 6)?,
+            fudge: read_u16(rdata, name_end + 6)?,
+            mac: rdata.get(mac_start..mac_end)?,
+            original_id: rdata.get(mac_end..mac_end + 2)?,
+            error: read_u16(rdata, mac_end + 2)?,
+            other: rdata.get(mac_end + 6..mac_end + 6 + other_size)?,
+        });
+    }
+    None
+}
+
+impl TsigKey {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mac = match self.algorithm.as_str() {
+            ALGORITHM_HMAC_SHA256 => hmac_sha256::HMAC::mac(data, &self.secret).to_vec(),
+            ALGORITHM_HMAC_SHA512 => hmac_sha512::HMAC::mac(data, &self.secret).to_vec(),
+            _ => {
+                return Err(dns_error(format!(
+                    "tsig algorithm({}) is not supported",
+                    self.algorithm
+                )))
+            }
+        };
+        Ok(mac)
+    }
+    /// The digest data of message: prior mac(response only) + message + tsig variables.
+    fn digest_data(
+        &self,
+        prior_mac: Option<&[u8]>,
+        msg: &[u8],
+        time_signed: &[u8],
+        fudge: u16,
+        error: u16,
+        other: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(msg.len() + 128);
+        if let Some(mac) = prior_mac {
+            data.extend((mac.len() as u16).to_be_bytes());
+            data.extend(mac);
+        }
+        data.extend(msg);
+        write_name(&mut data, &self.name)?;
+        data.extend(CLASS_ANY.to_be_bytes());
+        data.extend(0u32.to_be_bytes());
+        write_name(&mut data, &self.algorithm)?;
+        data.extend(time_signed);
+        data.extend(fudge.to_be_bytes());
+        data.extend(error.to_be_bytes());
+        data.extend((other.len() as u16).to_be_bytes());
+        data.extend(other);
+        Ok(data)
+    }
+    /// Append the tsig record to the message(RFC8945), the prior mac is
+    /// set for signing response. The additional count of message will be
+    /// increased, and returns the mac of message.
+    fn append_to(
+        &self,
+        msg: &mut Vec<u8>,
+        time_signed: u64,
+        prior_mac: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        let mut algorithm = vec![];
+        write_name(&mut algorithm, &self.algorithm)?;
+        let mut key_name = vec![];
+        write_name(&mut key_name, &self.name)?;
+        let time_signed = time_signed.to_be_bytes();
+        // time signed is 48 bits
+        let time_signed = &time_signed[2..];
+
+        let mac =
+            self.sign(&self.digest_data(prior_mac, msg, time_signed, TSIG_FUDGE, 0, &[])?)?;
+
+        let mut rdata = algorithm;
+        rdata.extend(time_signed);
+        rdata.extend(TSIG_FUDGE.to_be_bytes());
+        rdata.extend((mac.len() as u16).to_be_bytes());
+        rdata.extend(&mac);
+        // original id
+        rdata.extend(&msg[0..2]);
+        // error and other len
+        rdata.extend(0u16.to_be_bytes());
+        rdata.extend(0u16.to_be_bytes());
+
+        msg.extend(key_name);
+        msg.extend(TYPE_TSIG.to_be_bytes());
+        msg.extend(CLASS_ANY.to_be_bytes());
+        msg.extend(0u32.to_be_bytes());
+        msg.extend((rdata.len() as u16).to_be_bytes());
+        msg.extend(rdata);
+
+        let additional_count = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+        msg[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        Ok(mac)
+    }
+    /// Verify the tsig of response by the mac of request(RFC8945 5.3).
+    fn verify_response(&self, resp: &[u8], request_mac: &[u8], now: u64) -> Result<()> {
+        let record = parse_tsig_record(resp)
+            .ok_or_else(|| dns_error("tsig of dns update response is missing".to_string()))?;
+        let mut key_name = vec![];
+        write_name(&mut key_name, &self.name)?;
+        let mut algorithm = vec![];
+        write_name(&mut algorithm, &self.algorithm)?;
+        if !record.key_name.eq_ignore_ascii_case(&key_name)
+            || !record.algorithm.eq_ignore_ascii_case(&algorithm)
+        {
+            return Err(dns_error(
+                "tsig key of dns update response is not matched".to_string(),
+            ));
+        }
+        if record.error != 0 {
+            return Err(dns_error(format!(
+                "dns update fail, tsig error: {}",
+                get_tsig_error_name(record.error)
+            )));
+        }
+        // the message before tsig is added
+        let mut msg = resp[..record.start].to_vec();
+        msg[0..2].copy_from_slice(record.original_id);
+        let additional_count = u16::from_be_bytes([msg[10], msg[11]]).saturating_sub(1);
+        msg[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        let mac = self.sign(&self.digest_data(
+            Some(request_mac),
+            &msg,
+            record.time_signed,
+            record.fudge,
+            record.error,
+            record.other,
+        )?)?;
+        if mac.len() != record.mac.len() || !memcmp::eq(&mac, record.mac) {
+            return Err(dns_error(
+                "tsig of dns update response is invalid".to_string(),
+            ));
+        }
+        let mut time_signed = [0u8; 8];
+        time_signed[2..].copy_from_slice(record.time_signed);
+        if now.abs_diff(u64::from_be_bytes(time_signed)) > record.fudge as u64 {
+            return Err(dns_error(format!(
+                "dns update fail, tsig error: {}",
+                get_tsig_error_name(18)
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Rfc2136Provider {
+    /// Create a rfc2136 provider from url, the options are set by query:
+    /// `zone`(required), `key_name`, `key_secret`(base64), `key_algorithm` and `ttl`.
+    pub fn new(value: &str) -> Result<Self> {
+        let info = url::Url::parse(value).map_err(|e| dns_error(e.to_string()))?;
+        let host = info
+            .host_str()
+            .ok_or_else(|| dns_error(format!("dns server of {value} is empty")))?;
+        let server = format!("{host}:{}", info.port().unwrap_or(53));
+        let mut zone = "".to_string();
+        let mut ttl = 60;
+        let mut key_name = "".to_string();
+        let mut key_secret = "".to_string();
+        let mut key_algorithm = ALGORITHM_HMAC_SHA256.to_string();
+        for (key, value) in info.query_pairs() {
+            match key.as_ref() {
+                "zone" => zone = value.to_string(),
+                "ttl" => ttl = value.parse::<u32>().unwrap_or(ttl),
+                "key_name" => key_name = value.to_string(),
+                "key_secret" => key_secret = value.to_string(),
+                "key_algorithm" => key_algorithm = value.to_lowercase(),
+                _ => {}
+            }
+        }
+        if zone.is_empty() {
+            return Err(dns_error(format!("zone of {value} is empty")));
+        }
+        let tsig_key = if key_name.is_empty() {
+            None
+        } else {
+            if ![ALGORITHM_HMAC_SHA256, ALGORITHM_HMAC_SHA512].contains(&key_algorithm.as_str()) {
+                return Err(dns_error(format!(
+                    "tsig algorithm({key_algorithm}) is not supported"
+                )));
+            }
+            let secret = STANDARD
+                .decode(&key_secret)
+                .map_err(|e| dns_error(format!("tsig secret is invalid, {e}")))?;
+            Some(TsigKey {
+                name: key_name,
+                algorithm: key_algorithm,
+                secret,
+            })
+        };
+
+        Ok(Self {
+            server,
+            zone,
+            ttl,
+            tsig_key,
+            timeout: Duration::from_secs(10),
+        })
+    }
+    /// Build the dns update message which adds or deletes the txt record,
+    /// returns the message and the mac of tsig.
+    fn build_message(
+        &self,
+        id: u16,
+        name: &str,
+        value: &str,
+        add: bool,
+        time_signed: u64,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut msg = Vec::with_capacity(512);
+        msg.extend(id.to_be_bytes());
+        msg.extend((OPCODE_UPDATE << 11).to_be_bytes());
+        // zone count, prerequisite count, update count, additional count
+        msg.extend(1u16.to_be_bytes());
+        msg.extend(0u16.to_be_bytes());
+        msg.extend(1u16.to_be_bytes());
+        msg.extend(0u16.to_be_bytes());
+
+        // zone section
+        write_name(&mut msg, &self.zone)?;
+        msg.extend(TYPE_SOA.to_be_bytes());
+        msg.extend(CLASS_IN.to_be_bytes());
+
+        // update section, delete the rr with class none(RFC2136 2.5.4)
+        if add {
+            write_txt_record(&mut msg, name, CLASS_IN, self.ttl, value)?;
+        } else {
+            write_txt_record(&mut msg, name, CLASS_NONE, 0, value)?;
+        }
+
+        let mut mac = None;
+        if let Some(key) = &self.tsig_key {
+            mac = Some(key.append_to(&mut msg, time_signed, None)?);
+        }
+        Ok((msg, mac))
+    }
+    async fn exchange_udp(&self, addr: SocketAddr, msg: &[u8]) -> Result<Vec<u8>> {
+        let local_addr = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local_addr)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        socket
+            .connect(addr)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        socket
+            .send(msg)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+
+        let mut buf = vec![0; 4096];
+        let size = socket
+            .recv(&mut buf)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        buf.truncate(size);
+        Ok(buf)
+    }
+    /// Send the message over tcp, the message is prefixed with two bytes length.
+    async fn exchange_tcp(&self, addr: SocketAddr, msg: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        let mut data = (msg.len() as u16).to_be_bytes().to_vec();
+        data.extend(msg);
+        stream
+            .write_all(&data)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        let mut size = [0u8; 2];
+        stream
+            .read_exact(&mut size)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        let mut buf = vec![0; u16::from_be_bytes(size) as usize];
+        stream
+            .read_exact(&mut buf)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?;
+        Ok(buf)
+    }
+    /// Send the message by udp, and retry by tcp if the response is truncated.
+    async fn exchange(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        let addr = tokio::net::lookup_host(&self.server)
+            .await
+            .map_err(|e| dns_error(e.to_string()))?
+            .next()
+            .ok_or_else(|| dns_error(format!("dns server({}) is not found", self.server)))?;
+        let resp = self.exchange_udp(addr, msg).await?;
+        if resp.len() >= DNS_HEADER_SIZE && resp[2] & FLAG_TRUNCATED != 0 {
+            return self.exchange_tcp(addr, msg).await;
+        }
+        Ok(resp)
+    }
+    async fn update(&self, name: &str, value: &str, add: bool) -> Result<()> {
+        // the random id makes the response hard to be spoofed
+        let mut id = [0_u8; 2];
+        rand_bytes(&mut id).map_err(|e| dns_error(e.to_string()))?;
+        let id = u16::from_be_bytes(id);
+        let (msg, mac) = self.build_message(id, name, value, add, util::now().as_secs())?;
+
+        let resp = tokio::time::timeout(self.timeout, self.exchange(&msg))
+            .await
+            .map_err(|_| dns_error(format!("dns update({}) timeout", self.server)))??;
+        if resp.len() < DNS_HEADER_SIZE || resp[0..2] != id.to_be_bytes() {
+            return Err(dns_error("dns update response is invalid".to_string()));
+        }
+        // the response of signed request should be signed by the same key
+        if let (Some(key), Some(mac)) = (&self.tsig_key, &mac) {
+            key.verify_response(&resp, mac, util::now().as_secs())?;
+        }
+        let rcode = u16::from_be_bytes([resp[2], resp[3]]) & 0x000f;
+        if rcode != 0 {
+            return Err(dns_error(format!(
+                "dns update fail, rcode: {}",
+                get_rcode_name(rcode)
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136Provider {
+    async fn add_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        self.update(name, value, true).await
+    }
+    async fn remove_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        self.update(name, value, false).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tsig_record, write_name, Rfc2136Provider, TsigKey};
+    use crate::acme::dns_provider::DnsProvider;
+    use crate::util;
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+
+    /// Sign the response of request like dns server,
+    /// the request is replied with rcode.
+    fn new_signed_response(key: &TsigKey, request: &[u8], rcode: u8, truncated: bool) -> Vec<u8> {
+        let record = parse_tsig_record(request).unwrap();
+        let mac = record.mac.to_vec();
+        let mut resp = request[..record.start].to_vec();
+        // qr flag and update opcode
+        resp[2] = if truncated { 0xaa } else { 0xa8 };
+        resp[3] = rcode;
+        let additional_count = u16::from_be_bytes([resp[10], resp[11]]) - 1;
+        resp[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        key.append_to(&mut resp, util::now().as_secs(), Some(&mac))
+            .unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_build_message() {
+        let mut buf = vec![];
+        write_name(&mut buf, "_acme-challenge.Pingap.io.").unwrap();
+        assert_eq!(b"\x0f_acme-challenge\x06pingap\x02io\x00".to_vec(), buf);
+
+        let provider = Rfc2136Provider::new("rfc2136://127.0.0.1?zone=pingap.io").unwrap();
+        assert_eq!("127.0.0.1:53", provider.server);
+        assert_eq!(true, provider.tsig_key.is_none());
+        let (msg, mac) = provider
+            .build_message(1, "_acme-challenge.pingap.io", "abc", true, 0)
+            .unwrap();
+        assert_eq!(true, mac.is_none());
+        assert_eq!(
+            "00012800000100000001000006706\
+            96e67617002696f0000060001\
+            0f5f61636d652d6368616c6c656e676506\
+            70696e67617002696f00001000010000003c0004\
+            03616263",
+            hex::encode(msg)
+        );
+
+        let provider = Rfc2136Provider::new(
+            "rfc2136://127.0.0.1:5353?zone=pingap.io&key_name=pingap&key_secret=cGluZ2Fw",
+        )
+        .unwrap();
+        let (msg, mac) = provider
+            .build_message(1, "_acme-challenge.pingap.io", "abc", false, 1717200000)
+            .unwrap();
+        // delete the rr with class none and ttl 0, the tsig record is appended
+        assert_eq!(
+            "0001280000010000000100010670696e67617002696f0000060001\
+            0f5f61636d652d6368616c6c656e67650670696e67617002696f\
+            00001000fe00000000000403616263\
+            0670696e6761700000fa00ff00000000003d\
+            0b686d61632d73686132353600\
+            0000665a6480012c0020\
+            65e3fbcb43e8ad0382891bbb8654bfd72affc29cbc31b8d04ef86b7df461fdd5\
+            000100000000",
+            hex::encode(&msg)
+        );
+        assert_eq!(
+            "65e3fbcb43e8ad0382891bbb8654bfd72affc29cbc31b8d04ef86b7df461fdd5",
+            hex::encode(mac.unwrap())
+        );
+        let record = parse_tsig_record(&msg).unwrap();
+        assert_eq!(b"\x06pingap\x00".to_vec(), record.key_name);
+        assert_eq!(300, record.fudge);
+        assert_eq!([0u8, 1], record.original_id);
+
+        assert_eq!(
+            "Dns provider error, zone of rfc2136://127.0.0.1 is empty",
+            Rfc2136Provider::new("rfc2136://127.0.0.1")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let provider = Rfc2136Provider::new(
+            "rfc2136://127.0.0.1:5353?zone=pingap.io&key_name=pingap&key_secret=cGluZ2Fw",
+        )
+        .unwrap();
+        let key = provider.tsig_key.clone().unwrap();
+        let now = util::now().as_secs();
+        let (msg, mac) = provider
+            .build_message(1, "_acme-challenge.pingap.io", "abc", true, now)
+            .unwrap();
+        let mac = mac.unwrap();
+        let resp = new_signed_response(&key, &msg, 0, false);
+        assert_eq!(true, key.verify_response(&resp, &mac, now).is_ok());
+
+        // the mac of other request
+        assert_eq!(
+            "Dns provider error, tsig of dns update response is invalid",
+            key.verify_response(&resp, &[0; 32], now)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        // the response is modified
+        let mut modified = resp.clone();
+        modified[3] = 5;
+        assert_eq!(
+            "Dns provider error, tsig of dns update response is invalid",
+            key.verify_response(&modified, &mac, now)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        // signed by other key
+        let other = TsigKey {
+            secret: b"other".to_vec(),
+            ..key.clone()
+        };
+        assert_eq!(
+            "Dns provider error, tsig of dns update response is invalid",
+            other
+                .verify_response(&resp, &mac, now)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        assert_eq!(
+            "Dns provider error, dns update fail, tsig error: BADTIME",
+            key.verify_response(&resp, &mac, now + 600)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        // the response is not signed
+        assert_eq!(
+            "Dns provider error, tsig of dns update response is missing",
+            key.verify_response(&resp[..parse_tsig_record(&resp).unwrap().start], &mac, now)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rfc2136_provider() {
+        let provider = Rfc2136Provider::new(
+            "rfc2136://127.0.0.1:5353?zone=pingap.io&key_name=pingap&key_secret=cGluZ2Fw",
+        )
+        .unwrap();
+        let key = provider.tsig_key.clone().unwrap();
+        // the stand-in of dns server, the truncated response is retried by tcp
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = UdpSocket::bind(addr).await.unwrap();
+        let udp_key = key.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 4096];
+            for (rcode, truncated) in [(0u8, false), (5, false), (0, true)] {
+                let (size, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let resp = new_signed_response(&udp_key, &buf[0..size], rcode, truncated);
+                socket.send_to(&resp, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut size = [0u8; 2];
+            stream.read_exact(&mut size).await.unwrap();
+            let mut buf = vec![0; u16::from_be_bytes(size) as usize];
+            stream.read_exact(&mut buf).await.unwrap();
+            let resp = new_signed_response(&key, &buf, 0, false);
+            let mut data = (resp.len() as u16).to_be_bytes().to_vec();
+            data.extend(resp);
+            stream.write_all(&data).await.unwrap();
+        });
+        let provider = Rfc2136Provider::new(&format!(
+            "rfc2136://{addr}?zone=pingap.io&key_name=pingap&key_secret=cGluZ2Fw"
+        ))
+        .unwrap();
+        provider
+            .add_txt_record("_acme-challenge.pingap.io", "abc")
+            .await
+            .unwrap();
+        let result = provider
+            .remove_txt_record("_acme-challenge.pingap.io", "abc")
+            .await;
+        assert_eq!(
+            "Dns provider error, dns update fail, rcode: REFUSED",
+            result.err().unwrap().to_string()
+        );
+        // truncated
+        provider
+            .remove_txt_record("_acme-challenge.pingap.io", "abc")
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_cert_info, Error, Result};
+use crate::config::get_current_config;
+use crate::util;
+use log::info;
+use once_cell::sync::Lazy;
+use rcgen::{
+    date_time_ymd, BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, GeneralSubtree, IsCa, KeyPair, KeyUsagePurpose, NameConstraints,
+};
+use std::env;
+use std::fs::{DirBuilder, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const CA_COMMON_NAME: &str = "Pingap Development CA";
+const CA_CERT_FILE: &str = "ca.crt";
+const CA_KEY_FILE: &str = "ca.key";
+// the permitted domains of ca, the ca is recreated if they are changed
+const CA_DOMAINS_FILE: &str = "ca.domains";
+const LOCALHOST: &str = "localhost";
+
+// the lock for generating certificates
+static SELF_SIGNED_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+fn get_self_signed_dir() -> PathBuf {
+    if let Some(dir) = &get_current_config().basic.self_signed_dir {
+        util::resolve_path(dir).into()
+    } else {
+        env::temp_dir().join("pingap-self-signed")
+    }
+}
+
+/// Get the domains which are allowed to issue self signed certificate,
+/// `localhost` is always allowed, and `*.` prefix allows the sub domains.
+fn get_self_signed_domains() -> Vec<String> {
+    let mut domains = vec![LOCALHOST.to_string()];
+    let configured = get_current_config()
+        .basic
+        .self_signed_domains
+        .clone()
+        .unwrap_or_default();
+    for item in configured {
+        let item = item.trim().to_lowercase();
+        if !item.is_empty() && !domains.contains(&item) {
+            domains.push(item);
+        }
+    }
+    domains
+}
+
+/// Get the name of certificate for host, it's the host itself or the wildcard
+/// domain which matches the host. Returns none if the host is not allowed.
+fn find_self_signed_name(host: &str, domains: &[String]) -> Option<String> {
+    let host = host.to_lowercase();
+    if !is_valid_domain(&host) || host.starts_with("*.") {
+        return None;
+    }
+    if domains.contains(&host) {
+        return Some(host);
+    }
+    let (_, parent) = host.split_once('.')?;
+    let wildcard = format!("*.{parent}");
+    if domains.contains(&wildcard) {
+        return Some(wildcard);
+    }
+    None
+}
+
+/// Get the name of self signed certificate for host,
+/// returns none if the host is not localhost or the configured domains.
+pub fn get_self_signed_name(host: &str) -> Option<String> {
+    find_self_signed_name(host, &get_self_signed_domains())
+}
+
+fn get_current_year() -> i32 {
+    // the average seconds of a year
+    1970 + (util::now().as_secs() / 31_556_952) as i32
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| Error::Io { source: e })
+}
+
+fn write_file(path: &Path, data: &str) -> Result<()> {
+    std::fs::write(path, data).map_err(|e| Error::Io { source: e })
+}
+
+/// Write the file which is only readable by the owner, it's used for private keys.
+fn write_private_file(path: &Path, data: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| Error::Io { source: e })?;
+    // the mode is only applied to the new file
+    file.set_permissions(Permissions::from_mode(0o600))
+        .map_err(|e| Error::Io { source: e })?;
+    file.write_all(data.as_bytes())
+        .map_err(|e| Error::Io { source: e })
+}
+
+/// Create the dir which is only accessible by the owner,
+/// the keys of ca and certificates are saved in it.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(|e| Error::Io { source: e })?;
+    std::fs::set_permissions(dir, Permissions::from_mode(0o700))
+        .map_err(|e| Error::Io { source: e })
+}
+
+/// Create the params of ca, the same params are used for signing
+/// after the ca is reloaded from file, so the issuer of leaf matches the ca.
+/// The ca can only issue certificates for the domains and can't issue sub ca.
+fn new_ca_params(key_pair: Option<KeyPair>, domains: &[String]) -> CertificateParams {
+    let year = get_current_year();
+    let mut params = CertificateParams::default();
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, CA_COMMON_NAME);
+    distinguished_name.push(DnType::OrganizationName, "Pingap");
+    params.distinguished_name = distinguished_name;
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.name_constraints = Some(NameConstraints {
+        permitted_subtrees: domains
+            .iter()
+            .map(|item| GeneralSubtree::DnsName(item.trim_start_matches("*.").to_string()))
+            .collect(),
+        excluded_subtrees: vec![],
+    });
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.not_before = date_time_ymd(year - 1, 1, 1);
+    params.not_after = date_time_ymd(year + 10, 1, 1);
+    params.key_pair = key_pair;
+    params
+}
+
+/// Remove the certificates which are signed by the previous ca.
+fn remove_leaf_certs(dir: &Path) -> Result<()> {
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::Io { source: e })?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("ca.") {
+            continue;
+        }
+        if name.ends_with(".crt") || name.ends_with(".key") {
+            std::fs::remove_file(&path).map_err(|e| Error::Io { source: e })?;
+        }
+    }
+    Ok(())
+}
+
+/// Load the ca from dir, it will be created if not exists or the permitted
+/// domains are changed. Returns the pem of ca and the certificate for signing.
+fn get_or_create_ca(dir: &Path, domains: &[String]) -> Result<(String, Certificate)> {
+    create_private_dir(dir)?;
+    let cert_file = dir.join(CA_CERT_FILE);
+    let key_file = dir.join(CA_KEY_FILE);
+    let domains_file = dir.join(CA_DOMAINS_FILE);
+    let domains_value = domains.join("\n");
+    if cert_file.exists() && key_file.exists() {
+        let stored_domains = read_file(&domains_file).unwrap_or_default();
+        if stored_domains == domains_value {
+            let pem = read_file(&cert_file)?;
+            let key_pair = KeyPair::from_pem(&read_file(&key_file)?)
+                .map_err(|e| Error::Rcgen { source: e })?;
+            let ca = Certificate::from_params(new_ca_params(Some(key_pair), domains))
+                .map_err(|e| Error::Rcgen { source: e })?;
+            return Ok((pem, ca));
+        }
+        info!("Self signed domains are changed, the ca will be recreated");
+        remove_leaf_certs(dir)?;
+    }
+    let ca = Certificate::from_params(new_ca_params(None, domains))
+        .map_err(|e| Error::Rcgen { source: e })?;
+    let pem = ca.serialize_pem().map_err(|e| Error::Rcgen { source: e })?;
+    write_private_file(&key_file, &ca.serialize_private_key_pem())?;
+    write_file(&cert_file, &pem)?;
+    write_file(&domains_file, &domains_value)?;
+    info!("Create self signed ca, file: {cert_file:?}, domains: {domains:?}");
+    Ok((pem, ca))
+}
+
+/// Check the domain is valid, the `*.` prefix is allowed for wildcard domain.
+/// The sni is from client and it's used as file name.
+pub fn is_valid_domain(domain: &str) -> bool {
+    let host = domain.strip_prefix("*.").unwrap_or(domain);
+    !host.is_empty()
+        && host.len() <= 253
+        && !host.starts_with('.')
+        && !host.contains("..")
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+/// Create the leaf certificate of name which is signed by ca,
+/// returns the pem of certificate and key.
+fn new_leaf_cert(name: &str, ca: &Certificate) -> Result<(String, String)> {
+    let year = get_current_year();
+    let mut params = CertificateParams::new(vec![name.to_string()]);
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, name);
+    params.distinguished_name = distinguished_name;
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    // less than 825 days, it's the limit of apple
+    params.not_before = date_time_ymd(year, 1, 1);
+    params.not_after = date_time_ymd(year + 2, 1, 1);
+    let cert = Certificate::from_params(params).map_err(|e| Error::Rcgen { source: e })?;
+    let pem = cert
+        .serialize_pem_with_signer(ca)
+        .map_err(|e| Error::Rcgen { source: e })?;
+    Ok((pem, cert.serialize_private_key_pem()))
+}
+
+/// Get the pem of self signed ca, it will be created if not exists.
+pub fn get_self_signed_ca() -> Result<String> {
+    let _guard = SELF_SIGNED_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (pem, _) = get_or_create_ca(&get_self_signed_dir(), &get_self_signed_domains())?;
+    Ok(pem)
+}
+
+/// Get the self signed certificate of host, it will be generated and persisted
+/// if not exists or expired. Only localhost and the configured domains are allowed.
+/// Returns the pem of certificate chain(leaf and ca) and key.
+pub fn get_self_signed_cert(host: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    let domains = get_self_signed_domains();
+    let name = find_self_signed_name(host, &domains).ok_or_else(|| Error::Fail {
+        message: format!("host({host}) is not allowed for self signed certificate"),
+    })?;
+    let dir = get_self_signed_dir();
+    let _guard = SELF_SIGNED_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (ca_pem, ca) = get_or_create_ca(&dir, &domains)?;
+    // the file name of wildcard domain is `_.{domain}`
+    let file_name = name.replace('*', "_");
+    let cert_file = dir.join(format!("{file_name}.crt"));
+    let key_file = dir.join(format!("{file_name}.key"));
+    if cert_file.exists() && key_file.exists() {
+        let pem = read_file(&cert_file)?;
+        let now = util::now().as_secs() as i64;
+        let valid = get_cert_info(pem.as_bytes())
+            .map(|info| info.not_after > now)
+            .unwrap_or_default();
+        if valid {
+            let key = read_file(&key_file)?;
+            return Ok((format!("{pem}{ca_pem}").into_bytes(), key.into_bytes()));
+        }
+    }
+    let (pem, key) = new_leaf_cert(&name, &ca)?;
+    write_private_file(&key_file, &key)?;
+    write_file(&cert_file, &pem)?;
+    info!("Create self signed certificate, name: {name}");
+    Ok((format!("{pem}{ca_pem}").into_bytes(), key.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_self_signed_name, get_or_create_ca, is_valid_domain, new_leaf_cert};
+    use crate::acme::get_cert_info;
+    use pretty_assertions::assert_eq;
+    use std::os::unix::fs::PermissionsExt;
+    use x509_parser::extensions::GeneralName;
+
+    #[test]
+    fn test_self_signed() {
+        assert_eq!(true, is_valid_domain("pingap.io"));
+        assert_eq!(true, is_valid_domain("api-1.pingap.io"));
+        assert_eq!(true, is_valid_domain("*.pingap.io"));
+        assert_eq!(false, is_valid_domain(""));
+        assert_eq!(false, is_valid_domain("../ca"));
+        assert_eq!(false, is_valid_domain("pingap.io/ca"));
+
+        let domains = vec!["localhost".to_string(), "*.pingap.test".to_string()];
+        assert_eq!(
+            "localhost",
+            find_self_signed_name("LocalHost", &domains).unwrap()
+        );
+        assert_eq!(
+            "*.pingap.test",
+            find_self_signed_name("api.pingap.test", &domains).unwrap()
+        );
+        assert_eq!(
+            true,
+            find_self_signed_name("pingap.test", &domains).is_none()
+        );
+        assert_eq!(
+            true,
+            find_self_signed_name("a.api.pingap.test", &domains).is_none()
+        );
+        assert_eq!(
+            true,
+            find_self_signed_name("github.com", &domains).is_none()
+        );
+        assert_eq!(
+            true,
+            find_self_signed_name("*.pingap.test", &domains).is_none()
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca_dir = dir.path().join("self-signed");
+        let (pem, ca) = get_or_create_ca(&ca_dir, &domains).unwrap();
+        // the dir and key are only accessible by the owner
+        assert_eq!(
+            0o700,
+            std::fs::metadata(&ca_dir).unwrap().permissions().mode() & 0o777
+        );
+        assert_eq!(
+            0o600,
+            std::fs::metadata(ca_dir.join("ca.key"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        );
+        // the ca is reloaded from file
+        let (reload_pem, _) = get_or_create_ca(&ca_dir, &domains).unwrap();
+        assert_eq!(pem, reload_pem);
+
+        let (_, x509) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).unwrap();
+        let x509 = x509.parse_x509().unwrap();
+        let basic_constraints = x509.basic_constraints().unwrap().unwrap().value;
+        assert_eq!(true, basic_constraints.ca);
+        assert_eq!(Some(0), basic_constraints.path_len_constraint);
+        let name_constraints = x509.name_constraints().unwrap().unwrap().value;
+        let permitted: Vec<String> = name_constraints
+            .permitted_subtrees
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|item| match &item.base {
+                GeneralName::DNSName(name) => name.to_string(),
+                _ => "".to_string(),
+            })
+            .collect();
+        assert_eq!(vec!["localhost", "pingap.test"], permitted);
+
+        let (leaf, _) = new_leaf_cert("*.pingap.test", &ca).unwrap();
+        let ca_info = get_cert_info(pem.as_bytes()).unwrap();
+        let leaf_info = get_cert_info(leaf.as_bytes()).unwrap();
+        assert_eq!(true, ca_info.issuer.contains("Pingap Development CA"));
+        assert_eq!(ca_info.issuer, leaf_info.issuer);
+        assert_eq!(true, leaf_info.not_after > leaf_info.not_before);
+
+        // the ca is recreated if the domains are changed
+        std::fs::write(ca_dir.join("localhost.crt"), "").unwrap();
+        let (new_pem, _) = get_or_create_ca(&ca_dir, &domains[..1]).unwrap();
+        assert_eq!(false, pem == new_pem);
+        assert_eq!(false, ca_dir.join("localhost.crt").exists());
+    }
+}
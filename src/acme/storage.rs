@@ -0,0 +1,297 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use crate::config::{get_config_path, get_current_config, EtcdLock, EtcdStorage, ETCD_PROTOCOL};
+use crate::util;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+// the challenge should be finished in one hour
+const CHALLENGE_TTL: i64 = 3600;
+// the lock is released automatically if the node doesn't unlock in time
+const LOCK_TTL: i64 = 10 * 60;
+
+/// The storage of acme, it saves the cert store and the challenges.
+/// The etcd storage is shared by the nodes of cluster,
+/// so only one node renews the certificate and the others reuse it.
+#[async_trait]
+pub trait AcmeStorage: Send + Sync {
+    /// Load the data of cert store, returns none if it's not exists.
+    async fn load_store(&self) -> Result<Option<Vec<u8>>>;
+    /// Save the data of cert store.
+    async fn save_store(&self, data: Vec<u8>) -> Result<()>;
+    /// Set the value of challenge, the key is the token path of http-01.
+    async fn set_challenge(&self, key: &str, value: &str) -> Result<()>;
+    /// Get the value of challenge.
+    async fn get_challenge(&self, key: &str) -> Result<Option<String>>;
+    /// Remove the challenge, it should be called after the order is finished.
+    async fn remove_challenge(&self, key: &str) -> Result<()>;
+    /// Try to lock the renewal of certificate group, returns the lock if success.
+    async fn try_lock(&self, name: &str) -> Result<Option<AcmeLock>>;
+}
+
+/// The lock of renewal, the etcd lock is kept alive until it's unlocked,
+/// and it's released in background if the lock is dropped.
+pub struct AcmeLock {
+    etcd_lock: Option<EtcdLock>,
+}
+
+impl AcmeLock {
+    /// Release the lock of renewal.
+    pub async fn unlock(self) -> Result<()> {
+        if let Some(lock) = self.etcd_lock {
+            lock.unlock()
+                .await
+                .map_err(|e| Error::Config { source: e })?;
+        }
+        Ok(())
+    }
+}
+
+// the challenges of local node
+static CHALLENGES: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The local file storage, the challenges are saved in memory.
+struct FileAcmeStorage {
+    path: PathBuf,
+}
+
+#[async_trait]
+impl AcmeStorage for FileAcmeStorage {
+    async fn load_store(&self) -> Result<Option<Vec<u8>>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let buf = fs::read(&self.path)
+            .await
+            .map_err(|e| Error::Io { source: e })?;
+        Ok(Some(buf))
+    }
+    async fn save_store(&self, data: Vec<u8>) -> Result<()> {
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| Error::Io { source: e })?;
+        f.write_all(&data)
+            .await
+            .map_err(|e| Error::Io { source: e })?;
+        Ok(())
+    }
+    async fn set_challenge(&self, key: &str, value: &str) -> Result<()> {
+        CHALLENGES
+            .lock()
+            .await
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+    async fn get_challenge(&self, key: &str) -> Result<Option<String>> {
+        Ok(CHALLENGES.lock().await.get(key).cloned())
+    }
+    async fn remove_challenge(&self, key: &str) -> Result<()> {
+        CHALLENGES.lock().await.remove(key);
+        Ok(())
+    }
+    async fn try_lock(&self, _name: &str) -> Result<Option<AcmeLock>> {
+        // each group has only one renewal service in the process
+        Ok(Some(AcmeLock { etcd_lock: None }))
+    }
+}
+
+/// The etcd storage, it's shared by all nodes of cluster.
+struct EtcdAcmeStorage {
+    storage: EtcdStorage,
+}
+
+impl EtcdAcmeStorage {
+    fn get_challenge_key(key: &str) -> String {
+        format!("acme/challenges/{}", key.trim_start_matches('/'))
+    }
+}
+
+#[async_trait]
+impl AcmeStorage for EtcdAcmeStorage {
+    async fn load_store(&self) -> Result<Option<Vec<u8>>> {
+        self.storage
+            .get_data("acme/store")
+            .await
+            .map_err(|e| Error::Config { source: e })
+    }
+    async fn save_store(&self, data: Vec<u8>) -> Result<()> {
+        self.storage
+            .put_data("acme/store", data, None)
+            .await
+            .map_err(|e| Error::Config { source: e })
+    }
+    async fn set_challenge(&self, key: &str, value: &str) -> Result<()> {
+        self.storage
+            .put_data(
+                &Self::get_challenge_key(key),
+                value.as_bytes().to_vec(),
+                Some(CHALLENGE_TTL),
+            )
+            .await
+            .map_err(|e| Error::Config { source: e })
+    }
+    async fn get_challenge(&self, key: &str) -> Result<Option<String>> {
+        let value = self
+            .storage
+            .get_data(&Self::get_challenge_key(key))
+            .await
+            .map_err(|e| Error::Config { source: e })?;
+        Ok(value.map(|value| String::from_utf8_lossy(&value).to_string()))
+    }
+    async fn remove_challenge(&self, key: &str) -> Result<()> {
+        self.storage
+            .delete_data(&Self::get_challenge_key(key))
+            .await
+            .map_err(|e| Error::Config { source: e })
+    }
+    async fn try_lock(&self, name: &str) -> Result<Option<AcmeLock>> {
+        let lock = self
+            .storage
+            .try_lock(&format!("acme/locks/{name}"), LOCK_TTL)
+            .await
+            .map_err(|e| Error::Config { source: e })?;
+        Ok(lock.map(|lock| AcmeLock {
+            etcd_lock: Some(lock),
+        }))
+    }
+}
+
+/// Get the address of acme storage, etcd storage is used if the certificate file
+/// is an etcd url, or it's not set and the config is stored in etcd.
+/// Otherwise the local file is used.
+/// Returns whether it's etcd and the address.
+fn get_acme_storage_address() -> (bool, String) {
+    let file = get_current_config()
+        .basic
+        .certificate_file
+        .clone()
+        .unwrap_or_default();
+    let config_path = get_config_path();
+    if file.starts_with(ETCD_PROTOCOL) {
+        return (true, file);
+    }
+    if file.is_empty() && config_path.starts_with(ETCD_PROTOCOL) {
+        return (true, config_path);
+    }
+    let path = if file.is_empty() {
+        env::temp_dir()
+            .join("pingap-lets-encrypt.json")
+            .to_string_lossy()
+            .to_string()
+    } else {
+        util::resolve_path(&file)
+    };
+    (false, path)
+}
+
+/// Create the acme storage, the etcd client of it is connected in the current runtime.
+pub fn new_acme_storage() -> Result<Box<dyn AcmeStorage>> {
+    let (is_etcd, address) = get_acme_storage_address();
+    if is_etcd {
+        let storage = EtcdStorage::new(&address).map_err(|e| Error::Config { source: e })?;
+        return Ok(Box::new(EtcdAcmeStorage { storage }));
+    }
+    Ok(Box::new(FileAcmeStorage {
+        path: address.into(),
+    }))
+}
+
+// the shared acme storage and its address
+static ACME_STORAGE: Lazy<std::sync::Mutex<Option<(String, Arc<dyn AcmeStorage>)>>> =
+    Lazy::new(|| std::sync::Mutex::new(None));
+
+/// Get the shared acme storage, so the etcd client is reused by all requests.
+/// It's created again if the address is changed.
+/// The etcd client is bound to the runtime in which it's connected,
+/// so the temporary runtime should use `new_acme_storage` instead.
+pub fn get_acme_storage() -> Result<Arc<dyn AcmeStorage>> {
+    let (_, address) = get_acme_storage_address();
+    let mut shared = ACME_STORAGE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((shared_address, storage)) = shared.as_ref() {
+        if shared_address == &address {
+            return Ok(storage.clone());
+        }
+    }
+    let storage: Arc<dyn AcmeStorage> = new_acme_storage()?.into();
+    *shared = Some((address, storage.clone()));
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AcmeStorage, EtcdAcmeStorage, FileAcmeStorage};
+    use pretty_assertions::assert_eq;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_get_challenge_key() {
+        assert_eq!(
+            "acme/challenges/.well-known/acme-challenge/token",
+            EtcdAcmeStorage::get_challenge_key("/.well-known/acme-challenge/token")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_acme_storage() {
+        let file = NamedTempFile::with_suffix(".json").unwrap();
+        let storage = FileAcmeStorage {
+            path: file.path().to_path_buf(),
+        };
+        storage.save_store(b"{}".to_vec()).await.unwrap();
+        assert_eq!(b"{}".to_vec(), storage.load_store().await.unwrap().unwrap());
+
+        storage
+            .set_challenge("/.well-known/acme-challenge/token", "pingap")
+            .await
+            .unwrap();
+        assert_eq!(
+            "pingap",
+            storage
+                .get_challenge("/.well-known/acme-challenge/token")
+                .await
+                .unwrap()
+                .unwrap()
+        );
+        assert_eq!(true, storage.get_challenge("/abc").await.unwrap().is_none());
+        storage
+            .remove_challenge("/.well-known/acme-challenge/token")
+            .await
+            .unwrap();
+        assert_eq!(
+            true,
+            storage
+                .get_challenge("/.well-known/acme-challenge/token")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let lock = storage.try_lock("pingap").await.unwrap();
+        assert_eq!(true, lock.is_some());
+        lock.unwrap().unlock().await.unwrap();
+    }
+}
@@ -12,48 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::proxy::{get_certificate_info_list, CertificateInfo};
 use crate::service::{CommonServiceTask, ServiceTask};
 use crate::util;
 use crate::webhook;
 use async_trait::async_trait;
-use log::warn;
 use std::time::Duration;
 
-use super::CertInfo;
-
 struct ValidityChecker {
-    time_offset: i64,
-    tls_cert_info_list: Vec<(String, CertInfo)>,
+    // warn if the cert will be expired within the time
+    warning_offset: i64,
+    // error if the cert will be expired within the time
+    critical_offset: i64,
 }
 
-fn validity_check(validity_list: &[(String, CertInfo)], time_offset: i64) -> Option<String> {
+/// Check the validity of all certificates, returns the level and message
+/// of each certificate which is expiring or not valid yet.
+fn validity_check(
+    validity_list: &[CertificateInfo],
+    warning_offset: i64,
+    critical_offset: i64,
+) -> Vec<(webhook::NotificationLevel, String)> {
     let now = util::now().as_secs() as i64;
-    for (name, cert) in validity_list.iter() {
-        if now > cert.not_after - time_offset {
+    let mut messages = vec![];
+    for cert in validity_list.iter() {
+        let name = &cert.server;
+        if now > cert.not_after - warning_offset {
+            let level = if now > cert.not_after - critical_offset {
+                webhook::NotificationLevel::Error
+            } else {
+                webhook::NotificationLevel::Warn
+            };
             let message = format!(
-                "{name} cert will be expired, issuer: {}, expired date: {:?}",
-                cert.issuer, cert.not_after
+                "{name} cert will be expired, domains: {:?}, issuer: {}, expired date: {:?}",
+                cert.domains, cert.issuer, cert.not_after
             );
-            return Some(message);
+            messages.push((level, message));
+            continue;
         }
         if now < cert.not_before {
             let message = format!(
-                "{name} cert is not valid, issuer: {}, valid date: {:?}",
-                cert.issuer, cert.not_before
+                "{name} cert is not valid, domains: {:?}, issuer: {}, valid date: {:?}",
+                cert.domains, cert.issuer, cert.not_before
             );
-            return Some(message);
+            messages.push((webhook::NotificationLevel::Warn, message));
         }
     }
-    None
+    messages
 }
 
 #[async_trait]
 impl ServiceTask for ValidityChecker {
     async fn run(&self) -> Option<bool> {
-        if let Some(message) = validity_check(&self.tls_cert_info_list, self.time_offset) {
-            warn!("{message}");
+        // check the loaded certificates, they may be replaced after startup
+        let messages = validity_check(
+            &get_certificate_info_list(),
+            self.warning_offset,
+            self.critical_offset,
+        );
+        for (level, message) in messages {
             webhook::send(webhook::SendNotificationParams {
-                level: webhook::NotificationLevel::Warn,
+                level,
                 category: webhook::NotificationCategory::TlsValidity,
                 msg: message,
             });
@@ -61,19 +80,17 @@ impl ServiceTask for ValidityChecker {
         None
     }
     fn description(&self) -> String {
-        let offset_human: humantime::Duration = Duration::from_secs(self.time_offset as u64).into();
-        format!(
-            "offset: {offset_human}, tls_cert_info_list: {:?}",
-            self.tls_cert_info_list
-        )
+        let warning: humantime::Duration = Duration::from_secs(self.warning_offset as u64).into();
+        let critical: humantime::Duration = Duration::from_secs(self.critical_offset as u64).into();
+        format!("warning: {warning}, critical: {critical}")
     }
 }
 
-pub fn new_tls_validity_service(tls_cert_info_list: Vec<(String, CertInfo)>) -> CommonServiceTask {
+/// Create the validity checker of certificates, it checks once a day.
+pub fn new_tls_validity_service(warning: Duration, critical: Duration) -> CommonServiceTask {
     let checker = ValidityChecker {
-        tls_cert_info_list,
-        // cert will be expired 7 days later
-        time_offset: 7 * 24 * 3600_i64,
+        warning_offset: warning.as_secs() as i64,
+        critical_offset: critical.as_secs() as i64,
     };
     CommonServiceTask::new(
         "Tls validity checker",
@@ -86,69 +103,62 @@ pub fn new_tls_validity_service(tls_cert_info_list: Vec<(String, CertInfo)>) ->
 #[cfg(test)]
 mod tests {
     use super::{new_tls_validity_service, validity_check, ValidityChecker};
-    use crate::{acme::CertInfo, service::ServiceTask};
+    use crate::proxy::CertificateInfo;
+    use crate::service::ServiceTask;
+    use crate::util;
+    use crate::webhook::NotificationLevel;
     use pretty_assertions::assert_eq;
-    use x509_parser::time::ASN1Time;
+    use std::time::Duration;
 
     #[test]
     fn test_validity_check() {
+        let now = util::now().as_secs() as i64;
+        let new_cert = |server: &str, not_before: i64, not_after: i64| CertificateInfo {
+            server: server.to_string(),
+            domains: vec!["pingap.io".to_string()],
+            issuer: "pingap".to_string(),
+            not_before,
+            not_after,
+            ..Default::default()
+        };
         let result = validity_check(
-            &[(
-                "Pingap".to_string(),
-                CertInfo {
-                    not_after: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                    not_before: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                    issuer: "pingap".to_string(),
-                },
-            )],
+            &[
+                new_cert("valid", now - 10, now + 30 * 24 * 3600),
+                new_cert("not_valid", 2651852800, 2651852800),
+                new_cert("warning", now - 10, now + 3 * 24 * 3600),
+                new_cert("critical", now - 10, now + 3600),
+            ],
             7 * 24 * 3600,
+            24 * 3600,
         );
+        assert_eq!(3, result.len());
+        assert_eq!(NotificationLevel::Warn, result[0].0);
         assert_eq!(
-            "Pingap cert is not valid, issuer: pingap, valid date: 2651852800",
-            result.unwrap_or_default().to_string()
+            r#"not_valid cert is not valid, domains: ["pingap.io"], issuer: pingap, valid date: 2651852800"#,
+            result[0].1
         );
-
-        let result = validity_check(
-            &[(
-                "Pingap".to_string(),
-                CertInfo {
-                    not_after: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                    not_before: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                    issuer: "pingap".to_string(),
-                },
-            )],
-            7 * 24 * 3600,
+        assert_eq!(NotificationLevel::Warn, result[1].0);
+        assert_eq!(
+            true,
+            result[1].1.starts_with("warning cert will be expired")
         );
+        assert_eq!(NotificationLevel::Error, result[2].0);
         assert_eq!(
-            "Pingap cert is not valid, issuer: pingap, valid date: 2651852800",
-            result.unwrap_or_default().to_string()
+            true,
+            result[2].1.starts_with("critical cert will be expired")
         );
     }
     #[tokio::test]
     async fn test_validity_service() {
-        let _ = new_tls_validity_service(vec![(
-            "Pingap".to_string(),
-            CertInfo {
-                not_after: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                not_before: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                issuer: "".to_string(),
-            },
-        )]);
+        let _ = new_tls_validity_service(
+            Duration::from_secs(7 * 24 * 3600),
+            Duration::from_secs(24 * 3600),
+        );
         let checker = ValidityChecker {
-            tls_cert_info_list: vec![(
-                "Pingap".to_string(),
-                CertInfo {
-                    not_after: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                    not_before: ASN1Time::from_timestamp(2651852800).unwrap().timestamp(),
-                    issuer: "".to_string(),
-                },
-            )],
-            time_offset: 7 * 24 * 3600_i64,
+            warning_offset: 7 * 24 * 3600_i64,
+            critical_offset: 24 * 3600_i64,
         };
-        assert_eq!(
-            r#"offset: 7days, tls_cert_info_list: [("Pingap", CertInfo { not_after: 2651852800, not_before: 2651852800, issuer: "" })]"#,
-            checker.description()
-        );
+        assert_eq!("warning: 7days, critical: 1day", checker.description());
         let result = checker.run().await;
         assert_eq!(true, result.is_none());
     }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::{Error, Result};
+use crate::http_extra::is_valid_error_format;
 use crate::plugin::parse_plugins;
 use crate::proxy::Parser;
 use crate::util;
@@ -36,7 +37,16 @@ pub const CATEGORY_UPSTREAM: &str = "upstream";
 pub const CATEGORY_LOCATION: &str = "location";
 pub const CATEGORY_SERVER: &str = "server";
 pub const CATEGORY_PLUGIN: &str = "plugin";
+pub const CATEGORY_STREAM: &str = "stream";
 pub const CATEGORY_BASIC: &str = "basic";
+pub const CATEGORIES: [&str; 6] = [
+    CATEGORY_BASIC,
+    CATEGORY_SERVER,
+    CATEGORY_LOCATION,
+    CATEGORY_UPSTREAM,
+    CATEGORY_PLUGIN,
+    CATEGORY_STREAM,
+];
 
 #[derive(PartialEq, Debug, Default, Clone, EnumString, strum::Display)]
 #[strum(serialize_all = "snake_case")]
@@ -59,6 +69,8 @@ pub enum PluginCategory {
     ResponseHeaders,
     RefererRestriction,
     Csrf,
+    ClientCertRestriction,
+    TlsFingerprintRestriction,
 }
 
 impl Serialize for PluginCategory {
@@ -182,6 +194,100 @@ impl UpstreamConf {
     }
 }
 
+/// The fallback of error page, it can be an internal location
+/// or a static response body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorPage {
+    Location(String),
+    Body(String),
+}
+
+/// Parse the error status list, e.g. `502,503`, the status should be 4xx or 5xx.
+fn parse_error_status_list(codes: &str) -> Option<Vec<u16>> {
+    let mut status_list = vec![];
+    for code in codes.split(',') {
+        let status = code.trim().parse::<u16>().ok()?;
+        if !(400..600).contains(&status) {
+            return None;
+        }
+        status_list.push(status);
+    }
+    Some(status_list)
+}
+
+/// Parse the error template config, e.g. `502,503 <h1>{{status}}</h1>`.
+/// Returns the status list and the html template.
+pub fn parse_error_template(value: &str) -> Result<(Vec<u16>, String)> {
+    let invalid = || Error::Invalid {
+        message: format!("error template {value} is invalid"),
+    };
+    let (codes, template) = value.trim().split_once(' ').ok_or_else(invalid)?;
+    let status_list = parse_error_status_list(codes).ok_or_else(invalid)?;
+    let template = template.trim();
+    if template.is_empty() {
+        return Err(invalid());
+    }
+    Ok((status_list, template.to_string()))
+}
+
+/// Load the error template, the template which starts with `file:` is loaded from the file.
+pub fn load_error_template(template: &str) -> Result<String> {
+    if let Some(file) = template.strip_prefix("file:") {
+        let file = util::resolve_path(file.trim());
+        return std::fs::read_to_string(&file).map_err(|e| Error::Io { source: e, file });
+    }
+    Ok(template.to_string())
+}
+
+/// Validate the error templates, the template file should be exists.
+fn validate_error_templates(
+    values: &Option<Vec<String>>,
+    category: &str,
+    name: &str,
+) -> Result<()> {
+    for item in values.clone().unwrap_or_default().iter() {
+        let (_, template) = parse_error_template(item).map_err(|_| Error::Invalid {
+            message: format!("error template {item} is invalid({category}:{name})"),
+        })?;
+        load_error_template(&template)?;
+    }
+    Ok(())
+}
+
+/// Validate the error format, it should be `auto`, `html`, `json` or `text`.
+fn validate_error_format(value: &Option<String>, category: &str, name: &str) -> Result<()> {
+    if let Some(format) = value {
+        if !is_valid_error_format(format) {
+            return Err(Error::Invalid {
+                message: format!("error format({format}) is invalid({category}:{name})"),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Parse the error page config, e.g. `404 @static`, `502,503 =Service maintenance`.
+/// Returns the status list and the fallback of error page.
+pub fn parse_error_page(value: &str) -> Result<(Vec<u16>, ErrorPage)> {
+    let invalid = || Error::Invalid {
+        message: format!("error page {value} is invalid"),
+    };
+    let (codes, target) = value.trim().split_once(' ').ok_or_else(invalid)?;
+    let status_list = parse_error_status_list(codes).ok_or_else(invalid)?;
+    let target = target.trim();
+    let page = if let Some(name) = target.strip_prefix('@') {
+        if name.trim().is_empty() {
+            return Err(invalid());
+        }
+        ErrorPage::Location(name.trim().to_string())
+    } else if let Some(body) = target.strip_prefix('=') {
+        ErrorPage::Body(body.to_string())
+    } else {
+        return Err(invalid());
+    };
+    Ok((status_list, page))
+}
+
 #[derive(Debug, Default, Deserialize, Clone, Serialize)]
 pub struct LocationConf {
     pub upstream: Option<String>,
@@ -193,6 +299,15 @@ pub struct LocationConf {
     pub weight: Option<u16>,
     pub plugins: Option<Vec<String>>,
     pub client_max_body_size: Option<ByteSize>,
+    pub error_page: Option<Vec<String>>,
+    pub error_format: Option<String>,
+    pub error_templates: Option<Vec<String>>,
+    pub intercept_errors: Option<bool>,
+    pub enabled_websocket: Option<bool>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub websocket_idle_timeout: Option<Duration>,
+    pub websocket_max_connections: Option<u32>,
     pub remark: Option<String>,
 }
 
@@ -240,9 +355,28 @@ impl LocationConf {
             let arr: Vec<&str> = value.split(' ').collect();
             let _ = Regex::new(arr[0]).map_err(|e| Error::Regex { source: e })?;
         }
+        for item in self.error_page.clone().unwrap_or_default().iter() {
+            parse_error_page(item).map_err(|_| Error::Invalid {
+                message: format!("error page {item} is invalid(location:{name})"),
+            })?;
+        }
+        validate_error_format(&self.error_format, "location", name)?;
+        validate_error_templates(&self.error_templates, "location", name)?;
 
         Ok(())
     }
+    /// Get the location names which are used as error page fallback.
+    pub fn get_error_page_locations(&self) -> Vec<String> {
+        let mut locations = vec![];
+        for item in self.error_page.clone().unwrap_or_default().iter() {
+            if let Ok((_, ErrorPage::Location(name))) = parse_error_page(item) {
+                if !locations.contains(&name) {
+                    locations.push(name);
+                }
+            }
+        }
+        locations
+    }
     /// Get weight of location.
     pub fn get_weight(&self) -> u16 {
         if let Some(weight) = self.weight {
@@ -271,6 +405,28 @@ impl LocationConf {
     }
 }
 
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct TlsCertificateConf {
+    pub cert: String,
+    pub key: String,
+}
+
+/// Generate the self signed certificates for server automatically.
+pub const TLS_AUTO_SELF_SIGNED: &str = "self_signed";
+
+fn validate_pem_or_base64(value: &str) -> Result<()> {
+    // the secret reference is validated after it's resolved
+    if super::is_secret_ref(value) {
+        return Ok(());
+    }
+    if !util::is_pem(value) {
+        let _ = STANDARD
+            .decode(value)
+            .map_err(|e| Error::Base64Decode { source: e })?;
+    }
+    Ok(())
+}
+
 #[derive(Debug, Default, Deserialize, Clone, Serialize)]
 pub struct ServerConf {
     pub addr: String,
@@ -279,11 +435,30 @@ pub struct ServerConf {
     pub threads: Option<usize>,
     pub tls_cert: Option<String>,
     pub tls_key: Option<String>,
+    pub tls_certificates: Option<Vec<TlsCertificateConf>>,
+    pub tls_certificate_dir: Option<String>,
+    pub tls_auto: Option<String>,
+    pub tls_client_ca: Option<String>,
+    pub tls_client_verify: Option<String>,
+    pub enabled_ocsp_stapling: Option<bool>,
+    pub tls_ocsp_response_file: Option<String>,
+    pub tls_session_cache_size: Option<usize>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub tls_session_timeout: Option<Duration>,
+    pub enabled_tls_session_ticket: Option<bool>,
+    pub tls_ticket_keys: Option<Vec<String>>,
+    pub tls_ticket_key_file: Option<String>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub tls_ticket_key_rotation: Option<Duration>,
+    pub enabled_tls_fingerprint: Option<bool>,
     pub tls_cipher_list: Option<String>,
     pub tls_ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
     pub lets_encrypt: Option<String>,
+    pub lets_encrypt_group: Option<String>,
     pub enabled_h2: Option<bool>,
     #[serde(default)]
     #[serde(with = "humantime_serde")]
@@ -293,18 +468,81 @@ pub struct ServerConf {
     pub tcp_interval: Option<Duration>,
     pub tcp_probe_count: Option<usize>,
     pub tcp_fastopen: Option<usize>,
+    pub unix_socket_mode: Option<String>,
+    pub error_format: Option<String>,
+    pub error_templates: Option<Vec<String>>,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub header_read_timeout: Option<Duration>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub body_read_timeout: Option<Duration>,
+    pub min_transfer_rate: Option<ByteSize>,
     pub remark: Option<String>,
 }
 
 impl ServerConf {
+    /// Returns true if the server is listened on tls.
+    pub fn is_tls(&self) -> bool {
+        self.tls_cert.is_some()
+            || self.tls_certificates.is_some()
+            || self.tls_certificate_dir.is_some()
+            || self.tls_auto.is_some()
+            || self.lets_encrypt.is_some()
+    }
+    /// Returns true if the certificate options of server are changed.
+    pub fn is_tls_changed(&self, other: &ServerConf) -> bool {
+        self.tls_cert != other.tls_cert
+            || self.tls_key != other.tls_key
+            || self.tls_certificates != other.tls_certificates
+            || self.tls_certificate_dir != other.tls_certificate_dir
+            || self.tls_auto != other.tls_auto
+    }
     /// Validate the options of server config.
     fn validate(&self, name: &str, location_names: &[String]) -> Result<()> {
         for addr in self.addr.split(',') {
+            if let Some(path) = util::get_unix_socket_path(addr) {
+                if path.is_empty() || path == "\0" {
+                    return Err(Error::Invalid {
+                        message: format!("unix socket path is empty(server:{name})"),
+                    });
+                }
+                // the permission of abstract socket is not confirmed for the listener
+                if path.starts_with('\0') {
+                    return Err(Error::Invalid {
+                        message: format!("abstract socket is not supported(server:{name})"),
+                    });
+                }
+                if self.is_tls() {
+                    return Err(Error::Invalid {
+                        message: format!("tls is not supported for unix socket(server:{name})"),
+                    });
+                }
+                continue;
+            }
             let _ = addr.to_socket_addrs().map_err(|e| Error::Io {
                 source: e,
                 file: self.addr.clone(),
             })?;
         }
+        if let Some(value) = &self.unix_socket_mode {
+            if u32::from_str_radix(value, 8).is_err() {
+                return Err(Error::Invalid {
+                    message: format!("unix socket mode({value}) is invalid(server:{name})"),
+                });
+            }
+        }
+        if let (Some(max), Some(max_per_ip)) = (self.max_connections, self.max_connections_per_ip) {
+            if max_per_ip > max {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "max connections per ip({max_per_ip}) should not be greater than max connections({max})(server:{name})"
+                    ),
+                });
+            }
+        }
         if let Some(locations) = &self.locations {
             for item in locations {
                 if !location_names.contains(item) {
@@ -314,18 +552,60 @@ impl ServerConf {
                 }
             }
         }
-        if let Some(value) = &self.tls_key {
-            if !util::is_pem(value) {
-                let _ = STANDARD
-                    .decode(value)
-                    .map_err(|e| Error::Base64Decode { source: e })?;
+        validate_error_format(&self.error_format, "server", name)?;
+        validate_error_templates(&self.error_templates, "server", name)?;
+        if let Some(value) = &self.tls_auto {
+            if value != TLS_AUTO_SELF_SIGNED {
+                return Err(Error::Invalid {
+                    message: format!("tls auto({value}) is not supported(server:{name})"),
+                });
             }
         }
+        if let Some(value) = &self.tls_key {
+            validate_pem_or_base64(value)?;
+        }
         if let Some(value) = &self.tls_cert {
-            if !util::is_pem(value) {
-                let _ = STANDARD
-                    .decode(value)
+            validate_pem_or_base64(value)?;
+        }
+        if let Some(certificates) = &self.tls_certificates {
+            for item in certificates {
+                validate_pem_or_base64(&item.cert)?;
+                validate_pem_or_base64(&item.key)?;
+            }
+        }
+        if let Some(value) = &self.tls_client_ca {
+            validate_pem_or_base64(value)?;
+        }
+        if let Some(value) = &self.tls_client_verify {
+            if !["off", "optional", "required"].contains(&value.as_str()) {
+                return Err(Error::Invalid {
+                    message: format!("tls client verify({value}) is invalid(server:{name})"),
+                });
+            }
+            if value != "off" && self.tls_client_ca.is_none() {
+                return Err(Error::Invalid {
+                    message: format!("tls client ca is required(server:{name})"),
+                });
+            }
+        }
+        if let Some(keys) = &self.tls_ticket_keys {
+            for key in keys {
+                let buf = STANDARD
+                    .decode(key)
                     .map_err(|e| Error::Base64Decode { source: e })?;
+                // name(16) + hmac key(32) + aes key(32)
+                if buf.len() != 80 {
+                    return Err(Error::Invalid {
+                        message: format!("tls ticket key should be 80 bytes(server:{name})"),
+                    });
+                }
+            }
+        }
+        if let Some(dir) = &self.tls_certificate_dir {
+            if !std::path::Path::new(dir).is_dir() {
+                return Err(Error::Invalid {
+                    message: format!("tls certificate dir({dir}) is not found(server:{name})"),
+                });
             }
         }
         if let Some(access_log) = &self.access_log {
@@ -340,6 +620,101 @@ impl ServerConf {
         Ok(())
     }
 }
+#[derive(Debug, Default, Deserialize, Clone, Serialize)]
+pub struct StreamConf {
+    pub addr: String,
+    pub upstream: Option<String>,
+    pub sni_upstreams: Option<Vec<String>>,
+    pub access_log: Option<String>,
+    pub threads: Option<usize>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_certificates: Option<Vec<TlsCertificateConf>>,
+    pub tls_certificate_dir: Option<String>,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub client_hello_timeout: Option<Duration>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Option<Duration>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub total_timeout: Option<Duration>,
+    pub remark: Option<String>,
+}
+
+/// Parse the sni upstream config, e.g. `pg.example.com postgres`, `*.example.com redis`.
+/// Returns the domain and the upstream name.
+pub fn parse_sni_upstream(value: &str) -> Result<(String, String)> {
+    let arr: Vec<&str> = value.split_whitespace().collect();
+    if arr.len() != 2 {
+        return Err(Error::Invalid {
+            message: format!("sni upstream {value} is invalid"),
+        });
+    }
+    Ok((arr[0].to_lowercase(), arr[1].to_string()))
+}
+
+impl StreamConf {
+    /// Returns true if the stream is listened on tls.
+    pub fn is_tls(&self) -> bool {
+        self.tls_cert.is_some()
+            || self.tls_certificates.is_some()
+            || self.tls_certificate_dir.is_some()
+    }
+    /// Validate the options of stream config.
+    fn validate(&self, name: &str, upstream_names: &[String]) -> Result<()> {
+        for addr in self.addr.split(',') {
+            let _ = addr.to_socket_addrs().map_err(|e| Error::Io {
+                source: e,
+                file: self.addr.clone(),
+            })?;
+        }
+        let mut upstreams = vec![];
+        if let Some(upstream) = &self.upstream {
+            upstreams.push(upstream.clone());
+        }
+        for item in self.sni_upstreams.clone().unwrap_or_default() {
+            let (_, upstream) = parse_sni_upstream(&item)?;
+            upstreams.push(upstream);
+        }
+        if upstreams.is_empty() {
+            return Err(Error::Invalid {
+                message: format!("upstream is required(stream:{name})"),
+            });
+        }
+        for upstream in upstreams.iter() {
+            if !upstream_names.contains(upstream) {
+                return Err(Error::Invalid {
+                    message: format!("upstream({upstream}) is not found(stream:{name})"),
+                });
+            }
+        }
+        if let Some(value) = &self.tls_key {
+            validate_pem_or_base64(value)?;
+        }
+        if let Some(value) = &self.tls_cert {
+            validate_pem_or_base64(value)?;
+        }
+        if let Some(certificates) = &self.tls_certificates {
+            for item in certificates {
+                validate_pem_or_base64(&item.cert)?;
+                validate_pem_or_base64(&item.key)?;
+            }
+        }
+        if let Some(dir) = &self.tls_certificate_dir {
+            if !std::path::Path::new(dir).is_dir() {
+                return Err(Error::Invalid {
+                    message: format!("tls certificate dir({dir}) is not found(stream:{name})"),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Clone, Serialize)]
 pub struct BasicConf {
     pub name: Option<String>,
@@ -368,7 +743,90 @@ pub struct BasicConf {
     #[serde(with = "humantime_serde")]
     pub auto_restart_check_interval: Option<Duration>,
     pub cache_max_size: Option<ByteSize>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub tls_validity_warning: Option<Duration>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub tls_validity_critical: Option<Duration>,
     pub certificate_file: Option<String>,
+    pub self_signed_dir: Option<String>,
+    pub self_signed_domains: Option<Vec<String>>,
+    pub lets_encrypt_challenge: Option<String>,
+    pub lets_encrypt_dns_provider: Option<String>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub lets_encrypt_dns_delay: Option<Duration>,
+    pub lets_encrypt_directory: Option<String>,
+    pub lets_encrypt_directory_ca: Option<String>,
+    pub lets_encrypt_contact: Option<String>,
+    pub lets_encrypt_eab_kid: Option<String>,
+    pub lets_encrypt_eab_hmac_key: Option<String>,
+}
+
+impl BasicConf {
+    /// Validate the options of basic config.
+    fn validate(&self) -> Result<()> {
+        if let Some(value) = &self.lets_encrypt_challenge {
+            match value.as_str() {
+                "http-01" | "tls-alpn-01" => {}
+                "dns-01" => {
+                    if self.lets_encrypt_dns_provider.is_none() {
+                        return Err(Error::Invalid {
+                            message: "dns provider should be set for dns-01 challenge".to_string(),
+                        });
+                    }
+                }
+                _ => {
+                    return Err(Error::Invalid {
+                        message: format!("lets encrypt challenge({value}) is not supported"),
+                    });
+                }
+            }
+        }
+        if let Some(value) = &self.lets_encrypt_dns_provider {
+            crate::acme::new_dns_provider(value).map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })?;
+        }
+        if let Some(value) = &self.lets_encrypt_directory {
+            let url = crate::acme::get_acme_directory_url(value);
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(Error::Invalid {
+                    message: format!("lets encrypt directory({value}) is invalid"),
+                });
+            }
+        }
+        for domain in self.self_signed_domains.clone().unwrap_or_default() {
+            if !crate::acme::is_valid_domain(domain.trim()) {
+                return Err(Error::Invalid {
+                    message: format!("self signed domain({domain}) is invalid"),
+                });
+            }
+        }
+        if let Some(value) = &self.lets_encrypt_directory_ca {
+            let file = util::resolve_path(value);
+            if !std::path::Path::new(&file).is_file() {
+                return Err(Error::Invalid {
+                    message: format!("lets encrypt directory ca({value}) is not found"),
+                });
+            }
+        }
+        match (&self.lets_encrypt_eab_kid, &self.lets_encrypt_eab_hmac_key) {
+            (Some(_), Some(hmac_key)) if !super::is_secret_ref(hmac_key) => {
+                crate::acme::decode_eab_hmac_key(hmac_key).map_err(|e| Error::Invalid {
+                    message: e.to_string(),
+                })?;
+            }
+            (Some(_), Some(_)) | (None, None) => {}
+            _ => {
+                return Err(Error::Invalid {
+                    message: "eab kid and hmac key should be set together".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -378,6 +836,7 @@ struct TomlConfig {
     upstreams: Option<Map<String, Value>>,
     locations: Option<Map<String, Value>>,
     plugins: Option<Map<String, Value>>,
+    streams: Option<Map<String, Value>>,
 }
 
 fn format_toml(value: &Value) -> String {
@@ -397,6 +856,7 @@ pub struct PingapConf {
     pub locations: HashMap<String, LocationConf>,
     pub servers: HashMap<String, ServerConf>,
     pub plugins: HashMap<String, PluginConf>,
+    pub streams: HashMap<String, StreamConf>,
 }
 
 impl PingapConf {
@@ -441,11 +901,21 @@ impl PingapConf {
                 let value = toml::to_string_pretty(&m).map_err(|e| Error::Ser { source: e })?;
                 ("/plugins.toml".to_string(), value)
             }
+            CATEGORY_STREAM => {
+                let mut m = Map::new();
+                let _ = m.insert(
+                    "streams".to_string(),
+                    toml::Value::Table(data.streams.unwrap_or_default()),
+                );
+                let value = toml::to_string_pretty(&m).map_err(|e| Error::Ser { source: e })?;
+                ("/streams.toml".to_string(), value)
+            }
             _ => {
                 data.servers = None;
                 data.locations = None;
                 data.upstreams = None;
                 data.plugins = None;
+                data.streams = None;
                 let value = toml::to_string_pretty(&data).map_err(|e| Error::Ser { source: e })?;
                 ("/basic.toml".to_string(), value)
             }
@@ -488,6 +958,11 @@ impl TryFrom<&[u8]> for PingapConf {
                 .map_err(|e| Error::De { source: e })?;
             conf.plugins.insert(name, plugin);
         }
+        for (name, value) in data.streams.unwrap_or_default() {
+            let stream: StreamConf = toml::from_str(format_toml(&value).as_str())
+                .map_err(|e| Error::De { source: e })?;
+            conf.streams.insert(name, stream);
+        }
 
         Ok(conf)
     }
@@ -503,6 +978,7 @@ struct Description {
 impl PingapConf {
     /// Validate the options of pinggap config.
     pub fn validate(&self) -> Result<()> {
+        self.basic.validate()?;
         let mut upstream_names = vec![];
         for (name, upstream) in self.upstreams.iter() {
             upstream.validate(name)?;
@@ -513,9 +989,23 @@ impl PingapConf {
             location.validate(name, &upstream_names)?;
             location_names.push(name.to_string());
         }
+        for (name, location) in self.locations.iter() {
+            for item in location.get_error_page_locations() {
+                if !location_names.contains(&item) {
+                    return Err(Error::Invalid {
+                        message: format!(
+                            "error page location({item}) is not found(location:{name})"
+                        ),
+                    });
+                }
+            }
+        }
         for (name, server) in self.servers.iter() {
             server.validate(name, &location_names)?;
         }
+        for (name, stream) in self.streams.iter() {
+            stream.validate(name, &upstream_names)?;
+        }
         for (name, plugin) in self.plugins.iter() {
             parse_plugins(vec![(name.to_string(), plugin.clone())]).map_err(|e| {
                 Error::Invalid {
@@ -540,11 +1030,19 @@ impl PingapConf {
     pub fn remove(&mut self, category: &str, name: &str) -> Result<()> {
         match category {
             CATEGORY_UPSTREAM => {
-                let upstreams: Vec<String> = self
+                let mut upstreams: Vec<String> = self
                     .locations
                     .values()
                     .map(|lo| lo.upstream.clone().unwrap_or_default())
                     .collect();
+                for stream in self.streams.values() {
+                    upstreams.push(stream.upstream.clone().unwrap_or_default());
+                    for item in stream.sni_upstreams.clone().unwrap_or_default() {
+                        if let Ok((_, upstream)) = parse_sni_upstream(&item) {
+                            upstreams.push(upstream);
+                        }
+                    }
+                }
                 if upstreams.contains(&name.to_string()) {
                     return Err(Error::Invalid {
                         message: format!("upstream({name}) is in used"),
@@ -571,6 +1069,9 @@ impl PingapConf {
             CATEGORY_SERVER => {
                 self.servers.remove(name);
             }
+            CATEGORY_STREAM => {
+                self.streams.remove(name);
+            }
             CATEGORY_PLUGIN => {
                 let mut all_plugins = vec![];
                 for lo in self.locations.values() {
@@ -620,10 +1121,18 @@ impl PingapConf {
                 data: toml::to_string_pretty(data).unwrap_or_default(),
             });
         }
+        for (name, data) in value.streams.iter() {
+            descriptions.push(Description {
+                category: CATEGORY_STREAM.to_string(),
+                name: format!("stream:{name}"),
+                data: toml::to_string_pretty(data).unwrap_or_default(),
+            });
+        }
         value.servers = HashMap::new();
         value.locations = HashMap::new();
         value.upstreams = HashMap::new();
         value.plugins = HashMap::new();
+        value.streams = HashMap::new();
         descriptions.push(Description {
             category: CATEGORY_BASIC.to_string(),
             name: CATEGORY_BASIC.to_string(),
@@ -761,8 +1270,9 @@ pub fn get_config_hash() -> String {
 mod tests {
     use super::{get_app_name, get_config_hash, set_app_name, set_current_config, BasicConf};
     use super::{
-        LocationConf, PingapConf, PluginCategory, ServerConf, UpstreamConf, CATEGORY_LOCATION,
-        CATEGORY_PLUGIN, CATEGORY_SERVER, CATEGORY_UPSTREAM,
+        load_error_template, parse_error_page, parse_error_template, parse_sni_upstream, ErrorPage,
+        LocationConf, PingapConf, PluginCategory, ServerConf, StreamConf, TlsCertificateConf,
+        UpstreamConf, CATEGORY_LOCATION, CATEGORY_PLUGIN, CATEGORY_SERVER, CATEGORY_UPSTREAM,
     };
     use pretty_assertions::assert_eq;
     use serde::{Deserialize, Serialize};
@@ -788,6 +1298,60 @@ mod tests {
         assert_eq!("5A7EF0E3", get_config_hash());
     }
 
+    #[test]
+    fn test_basic_conf() {
+        let mut conf = BasicConf {
+            lets_encrypt_challenge: Some("dns-01".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "Invalid error dns provider should be set for dns-01 challenge",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.lets_encrypt_challenge = Some("tls-alpn".to_string());
+        assert_eq!(
+            "Invalid error lets encrypt challenge(tls-alpn) is not supported",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.lets_encrypt_challenge = Some("tls-alpn-01".to_string());
+
+        conf.lets_encrypt_dns_provider = Some("ftp://127.0.0.1".to_string());
+        assert_eq!(
+            "Invalid error Dns provider error, dns provider(ftp://127.0.0.1) is not supported",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.lets_encrypt_dns_provider = Some("https://dns.pingap.io/acme".to_string());
+
+        conf.lets_encrypt_directory = Some("pebble".to_string());
+        assert_eq!(
+            "Invalid error lets encrypt directory(pebble) is invalid",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.lets_encrypt_directory = Some("staging".to_string());
+
+        conf.lets_encrypt_directory_ca = Some("/pingap-not-exists.pem".to_string());
+        assert_eq!(
+            "Invalid error lets encrypt directory ca(/pingap-not-exists.pem) is not found",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.lets_encrypt_directory_ca = None;
+
+        conf.self_signed_domains = Some(vec!["*.pingap.test".to_string(), "../ca".to_string()]);
+        assert_eq!(
+            "Invalid error self signed domain(../ca) is invalid",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.self_signed_domains = Some(vec!["*.pingap.test".to_string()]);
+
+        conf.lets_encrypt_eab_kid = Some("kid".to_string());
+        assert_eq!(
+            "Invalid error eab kid and hmac key should be set together",
+            conf.validate().err().unwrap().to_string()
+        );
+        conf.lets_encrypt_eab_hmac_key = Some("cGluZ2Fw".to_string());
+        assert_eq!(true, conf.validate().is_ok());
+    }
+
     #[test]
     fn test_plugin_category_serde() {
         #[derive(Deserialize, Serialize)]
@@ -888,6 +1452,69 @@ mod tests {
         conf.rewrite = Some(r"^/api /".to_string());
         let result = conf.validate("lo", &upstream_names);
         assert_eq!(true, result.is_ok());
+
+        conf.error_page = Some(vec!["404".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(true, result.is_err());
+        assert_eq!(
+            "Invalid error error page 404 is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.error_page = Some(vec![
+            "404,502 @static".to_string(),
+            "503 =Maintenance".to_string(),
+        ]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(true, result.is_ok());
+        assert_eq!(vec!["static".to_string()], conf.get_error_page_locations());
+    }
+
+    #[test]
+    fn test_parse_error_page() {
+        let (status_list, page) = parse_error_page("404 @static").unwrap();
+        assert_eq!(vec![404], status_list);
+        assert_eq!(ErrorPage::Location("static".to_string()), page);
+
+        let (status_list, page) = parse_error_page("502,503 =Service maintenance").unwrap();
+        assert_eq!(vec![502, 503], status_list);
+        assert_eq!(ErrorPage::Body("Service maintenance".to_string()), page);
+
+        assert_eq!(
+            "Invalid error error page 200 @static is invalid",
+            parse_error_page("200 @static").err().unwrap().to_string()
+        );
+        assert_eq!(true, parse_error_page("404 static").is_err());
+        assert_eq!(true, parse_error_page("404 @").is_err());
+    }
+
+    #[test]
+    fn test_parse_error_template() {
+        let (status_list, template) =
+            parse_error_template("502,503 <h1>{{status}} {{request_id}}</h1>").unwrap();
+        assert_eq!(vec![502, 503], status_list);
+        assert_eq!("<h1>{{status}} {{request_id}}</h1>", template);
+
+        assert_eq!(
+            "Invalid error error template 302 <h1>Moved</h1> is invalid",
+            parse_error_template("302 <h1>Moved</h1>")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(true, parse_error_template("502").is_err());
+
+        assert_eq!(
+            "<h1>{{status}}</h1>",
+            load_error_template("<h1>{{status}}</h1>").unwrap()
+        );
+        assert_eq!(
+            true,
+            load_error_template("file:/pingap-not-exists/404.html")
+                .unwrap_err()
+                .to_string()
+                .starts_with("Io error No such file or directory")
+        );
     }
 
     #[test]
@@ -926,7 +1553,40 @@ mod tests {
             result.expect_err("").to_string()
         );
 
+        conf.addr = "unix:@pingap".to_string();
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error abstract socket is not supported(server:test)",
+            result.expect_err("").to_string()
+        );
+
+        conf.addr = "unix:/run/pingap.sock".to_string();
+        conf.unix_socket_mode = Some("0689".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error unix socket mode(0689) is invalid(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.unix_socket_mode = Some("0660".to_string());
+        conf.tls_certificate_dir = Some("~/certs".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls is not supported for unix socket(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.tls_certificate_dir = None;
+        conf.unix_socket_mode = None;
+
         conf.addr = "127.0.0.1:3001".to_string();
+        conf.max_connections = Some(100);
+        conf.max_connections_per_ip = Some(200);
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error max connections per ip(200) should not be greater than max connections(100)(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.max_connections_per_ip = Some(10);
+
         conf.locations = Some(vec!["lo1".to_string()]);
         let result = conf.validate("test", &location_names);
         assert_eq!(true, result.is_err());
@@ -936,6 +1596,28 @@ mod tests {
         );
 
         conf.locations = Some(vec!["lo".to_string()]);
+        conf.error_format = Some("xml".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error error format(xml) is invalid(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.error_format = Some("json".to_string());
+        conf.error_templates = Some(vec!["502".to_string()]);
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error error template 502 is invalid(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.error_templates = None;
+        conf.tls_auto = Some("acme".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls auto(acme) is not supported(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.tls_auto = None;
+
         conf.tls_key = Some("ab".to_string());
         let result = conf.validate("test", &location_names);
         assert_eq!(true, result.is_err());
@@ -948,6 +1630,52 @@ mod tests {
         conf.tls_cert = Some("YWJj".to_string());
         let result = conf.validate("test", &location_names);
         assert_eq!(true, result.is_ok());
+
+        conf.tls_certificates = Some(vec![TlsCertificateConf {
+            cert: "YWJj".to_string(),
+            key: "ab".to_string(),
+        }]);
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Base64 decode error Invalid padding",
+            result.expect_err("").to_string()
+        );
+
+        let mut other = conf.clone();
+        assert_eq!(true, conf.is_tls());
+        assert_eq!(false, conf.is_tls_changed(&other));
+        other.tls_certificates = None;
+        assert_eq!(true, conf.is_tls_changed(&other));
+
+        conf.tls_certificates = None;
+        conf.tls_client_verify = Some("any".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls client verify(any) is invalid(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.tls_client_verify = Some("required".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls client ca is required(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.tls_client_verify = None;
+
+        conf.tls_ticket_keys = Some(vec!["YWJj".to_string()]);
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls ticket key should be 80 bytes(server:test)",
+            result.expect_err("").to_string()
+        );
+        conf.tls_ticket_keys = None;
+
+        conf.tls_certificate_dir = Some("/pingap-not-exists".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls certificate dir(/pingap-not-exists) is not found(server:test)",
+            result.expect_err("").to_string()
+        );
     }
 
     #[test]
@@ -1037,6 +1765,47 @@ log_level = "info"
         );
     }
 
+    #[test]
+    fn test_stream_conf() {
+        let (domain, upstream) = parse_sni_upstream("*.Example.com  redis").unwrap();
+        assert_eq!("*.example.com", domain);
+        assert_eq!("redis", upstream);
+        assert_eq!(
+            "Invalid error sni upstream redis is invalid",
+            parse_sni_upstream("redis").unwrap_err().to_string()
+        );
+
+        let upstream_names = vec!["postgres".to_string()];
+        let mut conf = StreamConf {
+            addr: "127.0.0.1:5432".to_string(),
+            ..Default::default()
+        };
+        let result = conf.validate("pg", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream is required(stream:pg)",
+            result.expect_err("").to_string()
+        );
+
+        conf.sni_upstreams = Some(vec!["pg.example.com mysql".to_string()]);
+        let result = conf.validate("pg", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream(mysql) is not found(stream:pg)",
+            result.expect_err("").to_string()
+        );
+
+        conf.sni_upstreams = Some(vec!["pg.example.com postgres".to_string()]);
+        conf.tls_certificate_dir = Some("/pingap-not-exists".to_string());
+        let result = conf.validate("pg", &upstream_names);
+        assert_eq!(
+            "Invalid error tls certificate dir(/pingap-not-exists) is not found(stream:pg)",
+            result.expect_err("").to_string()
+        );
+
+        conf.tls_certificate_dir = None;
+        assert_eq!(false, conf.is_tls());
+        assert_eq!(true, conf.validate("pg", &upstream_names).is_ok());
+    }
+
     #[test]
     fn test_pingap_diff() {
         let toml_data = include_bytes!("../../conf/pingap.toml");
@@ -12,17 +12,65 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::PingapConf;
-use super::{ConfigStorage, Error, Result};
+use super::{
+    resolve_secret, resolve_secrets, ConfigSnapshot, ConfigStorage, ConfigVersion, Error,
+    PingapConf, Result, CATEGORIES,
+};
 use async_trait::async_trait;
-use etcd_client::{Client, ConnectOptions, GetOptions};
+use etcd_client::{Client, Compare, CompareOp, ConnectOptions, GetOptions, PutOptions, Txn, TxnOp};
 use humantime::parse_duration;
+use log::error;
+use std::time::Duration;
 use substring::Substring;
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
 
 pub struct EtcdStorage {
     path: String,
     addrs: Vec<String>,
     options: ConnectOptions,
+    // the client is created once and shared by all requests
+    client: OnceCell<Client>,
+}
+
+/// The lock of etcd, the lease is kept alive until it's unlocked,
+/// and it's revoked in background if the lock is dropped without unlock.
+pub struct EtcdLock {
+    client: Client,
+    lease_id: i64,
+    keep_alive: JoinHandle<()>,
+    released: bool,
+}
+
+impl EtcdLock {
+    /// Release the lock by revoking its lease.
+    pub async fn unlock(mut self) -> Result<()> {
+        self.keep_alive.abort();
+        self.released = true;
+        self.client
+            .lease_revoke(self.lease_id)
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        Ok(())
+    }
+}
+
+impl Drop for EtcdLock {
+    fn drop(&mut self) {
+        self.keep_alive.abort();
+        if self.released {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let mut client = self.client.clone();
+            let lease_id = self.lease_id;
+            handle.spawn(async move {
+                if let Err(e) = client.lease_revoke(lease_id).await {
+                    error!("Revoke etcd lease fail, lease: {lease_id}, error: {e}");
+                }
+            });
+        }
+    }
 }
 
 pub const ETCD_PROTOCOL: &str = "etcd://";
@@ -53,8 +101,8 @@ impl EtcdStorage {
         for item in query.split('&') {
             if let Some((key, value)) = item.split_once('=') {
                 match key {
-                    "user" => user = value.to_string(),
-                    "password" => password = value.to_string(),
+                    "user" => user = resolve_secret(value)?,
+                    "password" => password = resolve_secret(value)?,
                     "timeout" => {
                         if let Ok(d) = parse_duration(value) {
                             options = options.with_timeout(d);
@@ -76,13 +124,115 @@ impl EtcdStorage {
             addrs,
             options,
             path,
+            client: OnceCell::new(),
         })
     }
-    /// Connect to etcd server.
+    /// Connect to etcd server, the client is reused after it's connected.
     async fn connect(&self) -> Result<Client> {
-        Client::connect(&self.addrs, Some(self.options.clone()))
+        let client = self
+            .client
+            .get_or_try_init(|| async {
+                Client::connect(&self.addrs, Some(self.options.clone()))
+                    .await
+                    .map_err(|e| Error::Etcd { source: e })
+            })
+            .await?;
+        Ok(client.clone())
+    }
+    /// Get the key of data which is not config, e.g. acme certificates,
+    /// it's not under the config path, so it won't be loaded as config.
+    fn get_data_key(&self, key: &str) -> String {
+        format!("{}-data/{key}", self.path)
+    }
+    /// Get the data of key.
+    pub async fn get_data(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut c = self.connect().await?;
+        let resp = c
+            .get(self.get_data_key(key), None)
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        Ok(resp.kvs().first().map(|item| item.value().to_vec()))
+    }
+    /// Put the data of key, it will be removed after ttl(seconds) if ttl is set.
+    pub async fn put_data(&self, key: &str, value: Vec<u8>, ttl: Option<i64>) -> Result<()> {
+        let mut c = self.connect().await?;
+        let mut opts = None;
+        if let Some(ttl) = ttl {
+            let lease = c
+                .lease_grant(ttl, None)
+                .await
+                .map_err(|e| Error::Etcd { source: e })?;
+            opts = Some(PutOptions::new().with_lease(lease.id()));
+        }
+        c.put(self.get_data_key(key), value, opts)
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        Ok(())
+    }
+    /// Delete the data of key.
+    pub async fn delete_data(&self, key: &str) -> Result<()> {
+        let mut c = self.connect().await?;
+        c.delete(self.get_data_key(key), None)
             .await
-            .map_err(|e| Error::Etcd { source: e })
+            .map_err(|e| Error::Etcd { source: e })?;
+        Ok(())
+    }
+    /// Try to acquire the lock of key, the lock is bound to a lease with ttl(seconds),
+    /// so it will be released even if the node is crashed.
+    /// The lease is kept alive while the lock is held.
+    pub async fn try_lock(&self, key: &str, ttl: i64) -> Result<Option<EtcdLock>> {
+        let mut c = self.connect().await?;
+        let lease_id = c
+            .lease_grant(ttl, None)
+            .await
+            .map_err(|e| Error::Etcd { source: e })?
+            .id();
+        let key = self.get_data_key(key);
+        // put the key only if it doesn't exist
+        let txn = Txn::new()
+            .when(vec![Compare::create_revision(
+                key.clone(),
+                CompareOp::Equal,
+                0,
+            )])
+            .and_then(vec![TxnOp::put(
+                key,
+                lease_id.to_string(),
+                Some(PutOptions::new().with_lease(lease_id)),
+            )]);
+        let resp = c.txn(txn).await.map_err(|e| Error::Etcd { source: e })?;
+        if !resp.succeeded() {
+            c.lease_revoke(lease_id)
+                .await
+                .map_err(|e| Error::Etcd { source: e })?;
+            return Ok(None);
+        }
+        let (mut keeper, mut stream) = match c.lease_keep_alive(lease_id).await {
+            Ok(value) => value,
+            Err(e) => {
+                let _ = c.lease_revoke(lease_id).await;
+                return Err(Error::Etcd { source: e });
+            }
+        };
+        let interval = Duration::from_secs((ttl / 3).max(1) as u64);
+        let keep_alive = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let result = match keeper.keep_alive().await {
+                    Ok(()) => stream.message().await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    error!("Keep etcd lease alive fail, lease: {lease_id}, error: {e}");
+                }
+            }
+        });
+        Ok(Some(EtcdLock {
+            client: c,
+            lease_id,
+            keep_alive,
+            released: false,
+        }))
     }
 }
 
@@ -93,8 +243,11 @@ impl ConfigStorage for EtcdStorage {
         let mut c = self.connect().await?;
         let mut opts = GetOptions::new();
         opts = opts.with_prefix();
+        // the config keys are under the path,
+        // the trailing slash avoids matching the data keys
+        let prefix = format!("{}/", self.path);
         let arr = c
-            .get(self.path.as_bytes(), Some(opts))
+            .get(prefix.as_bytes(), Some(opts))
             .await
             .map_err(|e| Error::Etcd { source: e })?
             .take_kvs();
@@ -107,24 +260,86 @@ impl ConfigStorage for EtcdStorage {
     }
     /// Save config to etcd by category.
     async fn save_config(&self, conf: &PingapConf, category: &str) -> Result<()> {
-        let filepath = self.path.clone();
-        conf.validate()?;
-        let (path, toml_value) = conf.get_toml(category)?;
-        let key = format!("{filepath}{path}");
+        self.save_config_with_versions(conf, &[category], &[]).await
+    }
+    /// Save the config of categories and the snapshots in one transaction.
+    async fn save_config_with_versions(
+        &self,
+        conf: &PingapConf,
+        categories: &[&str],
+        snapshots: &[ConfigSnapshot],
+    ) -> Result<()> {
+        // the secret references are validated after they are resolved
+        resolve_secrets(conf)?.validate()?;
+        let mut ops = vec![];
+        for category in categories {
+            let (path, toml_value) = conf.get_toml(category)?;
+            ops.push(TxnOp::put(format!("{}{path}", self.path), toml_value, None));
+        }
+        for snapshot in snapshots {
+            let key = self.get_data_key(&format!("versions/{}", snapshot.version.version));
+            ops.push(TxnOp::put(key, snapshot.to_vec()?, None));
+        }
+        let mut c = self.connect().await?;
+        c.txn(Txn::new().and_then(ops))
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        Ok(())
+    }
+    /// Remove the snapshots of versions in one transaction.
+    async fn remove_versions(&self, versions: &[String]) -> Result<()> {
+        let ops: Vec<TxnOp> = versions
+            .iter()
+            .map(|version| TxnOp::delete(self.get_data_key(&format!("versions/{version}")), None))
+            .collect();
         let mut c = self.connect().await?;
-        c.put(key, toml_value, None)
+        c.txn(Txn::new().and_then(ops))
             .await
             .map_err(|e| Error::Etcd { source: e })?;
         Ok(())
     }
+    /// List the versions of config, the revision is the create revision of snapshot.
+    async fn list_versions(&self) -> Result<Vec<ConfigVersion>> {
+        let mut c = self.connect().await?;
+        let prefix = self.get_data_key("versions/");
+        let resp = c
+            .get(prefix.as_bytes(), Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        let mut versions = vec![];
+        for item in resp.kvs() {
+            let mut version = ConfigSnapshot::from_slice(item.value())?.version;
+            version.revision = Some(item.create_revision());
+            versions.push(version);
+        }
+        versions.sort_by(|a, b| b.version.cmp(&a.version));
+        Ok(versions)
+    }
+    /// Get the snapshot of version.
+    async fn get_version(&self, version: &str) -> Result<ConfigSnapshot> {
+        let mut c = self.connect().await?;
+        let resp = c
+            .get(self.get_data_key(&format!("versions/{version}")), None)
+            .await
+            .map_err(|e| Error::Etcd { source: e })?;
+        if let Some(item) = resp.kvs().first() {
+            let mut snapshot = ConfigSnapshot::from_slice(item.value())?;
+            snapshot.version.revision = Some(item.create_revision());
+            Ok(snapshot)
+        } else {
+            Err(Error::Invalid {
+                message: format!("config version({version}) is not found"),
+            })
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::EtcdStorage;
     use crate::config::{
-        ConfigStorage, PingapConf, CATEGORY_BASIC, CATEGORY_LOCATION, CATEGORY_PLUGIN,
-        CATEGORY_SERVER, CATEGORY_UPSTREAM,
+        ConfigSnapshot, ConfigStorage, PingapConf, CATEGORIES, CATEGORY_BASIC, CATEGORY_LOCATION,
+        CATEGORY_PLUGIN, CATEGORY_SERVER, CATEGORY_UPSTREAM,
     };
     use nanoid::nanoid;
     use pretty_assertions::assert_eq;
@@ -147,5 +362,69 @@ mod tests {
 
         let current_conf = storage.load_config(false).await.unwrap();
         assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+
+        let mut snapshot = ConfigSnapshot {
+            data: toml::to_string_pretty(&conf).unwrap(),
+            ..Default::default()
+        };
+        snapshot.version.version = "0000000000001".to_string();
+        storage
+            .save_config_with_versions(&conf, &CATEGORIES, &[snapshot])
+            .await
+            .unwrap();
+        let versions = storage.list_versions().await.unwrap();
+        assert_eq!(1, versions.len());
+        assert_eq!(true, versions[0].revision.is_some());
+        let snapshot = storage.get_version("0000000000001").await.unwrap();
+        storage
+            .save_config_with_versions(&snapshot.to_conf().unwrap(), &CATEGORIES, &[])
+            .await
+            .unwrap();
+        let current_conf = storage.load_config(false).await.unwrap();
+        assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+        storage
+            .remove_versions(&["0000000000001".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(true, storage.list_versions().await.unwrap().is_empty());
+
+        storage
+            .put_data("acme/store", b"pingap".to_vec(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            b"pingap".to_vec(),
+            storage.get_data("acme/store").await.unwrap().unwrap()
+        );
+        // the data is not loaded as config
+        let current_conf = storage.load_config(false).await.unwrap();
+        assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+        storage.delete_data("acme/store").await.unwrap();
+        assert_eq!(
+            true,
+            storage.get_data("acme/store").await.unwrap().is_none()
+        );
+
+        let lock = storage.try_lock("acme/locks/pingap", 3).await.unwrap();
+        assert_eq!(true, lock.is_some());
+        // the lease is kept alive after its ttl
+        tokio::time::sleep(std::time::Duration::from_secs(4)).await;
+        assert_eq!(
+            true,
+            storage
+                .try_lock("acme/locks/pingap", 60)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        lock.unwrap().unlock().await.unwrap();
+        let lock = storage.try_lock("acme/locks/pingap", 60).await.unwrap();
+        assert_eq!(true, lock.is_some());
+        // the lease is revoked after the lock is dropped
+        drop(lock);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let lock = storage.try_lock("acme/locks/pingap", 60).await.unwrap();
+        assert_eq!(true, lock.is_some());
+        lock.unwrap().unlock().await.unwrap();
     }
 }
@@ -12,14 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{ConfigStorage, Error, PingapConf, Result};
+use super::include::{exclude_included, load_files, merge_files, with_include};
+use super::{
+    is_valid_version, resolve_secrets, ConfigSnapshot, ConfigStorage, ConfigVersion, Error,
+    PingapConf, Result, CATEGORIES,
+};
 use crate::util;
 use async_trait::async_trait;
 use futures_util::TryFutureExt;
-use glob::glob;
-use log::debug;
+use log::error;
 use std::path::Path;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 
 pub struct FileStorage {
     path: String,
@@ -57,60 +61,210 @@ impl ConfigStorage for FileStorage {
                 .await?;
         }
 
-        let mut data = vec![];
-        if dir.is_dir() {
-            for entry in glob(&format!("{filepath}/**/*.toml")).map_err(|e| Error::Pattern {
-                source: e,
-                path: filepath,
-            })? {
-                let f = entry.map_err(|e| Error::Glob { source: e })?;
-                let mut buf = fs::read(&f).await.map_err(|e| Error::Io {
-                    source: e,
-                    file: f.to_string_lossy().to_string(),
-                })?;
-                debug!("Load config from: {:?}", f.file_name());
-                data.append(&mut buf);
-                data.push(0x0a);
+        let files = load_files(dir).await?;
+        merge_files(&files)
+    }
+    /// Save config to file by category.
+    async fn save_config(&self, conf: &PingapConf, category: &str) -> Result<()> {
+        self.save_config_with_versions(conf, &[category], &[]).await
+    }
+    /// Save config files of categories with the snapshots, the snapshots are written
+    /// to history dir(`{path}-history`) first, and the config files are written
+    /// to temp files and renamed after all files are written.
+    async fn save_config_with_versions(
+        &self,
+        conf: &PingapConf,
+        categories: &[&str],
+        snapshots: &[ConfigSnapshot],
+    ) -> Result<()> {
+        // the secret references are validated after they are resolved
+        resolve_secrets(conf)?.validate()?;
+        let files = self.get_config_files(conf, categories).await?;
+        let mut version_files = vec![];
+        for snapshot in snapshots {
+            match self.write_version(snapshot).await {
+                Ok(file) => version_files.push(file),
+                Err(e) => {
+                    remove_files(&version_files).await;
+                    return Err(e);
+                }
             }
-        } else {
-            let mut buf = fs::read(&filepath).await.map_err(|e| Error::Io {
+        }
+        if let Err(e) = write_files(files).await {
+            remove_files(&version_files).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+    /// Remove the snapshots of versions from history dir.
+    async fn remove_versions(&self, versions: &[String]) -> Result<()> {
+        let dir = self.get_history_dir();
+        for version in versions {
+            let file = format!("{dir}/{version}.json");
+            fs::remove_file(&file)
+                .await
+                .map_err(|e| Error::Io { source: e, file })?;
+        }
+        Ok(())
+    }
+    /// List the versions of config, the newest is first.
+    async fn list_versions(&self) -> Result<Vec<ConfigVersion>> {
+        let mut versions = vec![];
+        for version in self.get_version_files().await? {
+            versions.push(self.get_version(&version).await?.version);
+        }
+        versions.sort_by(|a, b| b.version.cmp(&a.version));
+        Ok(versions)
+    }
+    /// Get the snapshot of version from history dir.
+    async fn get_version(&self, version: &str) -> Result<ConfigSnapshot> {
+        let file = format!("{}/{version}.json", self.get_history_dir());
+        let buf = fs::read(&file)
+            .await
+            .map_err(|e| Error::Io { source: e, file })?;
+        ConfigSnapshot::from_slice(&buf)
+    }
+}
+
+impl FileStorage {
+    fn get_history_dir(&self) -> String {
+        format!("{}-history", self.path)
+    }
+    /// Write the snapshot to history dir, the snapshot may contain secrets,
+    /// so the dir and file are only accessible by owner.
+    async fn write_version(&self, snapshot: &ConfigSnapshot) -> Result<String> {
+        let dir = self.get_history_dir();
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .await
+            .map_err(|e| Error::Io {
+                source: e,
+                file: dir.clone(),
+            })?;
+        let file = format!("{dir}/{}.json", snapshot.version.version);
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&file)
+            .await
+            .map_err(|e| Error::Io {
                 source: e,
-                file: filepath,
+                file: file.clone(),
             })?;
-            data.append(&mut buf);
+        f.write_all(&snapshot.to_vec()?)
+            .await
+            .map_err(|e| Error::Io {
+                source: e,
+                file: file.clone(),
+            })?;
+        Ok(file)
+    }
+    /// Get the versions of history dir.
+    async fn get_version_files(&self) -> Result<Vec<String>> {
+        let dir = self.get_history_dir();
+        let mut versions = vec![];
+        if !Path::new(&dir).exists() {
+            return Ok(versions);
         }
-        PingapConf::try_from(data.as_slice())
+        let mut entries = fs::read_dir(&dir).await.map_err(|e| Error::Io {
+            source: e,
+            file: dir.clone(),
+        })?;
+        while let Some(entry) = entries.next_entry().await.map_err(|e| Error::Io {
+            source: e,
+            file: dir.clone(),
+        })? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(version) = name.strip_suffix(".json") {
+                if is_valid_version(version) {
+                    versions.push(version.to_string());
+                }
+            }
+        }
+        Ok(versions)
     }
-    /// Save config to file by category.
-    async fn save_config(&self, conf: &PingapConf, category: &str) -> Result<()> {
+    /// Get the config files and content of categories,
+    /// the items of included files are kept in their own files,
+    /// and the include directives of the config files are kept.
+    async fn get_config_files(
+        &self,
+        conf: &PingapConf,
+        categories: &[&str],
+    ) -> Result<Vec<(String, String)>> {
         let filepath = self.path.clone();
-        conf.validate()?;
-        if Path::new(&filepath).is_file() {
+        let path = Path::new(&filepath);
+        let files = if fs::try_exists(path).await.unwrap_or_default() {
+            load_files(path).await?
+        } else {
+            vec![]
+        };
+        let conf = exclude_included(conf, &files)?;
+        let is_file = fs::metadata(path)
+            .await
+            .map(|item| item.is_file())
+            .unwrap_or_default();
+        if is_file {
             let ping_conf = toml::to_string_pretty(&conf).map_err(|e| Error::Ser { source: e })?;
-            return fs::write(&filepath, ping_conf)
-                .await
-                .map_err(|e| Error::Io {
-                    source: e,
-                    file: filepath,
-                });
+            let ping_conf = if let Some(file) = files.first() {
+                with_include(&files, &file.file, ping_conf)?
+            } else {
+                ping_conf
+            };
+            return Ok(vec![(filepath, ping_conf)]);
+        }
+        let mut result = vec![];
+        for category in categories {
+            let (path, toml_value) = conf.get_toml(category)?;
+            let file = format!("{filepath}{path}");
+            // the loaded files are canonicalized
+            let toml_value = match fs::canonicalize(&file).await {
+                Ok(canonical) => with_include(&files, &canonical, toml_value)?,
+                Err(_) => toml_value,
+            };
+            result.push((file, toml_value));
         }
+        Ok(result)
+    }
+}
+
+/// Remove the files, it's used to clean up when the save fails.
+async fn remove_files(files: &[String]) {
+    for file in files {
+        if let Err(e) = fs::remove_file(file).await {
+            error!("Remove file fail, file: {file}, error: {e}");
+        }
+    }
+}
 
-        let (path, toml_value) = conf.get_toml(category)?;
-        let filepath = format!("{filepath}{path}");
-        fs::write(&filepath, toml_value)
+/// Write the files by temp files and rename, so the file is not half written.
+async fn write_files(files: Vec<(String, String)>) -> Result<()> {
+    let mut tmp_files = vec![];
+    for (file, data) in files {
+        let tmp_file = format!("{file}.tmp");
+        fs::write(&tmp_file, data).await.map_err(|e| Error::Io {
+            source: e,
+            file: tmp_file.clone(),
+        })?;
+        tmp_files.push((tmp_file, file));
+    }
+    for (tmp_file, file) in tmp_files {
+        fs::rename(&tmp_file, &file)
             .await
-            .map_err(|e| Error::Io {
-                source: e,
-                file: filepath,
-            })
+            .map_err(|e| Error::Io { source: e, file })?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::FileStorage;
     use crate::config::{
-        ConfigStorage, PingapConf, CATEGORY_BASIC, CATEGORY_LOCATION, CATEGORY_PLUGIN,
+        diff_config_versions, list_config_versions, rollback_config, save_config, ConfigStorage,
+        PingapConf, CATEGORIES, CATEGORY_BASIC, CATEGORY_LOCATION, CATEGORY_PLUGIN,
         CATEGORY_SERVER, CATEGORY_UPSTREAM,
     };
     use nanoid::nanoid;
@@ -142,4 +296,102 @@ mod tests {
         let current_conf = storage.load_config(false).await.unwrap();
         assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
     }
+    #[tokio::test]
+    async fn test_save_config_with_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf_dir = dir.path().join("conf");
+        let shared = dir.path().join("shared");
+        tokio::fs::create_dir(&conf_dir).await.unwrap();
+        tokio::fs::create_dir(&shared).await.unwrap();
+        tokio::fs::write(
+            conf_dir.join("upstreams.toml"),
+            r#"include = ["../shared/*.toml"]
+
+[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+"#,
+        )
+        .await
+        .unwrap();
+        tokio::fs::write(
+            shared.join("upstreams.toml"),
+            r#"[upstreams.diving]
+addrs = ["127.0.0.1:5001"]
+"#,
+        )
+        .await
+        .unwrap();
+        let path = conf_dir.to_string_lossy().to_string();
+        let storage = FileStorage::new(&path).unwrap();
+        let mut conf = storage.load_config(false).await.unwrap();
+        assert_eq!(true, conf.upstreams.contains_key("diving"));
+
+        conf.upstreams.get_mut("charts").unwrap().addrs = vec!["127.0.0.1:5002".to_string()];
+        storage.save_config(&conf, CATEGORY_UPSTREAM).await.unwrap();
+        // the include directive of category file is kept
+        let content = tokio::fs::read_to_string(conf_dir.join("upstreams.toml"))
+            .await
+            .unwrap();
+        assert_eq!(
+            true,
+            content.starts_with(r#"include = ["../shared/*.toml"]"#)
+        );
+        assert_eq!(false, content.contains("diving"));
+
+        let conf = storage.load_config(false).await.unwrap();
+        assert_eq!(true, conf.upstreams.contains_key("diving"));
+        assert_eq!(
+            vec!["127.0.0.1:5002".to_string()],
+            conf.upstreams.get("charts").unwrap().addrs
+        );
+    }
+    #[tokio::test]
+    async fn test_config_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("conf").to_string_lossy().to_string();
+        tokio::fs::create_dir(&path).await.unwrap();
+        let storage = FileStorage::new(&path).unwrap();
+        let toml_data = include_bytes!("../../conf/pingap.toml");
+        let conf = PingapConf::try_from(toml_data.to_vec().as_slice()).unwrap();
+        storage
+            .save_config_with_versions(&conf, &CATEGORIES, &[])
+            .await
+            .unwrap();
+        assert_eq!(true, storage.list_versions().await.unwrap().is_empty());
+
+        let mut new_conf = conf.clone();
+        new_conf.basic.name = Some("pingap-test".to_string());
+        save_config(&path, &new_conf, CATEGORY_BASIC, "pingap")
+            .await
+            .unwrap();
+        // the initial version is saved before the first change
+        let versions = list_config_versions(&path).await.unwrap();
+        assert_eq!(2, versions.len());
+        assert_eq!("pingap", versions[0].author);
+        assert_eq!(CATEGORY_BASIC, versions[0].category);
+        assert_eq!(false, versions[0].diff.is_empty());
+        assert_eq!(Some("initial".to_string()), versions[1].remark);
+
+        let result = diff_config_versions(&path, &versions[1].version, "current")
+            .await
+            .unwrap();
+        assert_eq!(vec![CATEGORY_BASIC.to_string()], result.category_list);
+
+        let version = rollback_config(&path, &versions[1].version, "pingap")
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(format!("rollback to {}", versions[1].version)),
+            version.remark
+        );
+        let current_conf = storage.load_config(false).await.unwrap();
+        assert_eq!(current_conf.hash().unwrap(), conf.hash().unwrap());
+        assert_eq!(3, list_config_versions(&path).await.unwrap().len());
+
+        let result = rollback_config(&path, "../abc", "pingap").await;
+        assert_eq!(
+            "Invalid error config version(../abc) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
 }
@@ -0,0 +1,307 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::secret::{seal_secrets, unseal_secrets};
+use super::{redact_secrets, ConfigStorage, Error, PingapConf, Result};
+use crate::util;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// the max count of config versions, the oldest versions are removed
+pub const MAX_CONFIG_VERSIONS: usize = 100;
+// the max age of config versions, the older versions are removed
+pub const MAX_CONFIG_VERSION_AGE: Duration = Duration::from_secs(90 * 24 * 3600);
+pub const CURRENT_CONFIG_VERSION: &str = "current";
+
+static LAST_VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// The version of config, it's created for every save of config.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ConfigVersion {
+    pub version: String,
+    /// The revision of etcd when the version is saved.
+    pub revision: Option<i64>,
+    pub author: String,
+    pub category: String,
+    pub remark: Option<String>,
+    pub created_at: u64,
+    pub diff: Vec<String>,
+}
+
+/// The snapshot of config, the data is the toml of whole config whose
+/// secrets are redacted, and the secrets are kept in `secrets`,
+/// they are encrypted by master key if it's set.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ConfigSnapshot {
+    #[serde(flatten)]
+    pub version: ConfigVersion,
+    pub data: String,
+    #[serde(default)]
+    pub secrets: String,
+}
+
+impl ConfigSnapshot {
+    fn new(version: ConfigVersion, conf: &PingapConf) -> Result<Self> {
+        let data =
+            toml::to_string_pretty(&redact_secrets(conf)?).map_err(|e| Error::Ser { source: e })?;
+        Ok(Self {
+            version,
+            data,
+            secrets: seal_secrets(conf)?,
+        })
+    }
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(data).map_err(|e| Error::SerdeJson { source: e })
+    }
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| Error::SerdeJson { source: e })
+    }
+    /// Get the config of snapshot, the secrets are restored from the snapshot itself.
+    pub fn to_conf(&self) -> Result<PingapConf> {
+        let conf = PingapConf::try_from(self.data.as_bytes())?;
+        if self.secrets.is_empty() {
+            return Ok(conf);
+        }
+        unseal_secrets(&conf, &self.secrets)
+    }
+}
+
+/// The diff result of two config versions.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ConfigVersionDiff {
+    pub category_list: Vec<String>,
+    pub diff: Vec<String>,
+}
+
+/// Create a new version id, it's the timestamp(ms) and increases monotonically.
+fn new_version_id() -> String {
+    let now = util::now().as_millis() as u64;
+    let mut value = now;
+    let _ = LAST_VERSION.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+        value = now.max(last + 1);
+        Some(value)
+    });
+    format!("{value:013}")
+}
+
+/// Get the diff of two config, the secrets are redacted.
+pub fn diff_config(current: &PingapConf, conf: &PingapConf) -> Result<ConfigVersionDiff> {
+    let (category_list, diff) = redact_secrets(current)?.diff(&redact_secrets(conf)?);
+    Ok(ConfigVersionDiff {
+        category_list,
+        diff,
+    })
+}
+
+/// Check the version is valid, it's used as file name or key of etcd.
+pub fn is_valid_version(version: &str) -> bool {
+    !version.is_empty() && version.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Get the versions which should be removed, they are more than the max count
+/// or older than the max age. The versions are sorted by newest first,
+/// and the newest version is always kept.
+fn get_expired_versions(versions: &[ConfigVersion], now: u64) -> Vec<String> {
+    let expired_at = now.saturating_sub(MAX_CONFIG_VERSION_AGE.as_secs());
+    versions
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(index, item)| *index >= MAX_CONFIG_VERSIONS || item.created_at < expired_at)
+        .map(|(_, item)| item.version.clone())
+        .collect()
+}
+
+/// Remove the versions which are more than the max count or older than the max age.
+async fn prune_versions<S: ConfigStorage + Sync>(storage: &S) -> Result<()> {
+    let versions = storage.list_versions().await?;
+    let expired = get_expired_versions(&versions, util::now().as_secs());
+    if !expired.is_empty() {
+        storage.remove_versions(&expired).await?;
+    }
+    Ok(())
+}
+
+/// The params for saving a version of config.
+pub(super) struct SaveVersionParams<'a> {
+    pub author: &'a str,
+    pub category: &'a str,
+    /// The categories of config to save.
+    pub categories: &'a [&'a str],
+    pub remark: Option<String>,
+}
+
+/// Save the config with the snapshot of it, the current config is saved
+/// as the initial version if there is no version, so it can be rolled back.
+/// The config fails to save if the snapshot fails, and the expired versions
+/// are removed after the config is saved.
+pub(super) async fn save_version<S: ConfigStorage + Sync>(
+    storage: &S,
+    current: &PingapConf,
+    conf: &PingapConf,
+    params: SaveVersionParams<'_>,
+) -> Result<ConfigVersion> {
+    let mut snapshots = vec![];
+    if storage.list_versions().await?.is_empty() {
+        let version = ConfigVersion {
+            version: new_version_id(),
+            remark: Some("initial".to_string()),
+            created_at: util::now().as_secs(),
+            ..Default::default()
+        };
+        snapshots.push(ConfigSnapshot::new(version, current)?);
+    }
+    let version = ConfigVersion {
+        version: new_version_id(),
+        author: params.author.to_string(),
+        category: params.category.to_string(),
+        remark: params.remark,
+        created_at: util::now().as_secs(),
+        diff: diff_config(current, conf)?.diff,
+        ..Default::default()
+    };
+    snapshots.push(ConfigSnapshot::new(version.clone(), conf)?);
+    storage
+        .save_config_with_versions(conf, params.categories, &snapshots)
+        .await?;
+    // the config is saved, so the prune error is only logged
+    if let Err(e) = prune_versions(storage).await {
+        error!("Prune config versions fail, error: {e}");
+    }
+    Ok(version)
+}
+
+/// Get the config of version, the current config of storage is returned for `current`.
+pub(super) async fn get_version_config<S: ConfigStorage + Sync>(
+    storage: &S,
+    version: &str,
+) -> Result<PingapConf> {
+    if version == CURRENT_CONFIG_VERSION {
+        return storage.load_config(true).await;
+    }
+    if !is_valid_version(version) {
+        return Err(Error::Invalid {
+            message: format!("config version({version}) is invalid"),
+        });
+    }
+    storage.get_version(version).await?.to_conf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        diff_config, get_expired_versions, is_valid_version, new_version_id, ConfigSnapshot,
+        ConfigVersion, MAX_CONFIG_VERSIONS, MAX_CONFIG_VERSION_AGE,
+    };
+    use crate::config::PingapConf;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_config_version() {
+        let first = new_version_id();
+        let second = new_version_id();
+        assert_eq!(13, first.len());
+        assert_eq!(true, second > first);
+        assert_eq!(true, is_valid_version(&first));
+        assert_eq!(false, is_valid_version("../pingap"));
+        assert_eq!(false, is_valid_version(""));
+
+        let toml_data = include_bytes!("../../conf/pingap.toml");
+        let conf = PingapConf::try_from(toml_data.to_vec().as_slice()).unwrap();
+        let version = ConfigVersion {
+            version: first,
+            author: "pingap".to_string(),
+            category: "server".to_string(),
+            created_at: 1,
+            ..Default::default()
+        };
+        let snapshot = ConfigSnapshot::new(version.clone(), &conf).unwrap();
+        let snapshot = ConfigSnapshot::from_slice(&snapshot.to_vec().unwrap()).unwrap();
+        assert_eq!(version, snapshot.version);
+        assert_eq!(
+            conf.hash().unwrap(),
+            snapshot.to_conf().unwrap().hash().unwrap()
+        );
+
+        let mut new_conf = conf.clone();
+        new_conf.basic.name = Some("pingap-test".to_string());
+        let result = diff_config(&conf, &new_conf).unwrap();
+        assert_eq!(vec!["basic".to_string()], result.category_list);
+        assert_eq!(true, !result.diff.is_empty());
+    }
+
+    #[test]
+    fn test_config_snapshot_secrets() {
+        let conf = PingapConf::try_from(
+            r#"
+[basic]
+webhook = "https://pingap.io/webhook?token=123"
+
+[plugins.jwt]
+category = "jwt"
+secret = "${env:PINGAP_JWT_SECRET}"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let snapshot = ConfigSnapshot::new(ConfigVersion::default(), &conf).unwrap();
+        // the secrets are not kept in data
+        assert_eq!(false, snapshot.data.contains("token=123"));
+        assert_eq!(false, snapshot.secrets.is_empty());
+        // the secrets are restored from snapshot
+        let snapshot = ConfigSnapshot::from_slice(&snapshot.to_vec().unwrap()).unwrap();
+        let restored = snapshot.to_conf().unwrap();
+        assert_eq!(conf.hash().unwrap(), restored.hash().unwrap());
+        assert_eq!(
+            "https://pingap.io/webhook?token=123",
+            restored.basic.webhook.unwrap()
+        );
+        assert_eq!(
+            "${env:PINGAP_JWT_SECRET}",
+            restored.plugins["jwt"]["secret"].as_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_expired_versions() {
+        let now = MAX_CONFIG_VERSION_AGE.as_secs() * 2;
+        let new_versions = |count: usize, created_at: u64| -> Vec<ConfigVersion> {
+            (0..count)
+                .map(|index| ConfigVersion {
+                    version: format!("{:013}", count - index),
+                    created_at,
+                    ..Default::default()
+                })
+                .collect()
+        };
+
+        let versions = new_versions(MAX_CONFIG_VERSIONS + 2, now);
+        assert_eq!(
+            vec!["0000000000002".to_string(), "0000000000001".to_string()],
+            get_expired_versions(&versions, now)
+        );
+
+        // the newest version is kept even if it's expired
+        let versions = new_versions(3, 1);
+        assert_eq!(
+            vec!["0000000000002".to_string(), "0000000000001".to_string()],
+            get_expired_versions(&versions, now)
+        );
+
+        let versions = new_versions(3, now - 10);
+        assert_eq!(true, get_expired_versions(&versions, now).is_empty());
+    }
+}
@@ -0,0 +1,580 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, PingapConf, Result};
+use crate::util;
+use futures::future::BoxFuture;
+use glob::glob;
+use log::debug;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use toml::{map::Map, Value};
+
+pub const INCLUDE_KEY: &str = "include";
+
+// the sections of config, other top level keys are ignored
+const SECTIONS: [&str; 6] = [
+    "basic",
+    "upstreams",
+    "locations",
+    "servers",
+    "plugins",
+    "streams",
+];
+
+/// The config file which is loaded directly or by include.
+pub(super) struct ConfigFile {
+    pub file: PathBuf,
+    pub content: String,
+    pub includes: Vec<String>,
+    pub table: Map<String, Value>,
+    pub included: bool,
+}
+
+impl ConfigFile {
+    fn location(&self, line: Option<usize>) -> String {
+        let file = self.file.to_string_lossy();
+        if let Some(line) = line {
+            format!("{file}:{line}")
+        } else {
+            file.to_string()
+        }
+    }
+    fn get_item(&self, section: &str, name: &str) -> Option<&Value> {
+        self.table.get(section)?.as_table()?.get(name)
+    }
+}
+
+/// Get the line number of byte offset.
+fn get_line(content: &str, offset: usize) -> usize {
+    let offset = offset.min(content.len());
+    content.as_bytes()[..offset]
+        .iter()
+        .filter(|c| **c == b'\n')
+        .count()
+        + 1
+}
+
+/// Find the line of key in the section, e.g. `[servers.test]` for servers.test,
+/// or `addr = "..."` in `[basic]`.
+fn find_key_line(content: &str, section: &str, name: &str) -> Option<usize> {
+    let header = format!("{section}.{name}");
+    let mut current = "".to_string();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            current = line
+                .trim_matches(|c| c == '[' || c == ']')
+                .replace(['"', '\'', ' '], "");
+            if current == header {
+                return Some(index + 1);
+            }
+            continue;
+        }
+        if current != section {
+            continue;
+        }
+        let key = line
+            .split('=')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .replace(['"', '\''], "");
+        if key == name || key.starts_with(&format!("{name}.")) {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
+/// Convert the table to config, the error of toml is returned.
+pub(super) fn convert_table(table: &Map<String, Value>) -> Result<PingapConf> {
+    let value = toml::to_string(table).map_err(|e| Error::Ser { source: e })?;
+    PingapConf::try_from(value.as_bytes())
+}
+
+/// Convert the config to table, the values are normalized by serialization.
+pub(super) fn convert_conf(conf: &PingapConf) -> Result<Map<String, Value>> {
+    let value = toml::to_string(conf).map_err(|e| Error::Ser { source: e })?;
+    toml::from_str(&value).map_err(|e| Error::De { source: e })
+}
+
+/// Resolve the include pattern, the relative path is based on
+/// the dir of the file which includes it.
+fn resolve_include(dir: &Path, pattern: &str) -> String {
+    if pattern.starts_with('~') || Path::new(pattern).is_absolute() {
+        util::resolve_path(pattern)
+    } else {
+        dir.join(pattern).to_string_lossy().to_string()
+    }
+}
+
+struct Loader {
+    root_dir: Option<PathBuf>,
+    stack: Vec<PathBuf>,
+    files: Vec<ConfigFile>,
+}
+
+impl Loader {
+    /// The file is included if it's not in the config dir,
+    /// or it's not the config file.
+    fn is_included(&self, file: &Path) -> bool {
+        if let Some(dir) = &self.root_dir {
+            !file.starts_with(dir)
+        } else {
+            !self.stack.is_empty()
+        }
+    }
+    /// Parse the file and check every item of it, so the error
+    /// contains the file and line.
+    fn parse(&self, file: &Path, content: &str) -> Result<(Map<String, Value>, Vec<String>)> {
+        let location = |line: Option<usize>| {
+            let file = file.to_string_lossy();
+            if let Some(line) = line {
+                format!("{file}:{line}")
+            } else {
+                file.to_string()
+            }
+        };
+        let mut table: Map<String, Value> = toml::from_str(content).map_err(|e| Error::Parse {
+            location: location(e.span().map(|span| get_line(content, span.start))),
+            message: e.message().to_string(),
+        })?;
+        let mut includes = vec![];
+        if let Some(value) = table.remove(INCLUDE_KEY) {
+            let items = value.as_array().cloned().unwrap_or_default();
+            for item in items.iter() {
+                if let Some(item) = item.as_str() {
+                    includes.push(item.to_string());
+                }
+            }
+            if !value.is_array() || includes.len() != items.len() {
+                return Err(Error::Parse {
+                    location: location(find_key_line(content, "", INCLUDE_KEY)),
+                    message: "include should be an array of string".to_string(),
+                });
+            }
+        }
+        for (section, value) in table.iter() {
+            if !SECTIONS.contains(&section.as_str()) {
+                continue;
+            }
+            let items = if let Some(items) = value.as_table() {
+                items
+            } else {
+                return Err(Error::Parse {
+                    location: location(find_key_line(content, "", section)),
+                    message: format!("{section} should be a table"),
+                });
+            };
+            for (name, item) in items.iter() {
+                let mut m = Map::new();
+                m.insert(name.to_string(), item.clone());
+                let mut data = Map::new();
+                data.insert(section.to_string(), Value::Table(m));
+                if let Err(e) = convert_table(&data) {
+                    let message = match e {
+                        Error::De { source } => source.message().to_string(),
+                        _ => e.to_string(),
+                    };
+                    return Err(Error::Parse {
+                        location: location(find_key_line(content, section, name)),
+                        message: format!("{message}({section}.{name})"),
+                    });
+                }
+            }
+        }
+        Ok((table, includes))
+    }
+    /// Load the file and the files included by it, it's boxed for recursion.
+    fn load<'a>(&'a mut self, file: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { self.load_file(file).await })
+    }
+    async fn load_file(&mut self, file: &Path) -> Result<()> {
+        let file = tokio::fs::canonicalize(file).await.map_err(|e| Error::Io {
+            source: e,
+            file: file.to_string_lossy().to_string(),
+        })?;
+        if self.stack.contains(&file) {
+            let mut files: Vec<String> = self
+                .stack
+                .iter()
+                .map(|item| item.to_string_lossy().to_string())
+                .collect();
+            files.push(file.to_string_lossy().to_string());
+            return Err(Error::Invalid {
+                message: format!("include cycle is found, {}", files.join(" -> ")),
+            });
+        }
+        // the file is included by more than one file
+        if self.files.iter().any(|item| item.file == file) {
+            return Ok(());
+        }
+        let content = tokio::fs::read_to_string(&file)
+            .await
+            .map_err(|e| Error::Io {
+                source: e,
+                file: file.to_string_lossy().to_string(),
+            })?;
+        debug!("Load config from: {file:?}");
+        let (table, includes) = self.parse(&file, &content)?;
+        let dir = file
+            .parent()
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_default();
+        let included = self.is_included(&file);
+        self.files.push(ConfigFile {
+            file: file.clone(),
+            content,
+            includes: includes.clone(),
+            table,
+            included,
+        });
+
+        self.stack.push(file.clone());
+        for pattern in includes.iter() {
+            let path = resolve_include(&dir, pattern);
+            let mut matched = vec![];
+            for entry in glob(&path).map_err(|e| Error::Pattern {
+                source: e,
+                path: path.clone(),
+            })? {
+                matched.push(entry.map_err(|e| Error::Glob { source: e })?);
+            }
+            if matched.is_empty() && !pattern.contains(['*', '?', '[']) {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "include file({path}) is not found, {}",
+                        file.to_string_lossy()
+                    ),
+                });
+            }
+            matched.sort();
+            for item in matched.iter() {
+                self.load(item).await?;
+            }
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// Load the config files of path and the files included by them.
+/// All toml files of dir are loaded if the path is a dir.
+pub(super) async fn load_files(path: &Path) -> Result<Vec<ConfigFile>> {
+    let mut loader = Loader {
+        root_dir: None,
+        stack: vec![],
+        files: vec![],
+    };
+    let is_dir = tokio::fs::metadata(path)
+        .await
+        .map(|item| item.is_dir())
+        .unwrap_or_default();
+    if !is_dir {
+        loader.load(path).await?;
+        return Ok(loader.files);
+    }
+    let dir = tokio::fs::canonicalize(path).await.map_err(|e| Error::Io {
+        source: e,
+        file: path.to_string_lossy().to_string(),
+    })?;
+    let pattern = format!("{}/**/*.toml", dir.to_string_lossy());
+    let mut matched = vec![];
+    for entry in glob(&pattern).map_err(|e| Error::Pattern {
+        source: e,
+        path: pattern.clone(),
+    })? {
+        matched.push(entry.map_err(|e| Error::Glob { source: e })?);
+    }
+    matched.sort();
+    loader.root_dir = Some(dir);
+    for item in matched.iter() {
+        loader.load(item).await?;
+    }
+    Ok(loader.files)
+}
+
+/// Prepend the include directive of the config file to its content,
+/// so the include is kept after the config is saved.
+pub(super) fn with_include(files: &[ConfigFile], file: &Path, content: String) -> Result<String> {
+    let includes = files
+        .iter()
+        .find(|item| item.file == file)
+        .map(|item| item.includes.clone())
+        .unwrap_or_default();
+    if includes.is_empty() {
+        return Ok(content);
+    }
+    let mut m = Map::new();
+    m.insert(
+        INCLUDE_KEY.to_string(),
+        Value::Array(
+            includes
+                .iter()
+                .map(|item| Value::from(item.as_str()))
+                .collect(),
+        ),
+    );
+    let include = toml::to_string(&m).map_err(|e| Error::Ser { source: e })?;
+    Ok(format!("{include}\n{content}"))
+}
+
+/// Merge the config files, the same key can't be defined in more than one file.
+fn merge_tables(files: &[&ConfigFile]) -> Result<Map<String, Value>> {
+    let mut merged = Map::new();
+    let mut origins: HashMap<String, usize> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        for (section, value) in file.table.iter() {
+            if !SECTIONS.contains(&section.as_str()) {
+                continue;
+            }
+            let target = merged
+                .entry(section.to_string())
+                .or_insert_with(|| Value::Table(Map::new()));
+            let (items, target) = match (value.as_table(), target.as_table_mut()) {
+                (Some(items), Some(target)) => (items, target),
+                _ => continue,
+            };
+            for (name, item) in items.iter() {
+                let key = format!("{section}.{name}");
+                if let Some(other) = origins.get(&key) {
+                    let other = files[*other];
+                    return Err(Error::Parse {
+                        location: file.location(find_key_line(&file.content, section, name)),
+                        message: format!(
+                            "duplicate key {key}, it's defined in {}",
+                            other.location(find_key_line(&other.content, section, name))
+                        ),
+                    });
+                }
+                origins.insert(key, index);
+                target.insert(name.to_string(), item.clone());
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Merge the config files and convert to config.
+pub(super) fn merge_files(files: &[ConfigFile]) -> Result<PingapConf> {
+    let files: Vec<&ConfigFile> = files.iter().collect();
+    convert_table(&merge_tables(&files)?)
+}
+
+/// Remove the items which are defined in the included files from config,
+/// so they are not written back to the config file.
+/// The included items can't be modified or removed.
+pub(super) fn exclude_included(conf: &PingapConf, files: &[ConfigFile]) -> Result<PingapConf> {
+    let included: Vec<&ConfigFile> = files.iter().filter(|item| item.included).collect();
+    if included.is_empty() {
+        return Ok(conf.clone());
+    }
+    let included_table = merge_tables(&included)?;
+    let normalized = convert_conf(&convert_table(&included_table)?)?;
+    let mut table = convert_conf(conf)?;
+    for (section, value) in included_table.iter() {
+        let items = if let Some(items) = value.as_table() {
+            items
+        } else {
+            continue;
+        };
+        for name in items.keys() {
+            let expected = normalized
+                .get(section)
+                .and_then(|value| value.as_table())
+                .and_then(|value| value.get(name));
+            let current = table
+                .get_mut(section)
+                .and_then(|value| value.as_table_mut());
+            let removed = current.and_then(|current| current.remove(name));
+            if removed.is_none() || removed.as_ref() != expected {
+                let file = included
+                    .iter()
+                    .find(|item| item.get_item(section, name).is_some())
+                    .map(|item| item.file.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                return Err(Error::Invalid {
+                    message: format!(
+                        "{section}.{name} is defined in included file({file}), it can't be modified"
+                    ),
+                });
+            }
+        }
+    }
+    convert_table(&table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exclude_included, find_key_line, get_line, load_files, merge_files, with_include};
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn test_find_key_line() {
+        let content = r#"include = ["shared/*.toml"]
+
+[basic]
+name = "pingap"
+log_level = "info"
+
+[servers.test]
+addr = "127.0.0.1:6188"
+
+[servers]
+"charts".addr = "127.0.0.1:6118"
+"#;
+        assert_eq!(3, get_line(content, content.find("[basic]").unwrap()));
+        assert_eq!(Some(1), find_key_line(content, "", "include"));
+        assert_eq!(Some(5), find_key_line(content, "basic", "log_level"));
+        assert_eq!(Some(7), find_key_line(content, "servers", "test"));
+        assert_eq!(Some(11), find_key_line(content, "servers", "charts"));
+        assert_eq!(None, find_key_line(content, "servers", "abc"));
+    }
+
+    #[tokio::test]
+    async fn test_load_include_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::write(
+            dir.path().join("pingap.toml"),
+            r#"include = ["shared/*.toml"]
+
+[basic]
+name = "pingap"
+
+[servers.test]
+addr = "127.0.0.1:6188"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            shared.join("upstreams.toml"),
+            r#"[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            shared.join("basic.toml"),
+            r#"[basic]
+log_level = "info"
+"#,
+        )
+        .unwrap();
+
+        let files = load_files(&dir.path().join("pingap.toml")).await.unwrap();
+        assert_eq!(3, files.len());
+        assert_eq!(false, files[0].included);
+        assert_eq!(true, files[1].included);
+        let conf = merge_files(&files).unwrap();
+        assert_eq!("pingap", conf.basic.name.clone().unwrap());
+        assert_eq!("info", conf.basic.log_level.clone().unwrap());
+        assert_eq!(true, conf.upstreams.contains_key("charts"));
+        assert_eq!(true, conf.servers.contains_key("test"));
+
+        // the include directive is kept for the file which has it
+        assert_eq!(
+            "include = [\"shared/*.toml\"]\n\n[basic]\n",
+            with_include(&files, &files[0].file, "[basic]\n".to_string()).unwrap()
+        );
+        assert_eq!(
+            "[upstreams]\n",
+            with_include(&files, &files[1].file, "[upstreams]\n".to_string()).unwrap()
+        );
+
+        // the included items are not saved to the config file
+        let mut current = exclude_included(&conf, &files).unwrap();
+        assert_eq!(false, current.upstreams.contains_key("charts"));
+        assert_eq!(true, current.basic.log_level.is_none());
+        assert_eq!(true, current.servers.contains_key("test"));
+
+        current = conf.clone();
+        current.upstreams.remove("charts");
+        let result = exclude_included(&current, &files);
+        assert_eq!(
+            true,
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("upstreams.charts is defined in included file")
+        );
+
+        // duplicate key
+        fs::write(
+            shared.join("servers.toml"),
+            r#"
+[servers.test]
+addr = "127.0.0.1:6189"
+"#,
+        )
+        .unwrap();
+        let files = load_files(&dir.path().join("pingap.toml")).await.unwrap();
+        let result = merge_files(&files);
+        assert_eq!(
+            format!(
+                "Parse config error duplicate key servers.test, it's defined in {}:6, {}:2",
+                files[0].file.to_string_lossy(),
+                shared
+                    .join("servers.toml")
+                    .canonicalize()
+                    .unwrap()
+                    .to_string_lossy(),
+            ),
+            result.err().unwrap().to_string()
+        );
+
+        // parse error
+        fs::write(
+            shared.join("servers.toml"),
+            r#"
+[servers.charts]
+addr =
+"#,
+        )
+        .unwrap();
+        let result = load_files(&dir.path().join("pingap.toml")).await;
+        assert_eq!(
+            true,
+            result.err().unwrap().to_string().contains(&format!(
+                "{}:",
+                shared
+                    .join("servers.toml")
+                    .canonicalize()
+                    .unwrap()
+                    .to_string_lossy()
+            ))
+        );
+
+        // include cycle
+        fs::write(
+            shared.join("servers.toml"),
+            r#"include = ["../pingap.toml"]
+"#,
+        )
+        .unwrap();
+        let result = load_files(&dir.path().join("pingap.toml")).await;
+        assert_eq!(
+            true,
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("include cycle is found")
+        );
+    }
+}
@@ -12,16 +12,62 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{ConfigStorage, EtcdStorage, FileStorage, PingapConf, Result, ETCD_PROTOCOL};
+use super::history::{get_version_config, save_version, SaveVersionParams};
+use super::{
+    diff_config, ConfigStorage, ConfigVersion, ConfigVersionDiff, EtcdStorage, FileStorage,
+    PingapConf, Result, CATEGORIES, ETCD_PROTOCOL,
+};
+
+/// Save the config and the snapshot of it together,
+/// the config fails to save if the snapshot fails.
+async fn save_config_with_version<S: ConfigStorage + Sync>(
+    storage: &S,
+    conf: &PingapConf,
+    category: &str,
+    author: &str,
+) -> Result<()> {
+    let current = storage.load_config(true).await?;
+    let params = SaveVersionParams {
+        author,
+        category,
+        categories: &[category],
+        remark: None,
+    };
+    save_version(storage, &current, conf, params).await?;
+    Ok(())
+}
+
+/// Replace the config by the snapshot of version, and save a new version for it.
+async fn rollback_config_version<S: ConfigStorage + Sync>(
+    storage: &S,
+    version: &str,
+    author: &str,
+) -> Result<ConfigVersion> {
+    // the secrets are restored from the snapshot of version
+    let conf = get_version_config(storage, version).await?;
+    let current = storage.load_config(true).await?;
+    let params = SaveVersionParams {
+        author,
+        category: "",
+        categories: &CATEGORIES,
+        remark: Some(format!("rollback to {version}")),
+    };
+    save_version(storage, &current, &conf, params).await
+}
 
 /// Save the confog to path.
 ///
-/// Validate the config before save.
-pub async fn save_config(path: &str, conf: &PingapConf, category: &str) -> Result<()> {
+/// Validate the config before save, and a snapshot is saved with the author.
+pub async fn save_config(
+    path: &str,
+    conf: &PingapConf,
+    category: &str,
+    author: &str,
+) -> Result<()> {
     if path.starts_with(ETCD_PROTOCOL) {
-        EtcdStorage::new(path)?.save_config(conf, category).await
+        save_config_with_version(&EtcdStorage::new(path)?, conf, category, author).await
     } else {
-        FileStorage::new(path)?.save_config(conf, category).await
+        save_config_with_version(&FileStorage::new(path)?, conf, category, author).await
     }
 }
 
@@ -33,3 +79,37 @@ pub async fn load_config(path: &str, admin: bool) -> Result<PingapConf> {
         FileStorage::new(path)?.load_config(admin).await
     }
 }
+
+/// List the versions of config, the newest is first.
+pub async fn list_config_versions(path: &str) -> Result<Vec<ConfigVersion>> {
+    if path.starts_with(ETCD_PROTOCOL) {
+        EtcdStorage::new(path)?.list_versions().await
+    } else {
+        FileStorage::new(path)?.list_versions().await
+    }
+}
+
+/// Get the config of version, `current` means the current config.
+pub async fn get_config_version(path: &str, version: &str) -> Result<PingapConf> {
+    if path.starts_with(ETCD_PROTOCOL) {
+        get_version_config(&EtcdStorage::new(path)?, version).await
+    } else {
+        get_version_config(&FileStorage::new(path)?, version).await
+    }
+}
+
+/// Get the diff between two versions of config.
+pub async fn diff_config_versions(path: &str, from: &str, to: &str) -> Result<ConfigVersionDiff> {
+    let from_conf = get_config_version(path, from).await?;
+    let to_conf = get_config_version(path, to).await?;
+    diff_config(&from_conf, &to_conf)
+}
+
+/// Roll back the config to the version, all categories are replaced at once.
+pub async fn rollback_config(path: &str, version: &str, author: &str) -> Result<ConfigVersion> {
+    if path.starts_with(ETCD_PROTOCOL) {
+        rollback_config_version(&EtcdStorage::new(path)?, version, author).await
+    } else {
+        rollback_config_version(&FileStorage::new(path)?, version, author).await
+    }
+}
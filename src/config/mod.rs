@@ -18,7 +18,10 @@ use snafu::Snafu;
 mod common;
 mod etcd;
 mod file;
+mod history;
+mod include;
 mod load;
+mod secret;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -38,6 +41,8 @@ pub enum Error {
     },
     #[snafu(display("Toml de error {source}"))]
     De { source: toml::de::Error },
+    #[snafu(display("Parse config error {message}, {location}"))]
+    Parse { location: String, message: String },
     #[snafu(display("Toml ser error {source}"))]
     Ser { source: toml::ser::Error },
     #[snafu(display("Url parse error {source}, {url}"))]
@@ -54,6 +59,10 @@ pub enum Error {
     Base64Decode { source: base64::DecodeError },
     #[snafu(display("Regex error {source}"))]
     Regex { source: regex::Error },
+    #[snafu(display("Json error {source}"))]
+    SerdeJson { source: serde_json::Error },
+    #[snafu(display("Secret error {message}"))]
+    Secret { message: String },
     #[snafu(display("Etcd error {source}"))]
     Etcd { source: etcd_client::Error },
 }
@@ -63,9 +72,34 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 pub trait ConfigStorage {
     async fn load_config(&self, admin: bool) -> Result<PingapConf>;
     async fn save_config(&self, conf: &PingapConf, category: &str) -> Result<()>;
+    /// Save the config of categories with the snapshots together,
+    /// the config isn't saved if the snapshots fail to save.
+    async fn save_config_with_versions(
+        &self,
+        conf: &PingapConf,
+        categories: &[&str],
+        snapshots: &[ConfigSnapshot],
+    ) -> Result<()>;
+    /// Remove the snapshots of versions.
+    async fn remove_versions(&self, versions: &[String]) -> Result<()>;
+    /// List the versions of config, the newest is first.
+    async fn list_versions(&self) -> Result<Vec<ConfigVersion>>;
+    /// Get the snapshot of version.
+    async fn get_version(&self, version: &str) -> Result<ConfigSnapshot>;
 }
 
 pub use common::*;
-pub use etcd::{EtcdStorage, ETCD_PROTOCOL};
+pub use etcd::{EtcdLock, EtcdStorage, ETCD_PROTOCOL};
 pub use file::FileStorage;
-pub use load::{load_config, save_config};
+pub use history::{
+    diff_config, is_valid_version, ConfigSnapshot, ConfigVersion, ConfigVersionDiff,
+    CURRENT_CONFIG_VERSION, MAX_CONFIG_VERSIONS, MAX_CONFIG_VERSION_AGE,
+};
+pub use load::{
+    diff_config_versions, get_config_version, list_config_versions, load_config, rollback_config,
+    save_config,
+};
+pub use secret::{
+    encrypt_secret, is_secret_ref, redact_secrets, resolve_secret, resolve_secrets,
+    restore_secrets, set_secret_key,
+};
@@ -0,0 +1,637 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::include::{convert_conf, convert_table};
+use super::{Error, PingapConf, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use once_cell::sync::{Lazy, OnceCell};
+use pingora::tls::rand::rand_bytes;
+use pingora::tls::symm::{decrypt_aead, encrypt_aead, Cipher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+pub const SECRET_KEY_ENV: &str = "PINGAP_SECRET_KEY";
+pub const ENCRYPTED_PREFIX: &str = "enc:";
+pub const REDACTED_SECRET: &str = "******";
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+static SECRET_KEY: OnceCell<[u8; KEY_SIZE]> = OnceCell::new();
+static SECRET_REF_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$\{(env|file):([^}]+)\}").unwrap());
+
+/// Parse the master key, it should be 32 bytes encoded by hex or base64,
+/// e.g. the output of `openssl rand -hex 32`.
+fn parse_secret_key(key: &str) -> Result<[u8; KEY_SIZE]> {
+    let key = key.trim();
+    let buf = if key.len() == KEY_SIZE * 2 {
+        hex::decode(key).map_err(|e| Error::Secret {
+            message: e.to_string(),
+        })?
+    } else {
+        STANDARD
+            .decode(key)
+            .map_err(|e| Error::Base64Decode { source: e })?
+    };
+    buf.try_into().map_err(|_| Error::Secret {
+        message: format!("secret key should be {KEY_SIZE} bytes encoded by hex or base64"),
+    })
+}
+
+/// Set the master key of secrets, it should be set before the config is loaded.
+pub fn set_secret_key(key: &str) -> Result<()> {
+    let key = parse_secret_key(key)?;
+    let _ = SECRET_KEY.set(key);
+    Ok(())
+}
+
+/// Get the master key of secrets, the env `PINGAP_SECRET_KEY` is used if it's not set.
+fn get_secret_key() -> Result<[u8; KEY_SIZE]> {
+    if let Some(key) = SECRET_KEY.get() {
+        return Ok(*key);
+    }
+    let key = std::env::var(SECRET_KEY_ENV).unwrap_or_default();
+    if key.is_empty() {
+        return Err(Error::Secret {
+            message: "secret key is not set".to_string(),
+        });
+    }
+    parse_secret_key(&key)
+}
+
+/// Check the value is a secret reference(`${env:NAME}`, `${file:path}`)
+/// or an encrypted value(`enc:ciphertext`).
+pub fn is_secret_ref(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX) || SECRET_REF_REGEX.is_match(value)
+}
+
+/// Encrypt the value by aes-256-gcm with master key,
+/// the result is `enc:` + base64(nonce + ciphertext + tag).
+pub fn encrypt_secret(value: &str) -> Result<String> {
+    let key = get_secret_key()?;
+    let mut nonce = [0; NONCE_SIZE];
+    rand_bytes(&mut nonce).map_err(|e| Error::Secret {
+        message: e.to_string(),
+    })?;
+    let mut tag = [0; TAG_SIZE];
+    let data = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        &[],
+        value.as_bytes(),
+        &mut tag,
+    )
+    .map_err(|e| Error::Secret {
+        message: e.to_string(),
+    })?;
+    let mut buf = nonce.to_vec();
+    buf.extend(data);
+    buf.extend(tag);
+    Ok(format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(buf)))
+}
+
+fn decrypt_secret(value: &str) -> Result<String> {
+    let key = get_secret_key()?;
+    let buf = STANDARD
+        .decode(value)
+        .map_err(|e| Error::Base64Decode { source: e })?;
+    if buf.len() < NONCE_SIZE + TAG_SIZE {
+        return Err(Error::Secret {
+            message: "encrypted value is invalid".to_string(),
+        });
+    }
+    let (nonce, data) = buf.split_at(NONCE_SIZE);
+    let (data, tag) = data.split_at(data.len() - TAG_SIZE);
+    let data =
+        decrypt_aead(Cipher::aes_256_gcm(), &key, Some(nonce), &[], data, tag).map_err(|_| {
+            Error::Secret {
+                message: "decrypt fail, the secret key may be wrong".to_string(),
+            }
+        })?;
+    String::from_utf8(data).map_err(|e| Error::Secret {
+        message: e.to_string(),
+    })
+}
+
+/// Resolve the secret value, the encrypted value is decrypted,
+/// and the references of env or file are replaced.
+pub fn resolve_secret(value: &str) -> Result<String> {
+    if let Some(value) = value.strip_prefix(ENCRYPTED_PREFIX) {
+        return decrypt_secret(value);
+    }
+    let mut result = String::with_capacity(value.len());
+    let mut last = 0;
+    for caps in SECRET_REF_REGEX.captures_iter(value) {
+        let (all, category, name) = match (caps.get(0), caps.get(1), caps.get(2)) {
+            (Some(all), Some(category), Some(name)) => (all, category, name),
+            _ => continue,
+        };
+        let name = name.as_str().trim();
+        let data = if category.as_str() == "env" {
+            std::env::var(name).map_err(|_| Error::Secret {
+                message: format!("env({name}) is not found"),
+            })?
+        } else {
+            let data = std::fs::read_to_string(name).map_err(|e| Error::Io {
+                source: e,
+                file: name.to_string(),
+            })?;
+            // the secret file usually ends with new line
+            data.trim_end_matches(['\r', '\n']).to_string()
+        };
+        result.push_str(&value[last..all.start()]);
+        result.push_str(&data);
+        last = all.end();
+    }
+    result.push_str(&value[last..]);
+    Ok(result)
+}
+
+fn resolve_value(value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(data) => {
+            if is_secret_ref(data) {
+                *data = resolve_secret(data)?;
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                resolve_value(item)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Resolve the secret values of config, only the secret fields are resolved.
+/// It should be called before the config is used by services.
+pub fn resolve_secrets(conf: &PingapConf) -> Result<PingapConf> {
+    let mut value = Value::Table(convert_conf(conf)?);
+    for path in get_secret_paths(&value) {
+        if let Some(item) = get_value_mut(&mut value, &path) {
+            resolve_value(item)?;
+        }
+    }
+    convert_table(value.as_table().unwrap_or(&Default::default()))
+}
+
+/// Get the paths of secret fields in config.
+fn get_secret_paths(value: &Value) -> Vec<Vec<String>> {
+    let mut paths = vec![];
+    for key in [
+        "webhook",
+        "certificate_file",
+        "lets_encrypt_dns_provider",
+        "lets_encrypt_eab_hmac_key",
+    ] {
+        paths.push(vec!["basic".to_string(), key.to_string()]);
+    }
+    let get_items = |section: &str| {
+        value
+            .get(section)
+            .and_then(|value| value.as_table())
+            .cloned()
+            .unwrap_or_default()
+    };
+    for section in ["servers", "streams"] {
+        for (name, item) in get_items(section).iter() {
+            paths.push(vec![
+                section.to_string(),
+                name.to_string(),
+                "tls_key".to_string(),
+            ]);
+            if section == "servers" {
+                paths.push(vec![
+                    section.to_string(),
+                    name.to_string(),
+                    "tls_ticket_keys".to_string(),
+                ]);
+            }
+            let count = item
+                .get("tls_certificates")
+                .and_then(|value| value.as_array())
+                .map(|value| value.len())
+                .unwrap_or_default();
+            for index in 0..count {
+                paths.push(vec![
+                    section.to_string(),
+                    name.to_string(),
+                    "tls_certificates".to_string(),
+                    index.to_string(),
+                    "key".to_string(),
+                ]);
+            }
+        }
+    }
+    for (name, item) in get_items("plugins").iter() {
+        let category = item
+            .get("category")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default();
+        let keys: &[&str] = match category {
+            "jwt" => &["secret"],
+            "admin" | "basic_auth" => &["authorizations"],
+            "key_auth" => &["keys"],
+            "csrf" => &["key"],
+            _ => &[],
+        };
+        for key in keys {
+            paths.push(vec![
+                "plugins".to_string(),
+                name.to_string(),
+                key.to_string(),
+            ]);
+        }
+    }
+    paths
+}
+
+fn get_value<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    let mut current = value;
+    for key in path {
+        current = if current.is_array() {
+            current.get(key.parse::<usize>().ok()?)?
+        } else {
+            current.get(key)?
+        };
+    }
+    Some(current)
+}
+
+fn get_value_mut<'a>(value: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    let mut current = value;
+    for key in path {
+        current = if current.is_array() {
+            current.get_mut(key.parse::<usize>().ok()?)?
+        } else {
+            current.get_mut(key)?
+        };
+    }
+    Some(current)
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::String(data) => {
+            if !data.is_empty() && !is_secret_ref(data) {
+                *data = REDACTED_SECRET.to_string();
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                redact_value(item);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn restore_value(value: &mut Value, original: Option<&Value>) {
+    match value {
+        Value::String(data) => {
+            if let Some(Value::String(original)) = original {
+                if data == REDACTED_SECRET {
+                    data.clone_from(original);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                restore_value(item, original.and_then(|value| value.get(index)));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Redact the plain secrets of config, the secret references are kept,
+/// it's used for reading config by admin.
+pub fn redact_secrets(conf: &PingapConf) -> Result<PingapConf> {
+    let mut value = Value::Table(convert_conf(conf)?);
+    for path in get_secret_paths(&value) {
+        if let Some(item) = get_value_mut(&mut value, &path) {
+            redact_value(item);
+        }
+    }
+    convert_table(value.as_table().unwrap_or(&Default::default()))
+}
+
+/// Restore the redacted secrets of config from the original config,
+/// so the secrets are not overwritten when the redacted config is submitted.
+pub fn restore_secrets(conf: &PingapConf, original: &PingapConf) -> Result<PingapConf> {
+    let mut value = Value::Table(convert_conf(conf)?);
+    let original = Value::Table(convert_conf(original)?);
+    for path in get_secret_paths(&value) {
+        if let Some(item) = get_value_mut(&mut value, &path) {
+            restore_value(item, get_value(&original, &path));
+        }
+    }
+    convert_table(value.as_table().unwrap_or(&Default::default()))
+}
+
+/// The value of secret field, it's used to keep the secrets of config snapshot.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct SecretValue {
+    path: Vec<String>,
+    value: Value,
+}
+
+/// Seal the values of secret fields, they are encrypted by master key if it's set,
+/// otherwise they are kept as json.
+pub(super) fn seal_secrets(conf: &PingapConf) -> Result<String> {
+    let value = Value::Table(convert_conf(conf)?);
+    let items: Vec<SecretValue> = get_secret_paths(&value)
+        .into_iter()
+        .filter_map(|path| {
+            get_value(&value, &path)
+                .cloned()
+                .map(|value| SecretValue { path, value })
+        })
+        .collect();
+    let data = serde_json::to_string(&items).map_err(|e| Error::SerdeJson { source: e })?;
+    if get_secret_key().is_ok() {
+        encrypt_secret(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Unseal the secrets and set them to the secret fields of config,
+/// it's the reverse of `seal_secrets`.
+pub(super) fn unseal_secrets(conf: &PingapConf, secrets: &str) -> Result<PingapConf> {
+    let data = if let Some(value) = secrets.strip_prefix(ENCRYPTED_PREFIX) {
+        decrypt_secret(value)?
+    } else {
+        secrets.to_string()
+    };
+    let items: Vec<SecretValue> =
+        serde_json::from_str(&data).map_err(|e| Error::SerdeJson { source: e })?;
+    let mut value = Value::Table(convert_conf(conf)?);
+    for item in items {
+        if let Some(current) = get_value_mut(&mut value, &item.path) {
+            *current = item.value;
+        }
+    }
+    convert_table(value.as_table().unwrap_or(&Default::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encrypt_secret, get_secret_paths, get_value, is_secret_ref, parse_secret_key,
+        redact_secrets, resolve_secret, resolve_secrets, restore_secrets, seal_secrets,
+        set_secret_key, unseal_secrets, REDACTED_SECRET,
+    };
+    use crate::config::include::convert_conf;
+    use crate::config::PingapConf;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use toml::Value;
+
+    const TEST_SECRET_KEY: &str =
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_parse_secret_key() {
+        assert_eq!(
+            (0..32).collect::<Vec<u8>>(),
+            parse_secret_key(TEST_SECRET_KEY).unwrap().to_vec()
+        );
+        assert_eq!(
+            (0..32).collect::<Vec<u8>>(),
+            parse_secret_key("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=")
+                .unwrap()
+                .to_vec()
+        );
+        assert_eq!(
+            "Secret error secret key should be 32 bytes encoded by hex or base64",
+            parse_secret_key("cGluZ2Fw").err().unwrap().to_string()
+        );
+        assert_eq!(true, parse_secret_key("pingap").is_err());
+    }
+
+    #[test]
+    fn test_resolve_secret() {
+        set_secret_key(TEST_SECRET_KEY).unwrap();
+        assert_eq!(true, is_secret_ref("${env:PINGAP_TEST}"));
+        assert_eq!(true, is_secret_ref("enc:YWJj"));
+        assert_eq!(false, is_secret_ref("$remote_addr"));
+
+        let value = encrypt_secret("pingap-secret").unwrap();
+        assert_eq!(true, value.starts_with("enc:"));
+        assert_eq!("pingap-secret", resolve_secret(&value).unwrap());
+        assert_eq!(
+            "Secret error decrypt fail, the secret key may be wrong",
+            resolve_secret("enc:YWJjZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXoxMjM0NTY=")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        std::env::set_var("PINGAP_TEST_SECRET", "abc");
+        assert_eq!(
+            "https://pingap.io/webhook?token=abc",
+            resolve_secret("https://pingap.io/webhook?token=${env:PINGAP_TEST_SECRET}").unwrap()
+        );
+        assert_eq!(
+            "Secret error env(PINGAP_TEST_NOT_FOUND) is not found",
+            resolve_secret("${env:PINGAP_TEST_NOT_FOUND}")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"file-secret\n").unwrap();
+        assert_eq!(
+            "file-secret",
+            resolve_secret(&format!("${{file:{}}}", file.path().to_string_lossy())).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_secrets() {
+        set_secret_key(TEST_SECRET_KEY).unwrap();
+        std::env::set_var("PINGAP_TEST_JWT_SECRET", "123123");
+        let data = format!(
+            r#"
+[basic]
+webhook = "https://pingap.io/webhook"
+
+[plugins.jwt]
+category = "jwt"
+secret = "${{env:PINGAP_TEST_JWT_SECRET}}"
+
+[plugins.basicAuth]
+category = "basic_auth"
+authorizations = ["{}", "YWRtaW46MTIzMTIz"]
+"#,
+            encrypt_secret("YWRtaW46MTIzMTIz").unwrap()
+        );
+        let conf = PingapConf::try_from(data.as_bytes()).unwrap();
+
+        let resolved = resolve_secrets(&conf).unwrap();
+        assert_eq!(
+            "123123",
+            resolved.plugins["jwt"]["secret"].as_str().unwrap()
+        );
+        assert_eq!(
+            r#"["YWRtaW46MTIzMTIz", "YWRtaW46MTIzMTIz"]"#,
+            resolved.plugins["basicAuth"]["authorizations"].to_string()
+        );
+
+        let redacted = redact_secrets(&conf).unwrap();
+        assert_eq!(REDACTED_SECRET, redacted.basic.webhook.clone().unwrap());
+        assert_eq!(
+            "${env:PINGAP_TEST_JWT_SECRET}",
+            redacted.plugins["jwt"]["secret"].as_str().unwrap()
+        );
+        let authorizations = redacted.plugins["basicAuth"]["authorizations"]
+            .as_array()
+            .unwrap();
+        assert_eq!(
+            true,
+            authorizations[0].as_str().unwrap().starts_with("enc:")
+        );
+        assert_eq!(REDACTED_SECRET, authorizations[1].as_str().unwrap());
+
+        let restored = restore_secrets(&redacted, &conf).unwrap();
+        assert_eq!(
+            "https://pingap.io/webhook",
+            restored.basic.webhook.clone().unwrap()
+        );
+        assert_eq!(
+            conf.plugins["basicAuth"]["authorizations"].to_string(),
+            restored.plugins["basicAuth"]["authorizations"].to_string()
+        );
+
+        // the sealed secrets are encrypted by master key
+        let sealed = seal_secrets(&conf).unwrap();
+        assert_eq!(true, sealed.starts_with("enc:"));
+        assert_eq!(false, sealed.contains("pingap.io"));
+        let unsealed = unseal_secrets(&redacted, &sealed).unwrap();
+        assert_eq!(conf.hash().unwrap(), unsealed.hash().unwrap());
+    }
+
+    #[test]
+    fn test_secret_paths() {
+        set_secret_key(TEST_SECRET_KEY).unwrap();
+        std::env::set_var("PINGAP_TEST_PATH_SECRET", "pingap-secret");
+        let secret = "${env:PINGAP_TEST_PATH_SECRET}";
+        let data = format!(
+            r#"
+[basic]
+webhook = "{secret}"
+certificate_file = "{secret}"
+lets_encrypt_dns_provider = "{secret}"
+lets_encrypt_eab_hmac_key = "{secret}"
+
+[upstreams.charts]
+addrs = ["127.0.0.1:5000"]
+
+[locations.lo]
+upstream = "charts"
+proxy_set_headers = ["X-Token:{secret}"]
+
+[servers.test]
+addr = "0.0.0.0:6188"
+locations = ["lo"]
+tls_key = "{secret}"
+tls_ticket_keys = ["{secret}"]
+
+[[servers.test.tls_certificates]]
+cert = ""
+key = "{secret}"
+
+[streams.tcp]
+addr = "0.0.0.0:6189"
+upstream = "charts"
+tls_key = "{secret}"
+
+[[streams.tcp.tls_certificates]]
+cert = ""
+key = "{secret}"
+
+[plugins.jwt]
+category = "jwt"
+secret = "{secret}"
+
+[plugins.admin]
+category = "admin"
+authorizations = ["{secret}"]
+
+[plugins.basicAuth]
+category = "basic_auth"
+authorizations = ["{secret}"]
+
+[plugins.keyAuth]
+category = "key_auth"
+keys = ["{secret}"]
+
+[plugins.csrf]
+category = "csrf"
+key = "{secret}"
+"#
+        );
+        let conf = PingapConf::try_from(data.as_bytes()).unwrap();
+        let value = Value::Table(convert_conf(&conf).unwrap());
+        let mut paths: Vec<String> = get_secret_paths(&value)
+            .iter()
+            .map(|path| path.join("."))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            vec![
+                "basic.certificate_file",
+                "basic.lets_encrypt_dns_provider",
+                "basic.lets_encrypt_eab_hmac_key",
+                "basic.webhook",
+                "plugins.admin.authorizations",
+                "plugins.basicAuth.authorizations",
+                "plugins.csrf.key",
+                "plugins.jwt.secret",
+                "plugins.keyAuth.keys",
+                "servers.test.tls_certificates.0.key",
+                "servers.test.tls_key",
+                "servers.test.tls_ticket_keys",
+                "streams.tcp.tls_certificates.0.key",
+                "streams.tcp.tls_key",
+            ],
+            paths
+        );
+
+        // each secret field is resolved
+        let resolved = Value::Table(convert_conf(&resolve_secrets(&conf).unwrap()).unwrap());
+        for path in get_secret_paths(&value) {
+            let item = get_value(&resolved, &path).unwrap();
+            let item = if let Some(items) = item.as_array() {
+                &items[0]
+            } else {
+                item
+            };
+            assert_eq!("pingap-secret", item.as_str().unwrap());
+        }
+        // the other fields are kept as it is
+        let resolved = resolve_secrets(&conf).unwrap();
+        assert_eq!(
+            vec!["X-Token:${env:PINGAP_TEST_PATH_SECRET}".to_string()],
+            resolved.locations["lo"].proxy_set_headers.clone().unwrap()
+        );
+    }
+}
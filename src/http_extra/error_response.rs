@@ -0,0 +1,230 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    HttpHeader, HTTP_HEADER_CONTENT_HTML, HTTP_HEADER_CONTENT_JSON, HTTP_HEADER_CONTENT_TEXT,
+};
+use crate::util;
+use bytes::Bytes;
+use serde::Serialize;
+
+pub const ERROR_FORMAT_AUTO: &str = "auto";
+pub const ERROR_FORMAT_HTML: &str = "html";
+pub const ERROR_FORMAT_JSON: &str = "json";
+pub const ERROR_FORMAT_TEXT: &str = "text";
+
+/// Returns true if the error format is supported.
+pub fn is_valid_error_format(format: &str) -> bool {
+    [
+        ERROR_FORMAT_AUTO,
+        ERROR_FORMAT_HTML,
+        ERROR_FORMAT_JSON,
+        ERROR_FORMAT_TEXT,
+    ]
+    .contains(&format)
+}
+
+/// Get the error format from the accept header of request,
+/// the first acceptable media type is used, default is html.
+pub fn get_error_format(accept: &str) -> &'static str {
+    for item in accept.split(',') {
+        let mut arr = item.split(';');
+        let media_type = arr.next().unwrap_or_default().trim().to_lowercase();
+        // q=0 means not acceptable
+        if arr.any(|param| {
+            let param = param.trim().replace(' ', "");
+            param == "q=0" || param == "q=0.0"
+        }) {
+            continue;
+        }
+        match media_type.as_str() {
+            "text/html" | "application/xhtml+xml" | "*/*" => return ERROR_FORMAT_HTML,
+            "application/json" => return ERROR_FORMAT_JSON,
+            "text/plain" => return ERROR_FORMAT_TEXT,
+            _ => {
+                if media_type.ends_with("+json") {
+                    return ERROR_FORMAT_JSON;
+                }
+            }
+        }
+    }
+    ERROR_FORMAT_HTML
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ErrorBody {
+    pub status: u16,
+    pub code: String,
+    pub message: String,
+    pub request_id: String,
+    // the host and time are only used for html template
+    #[serde(skip)]
+    pub host: String,
+    #[serde(skip)]
+    pub time: String,
+}
+
+impl ErrorBody {
+    /// Render the error body by format, the template is only used for html format.
+    /// Returns the content type header and the body.
+    pub fn render(&self, format: &str, template: &str) -> (HttpHeader, Bytes) {
+        match format {
+            ERROR_FORMAT_JSON => {
+                let buf = serde_json::to_vec(self).unwrap_or_default();
+                (HTTP_HEADER_CONTENT_JSON.clone(), Bytes::from(buf))
+            }
+            ERROR_FORMAT_TEXT => {
+                let mut content = format!("{} {}: {}", self.status, self.code, self.message);
+                if !self.request_id.is_empty() {
+                    content += &format!(" (request id: {})", self.request_id);
+                }
+                (HTTP_HEADER_CONTENT_TEXT.clone(), Bytes::from(content))
+            }
+            _ => {
+                let content = self.render_html(template);
+                (HTTP_HEADER_CONTENT_HTML.clone(), Bytes::from(content))
+            }
+        }
+    }
+    /// Render the html template, the template is scanned only once and
+    /// the values are escaped, so they can't inject html or other tags.
+    fn render_html(&self, template: &str) -> String {
+        let mut buf = String::with_capacity(template.len() + 128);
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            buf.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            if let Some(end) = tail.find("}}") {
+                let key = &tail[..end + 2];
+                let value = match key {
+                    "{{version}}" => util::get_pkg_version().to_string(),
+                    "{{content}}" | "{{message}}" => self.message.clone(),
+                    "{{status}}" => self.status.to_string(),
+                    "{{code}}" => self.code.clone(),
+                    "{{request_id}}" => self.request_id.clone(),
+                    "{{host}}" => self.host.clone(),
+                    "{{time}}" => self.time.clone(),
+                    // keep the unknown tag of template
+                    _ => {
+                        buf.push_str(key);
+                        rest = &tail[end + 2..];
+                        continue;
+                    }
+                };
+                buf.push_str(&html_escape(&value));
+                rest = &tail[end + 2..];
+            } else {
+                buf.push_str(tail);
+                rest = "";
+            }
+        }
+        buf.push_str(rest);
+        buf
+    }
+}
+
+/// Escape the special characters of html.
+fn html_escape(value: &str) -> String {
+    let mut buf = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&#x27;"),
+            _ => buf.push(ch),
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_error_format, is_valid_error_format, ErrorBody};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_get_error_format() {
+        assert_eq!(true, is_valid_error_format("json"));
+        assert_eq!(false, is_valid_error_format("xml"));
+
+        assert_eq!("html", get_error_format(""));
+        assert_eq!(
+            "html",
+            get_error_format("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
+        );
+        assert_eq!(
+            "json",
+            get_error_format("application/json, text/plain, */*")
+        );
+        assert_eq!("json", get_error_format("application/problem+json"));
+        assert_eq!("text", get_error_format("text/html;q=0, text/plain"));
+        assert_eq!("html", get_error_format("image/webp"));
+    }
+
+    #[test]
+    fn test_render_error_body() {
+        let body = ErrorBody {
+            status: 502,
+            code: "ConnectRefused".to_string(),
+            message: "Connection refused".to_string(),
+            request_id: "abc".to_string(),
+            host: "pingap.io".to_string(),
+            time: "2024-06-01T00:00:00Z".to_string(),
+        };
+        let (header, buf) = body.render("json", "");
+        assert_eq!(
+            "application/json; charset=utf-8",
+            header.1.to_str().unwrap()
+        );
+        assert_eq!(
+            r#"{"status":502,"code":"ConnectRefused","message":"Connection refused","request_id":"abc"}"#,
+            std::string::String::from_utf8_lossy(&buf)
+        );
+
+        let (header, buf) = body.render("text", "");
+        assert_eq!("text/plain; charset=utf-8", header.1.to_str().unwrap());
+        assert_eq!(
+            "502 ConnectRefused: Connection refused (request id: abc)",
+            std::string::String::from_utf8_lossy(&buf)
+        );
+
+        let (header, buf) = body.render(
+            "html",
+            "<p>{{status}} {{content}} {{request_id}} {{host}} {{time}}</p>",
+        );
+        assert_eq!("text/html; charset=utf-8", header.1.to_str().unwrap());
+        assert_eq!(
+            "<p>502 Connection refused abc pingap.io 2024-06-01T00:00:00Z</p>",
+            std::string::String::from_utf8_lossy(&buf)
+        );
+
+        // the values from request are escaped
+        let body = ErrorBody {
+            status: 404,
+            message: "<script>alert('x')</script> {{host}}".to_string(),
+            host: "a.com\"><img src=x>".to_string(),
+            ..Default::default()
+        };
+        let (_, buf) = body.render(
+            "html",
+            "<p title=\"{{host}}\">{{message}} {{unknown}} {{</p>",
+        );
+        assert_eq!(
+            "<p title=\"a.com&quot;&gt;&lt;img src=x&gt;\">&lt;script&gt;alert(&#x27;x&#x27;)&lt;/script&gt; {{host}} {{unknown}} {{</p>",
+            std::string::String::from_utf8_lossy(&buf)
+        );
+    }
+}
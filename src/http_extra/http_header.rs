@@ -27,6 +27,9 @@ const REMOTE_ADDR_TAG: &[u8] = b"$remote_addr";
 const PROXY_ADD_FORWARDED_TAG: &[u8] = b"$proxy_add_x_forwarded_for";
 const HTTP_ORIGIN_TAG: &[u8] = b"$http_origin";
 const UPSTREAM_ADDR_TAG: &[u8] = b"$upstream_addr";
+const SSL_CLIENT_SUBJECT_TAG: &[u8] = b"$ssl_client_s_dn";
+const SSL_CLIENT_SAN_TAG: &[u8] = b"$ssl_client_san";
+const SSL_CLIENT_FINGERPRINT_TAG: &[u8] = b"$ssl_client_fingerprint";
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -64,6 +67,38 @@ pub fn convert_header(value: &str) -> Result<Option<HttpHeader>> {
     }
 }
 
+#[inline]
+fn to_header_value(value: &Option<String>) -> Option<HeaderValue> {
+    if let Some(value) = value {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Convert the list to header value, it's encoded as structured field strings.
+#[inline]
+fn to_list_header_value(value: &Option<Vec<String>>) -> Option<HeaderValue> {
+    if let Some(value) = value {
+        if let Ok(value) = HeaderValue::from_str(&util::to_sf_string_list(value)) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Returns true if the value is the tag of client certificate, the header
+/// of these tags should be removed from the request of client.
+pub fn is_ssl_client_tag(value: &HeaderValue) -> bool {
+    [
+        SSL_CLIENT_SUBJECT_TAG,
+        SSL_CLIENT_SAN_TAG,
+        SSL_CLIENT_FINGERPRINT_TAG,
+    ]
+    .contains(&value.as_bytes())
+}
+
 pub fn convert_header_value(
     value: &HeaderValue,
     session: &Session,
@@ -104,6 +139,15 @@ pub fn convert_header_value(
                 return Some(origin.clone());
             }
         }
+        SSL_CLIENT_SUBJECT_TAG => {
+            return to_list_header_value(&ctx.client_cert_subject);
+        }
+        SSL_CLIENT_SAN_TAG => {
+            return to_list_header_value(&ctx.client_cert_sans);
+        }
+        SSL_CLIENT_FINGERPRINT_TAG => {
+            return to_header_value(&ctx.client_cert_fingerprint);
+        }
         _ => {}
     };
 
@@ -156,6 +200,13 @@ pub static HTTP_HEADER_CONTENT_HTML: Lazy<HttpHeader> = Lazy::new(|| {
     )
 });
 
+pub static HTTP_HEADER_CONTENT_TEXT: Lazy<HttpHeader> = Lazy::new(|| {
+    (
+        header::CONTENT_TYPE,
+        HeaderValue::from_str("text/plain; charset=utf-8").unwrap(),
+    )
+});
+
 pub static HTTP_HEADER_TRANSFER_CHUNKED: Lazy<HttpHeader> = Lazy::new(|| {
     (
         header::TRANSFER_ENCODING,
@@ -214,6 +265,25 @@ mod tests {
         assert_eq!(true, value.is_some());
         assert_eq!("10.1.1.1", value.unwrap().to_str().unwrap());
 
+        let value = convert_header_value(
+            &HeaderValue::from_str("$ssl_client_s_dn").unwrap(),
+            &session,
+            &State {
+                client_cert_subject: Some(vec!["CN=a,O=b".to_string(), "O=pingap".to_string()]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            r#""CN=a,O=b", "O=pingap""#,
+            value.unwrap().to_str().unwrap()
+        );
+        let value = convert_header_value(
+            &HeaderValue::from_str("$ssl_client_san").unwrap(),
+            &session,
+            &State::default(),
+        );
+        assert_eq!(true, value.is_none());
+
         let headers = ["X-Forwarded-For: 1.1.1.1, 2.2.2.2"].join("\r\n");
         let input_header = format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
         let mock_io = Builder::new().read(input_header.as_bytes()).build();
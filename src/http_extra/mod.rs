@@ -12,8 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod error_response;
 mod http_header;
 mod http_response;
 
+pub use error_response::*;
 pub use http_header::*;
 pub use http_response::*;
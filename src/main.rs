@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::acme::{new_lets_encrypt_service, new_tls_validity_service};
+use crate::acme::{
+    is_http_challenge, new_lets_encrypt_service, new_tls_validity_service, try_init_cert_store,
+};
 use crate::config::ETCD_PROTOCOL;
 use crate::service::new_auto_restart_service;
 use clap::Parser;
@@ -22,8 +24,12 @@ use log::{error, info};
 use pingora::server;
 use pingora::server::configuration::Opt;
 use pingora::services::background::background_service;
-use proxy::{new_upstream_health_check_task, Server, ServerConf};
+use proxy::{
+    new_ocsp_stapling_service, new_ticket_key_rotation_service, new_upstream_health_check_task,
+    Server, ServerConf, StreamServer,
+};
 use state::get_start_time;
+use std::collections::HashMap;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
@@ -82,6 +88,13 @@ struct Args {
     /// Whether this server should try to auto restart
     #[arg(short, long)]
     autorestart: bool,
+    /// The file of master key for secrets
+    ///
+    /// The encrypted values(`enc:`) of config are decrypted by the master key,
+    /// it should be 32 bytes encoded by hex or base64,
+    /// the env `PINGAP_SECRET_KEY` is used if it's not set.
+    #[arg(long)]
+    secret_key_file: Option<String>,
 }
 
 fn new_server_conf(args: &Args, conf: &PingapConf) -> server::configuration::ServerConf {
@@ -126,7 +139,10 @@ fn get_config(conf: String, admin: bool, s: Sender<Result<PingapConf, config::Er
         match tokio::runtime::Runtime::new() {
             Ok(rt) => {
                 let send = async move {
-                    let result = config::load_config(&conf, admin).await;
+                    // the secrets are resolved before the config is used
+                    let result = config::load_config(&conf, admin)
+                        .await
+                        .and_then(|conf| config::resolve_secrets(&conf));
                     if let Err(e) = s.send(result) {
                         // use pringln because log is not init
                         println!("sender fail, {e}");
@@ -201,6 +217,10 @@ fn run_admin_node(args: Args) -> Result<(), Box<dyn Error>> {
 
 fn run() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
+    if let Some(file) = &args.secret_key_file {
+        let key = std::fs::read_to_string(util::resolve_path(file))?;
+        config::set_secret_key(&key)?;
+    }
 
     if args.cp && args.admin.is_some() {
         return run_admin_node(args);
@@ -270,6 +290,9 @@ fn run() -> Result<(), Box<dyn Error>> {
         if args.autorestart {
             new_args.push("--autorestart".to_string());
         }
+        if let Some(file) = &args.secret_key_file {
+            new_args.push(format!("--secret-key-file={}", util::resolve_path(file)));
+        }
         cmd.args = new_args;
         state::set_restart_process_command(cmd);
     }
@@ -304,6 +327,17 @@ fn run() -> Result<(), Box<dyn Error>> {
         .map(|(name, value)| (name.to_string(), value.clone()))
         .collect();
 
+    let stream_confs = conf.streams.clone();
+    // only http-01 challenge needs the server listen 80
+    let enabled_http_challenge = is_http_challenge(&conf.basic);
+    let tls_validity_warning = conf
+        .basic
+        .tls_validity_warning
+        .unwrap_or(Duration::from_secs(7 * 24 * 3600));
+    let tls_validity_critical = conf
+        .basic
+        .tls_validity_critical
+        .unwrap_or(Duration::from_secs(24 * 3600));
     let mut server_conf_list: Vec<ServerConf> = conf.into();
     if let Some(addr) = args.admin {
         let (server_conf, name, proxy_plugin_info) = parse_admin_proxy_plugin(&addr);
@@ -314,13 +348,27 @@ fn run() -> Result<(), Box<dyn Error>> {
     if let Err(e) = plugin::init_plugins(plugin_confs) {
         error!("init plugins fail, {e}");
     }
-    let mut domains = vec![];
+    // the lets encrypt domains of each certificate group
+    let mut domain_groups: HashMap<String, Vec<String>> = HashMap::new();
     let mut exits_80_server = false;
+    let mut enabled_ocsp_stapling = false;
+    let mut enabled_ticket_key_rotation = false;
     for serve_conf in server_conf_list.iter() {
+        if serve_conf.enabled_ocsp_stapling {
+            enabled_ocsp_stapling = true;
+        }
+        if serve_conf.tls_session.ticket_key_file.is_some()
+            || serve_conf.tls_session.ticket_key_rotation.is_some()
+        {
+            enabled_ticket_key_rotation = true;
+        }
         if serve_conf.addr.ends_with(":80") {
             exits_80_server = true;
         }
         if let Some(value) = &serve_conf.lets_encrypt {
+            let domains = domain_groups
+                .entry(serve_conf.lets_encrypt_group.clone())
+                .or_default();
             value.split(',').for_each(|item| {
                 let v = item.trim().to_string();
                 if !v.is_empty() && !domains.contains(&v) {
@@ -330,28 +378,37 @@ fn run() -> Result<(), Box<dyn Error>> {
         }
     }
     // no server listen 80 and lets encrypt domains is not empty
-    if !exits_80_server && !domains.is_empty() {
+    if !exits_80_server && !domain_groups.is_empty() && enabled_http_challenge {
         server_conf_list.push(ServerConf {
             name: "lets encrypt".to_string(),
             addr: "0.0.0.0:80".to_string(),
             ..Default::default()
         });
     }
+    // load the acme certificates before the tls servers are created
+    if !domain_groups.is_empty() {
+        if let Err(e) = try_init_cert_store() {
+            error!("Load acme cert store fail, error: {e}");
+        }
+    }
 
-    let mut tls_cert_info_list = vec![];
+    let mut enabled_tls = false;
     for server_conf in server_conf_list.iter() {
         let listen_80_port = server_conf.addr.ends_with(":80");
-        let name = server_conf.name.clone();
         let mut ps = Server::new(server_conf)?;
-        if !domains.is_empty() && listen_80_port {
+        if !domain_groups.is_empty() && listen_80_port {
             ps.enable_lets_encrypt();
         }
         let services = ps.run(&my_server.configuration)?;
         my_server.add_service(services.lb);
-        if let Some(tls_cert_info) = services.tls_cert_info {
-            tls_cert_info_list.push((name, tls_cert_info));
+        if !services.tls_cert_info_list.is_empty() {
+            enabled_tls = true;
         }
     }
+    for (name, stream_conf) in stream_confs.iter() {
+        let stream = StreamServer::new(name, stream_conf)?;
+        my_server.add_service(stream.run()?);
+    }
 
     if args.autorestart {
         my_server.add_service(background_service(
@@ -359,16 +416,28 @@ fn run() -> Result<(), Box<dyn Error>> {
             new_auto_restart_service(auto_restart_check_interval),
         ));
     }
-    if !domains.is_empty() {
+    for (group, domains) in domain_groups {
         my_server.add_service(background_service(
-            "Lets encrypt",
-            new_lets_encrypt_service(domains),
+            &format!("Lets encrypt({group})"),
+            new_lets_encrypt_service(&group, domains),
         ));
     }
-    if !tls_cert_info_list.is_empty() {
+    if enabled_tls {
         my_server.add_service(background_service(
             "Tls cert validity checker",
-            new_tls_validity_service(tls_cert_info_list),
+            new_tls_validity_service(tls_validity_warning, tls_validity_critical),
+        ));
+    }
+    if enabled_ocsp_stapling {
+        my_server.add_service(background_service(
+            "Ocsp stapling",
+            new_ocsp_stapling_service(),
+        ));
+    }
+    if enabled_ticket_key_rotation {
+        my_server.add_service(background_service(
+            "Tls ticket key rotation",
+            new_ticket_key_rotation_service(),
         ));
     }
     my_server.add_service(background_service(
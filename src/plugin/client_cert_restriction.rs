@@ -0,0 +1,276 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_step_conf, get_str_conf, get_str_slice_conf, Error, ProxyPlugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::StatusCode;
+use log::debug;
+use pingora::proxy::Session;
+
+pub struct ClientCertRestriction {
+    plugin_step: PluginStep,
+    subject_list: Vec<String>,
+    san_list: Vec<String>,
+    fingerprint_list: Vec<String>,
+    restriction_category: String,
+    forbidden_resp: HttpResponse,
+}
+
+struct ClientCertRestrictionParams {
+    plugin_step: PluginStep,
+    subject_list: Vec<String>,
+    san_list: Vec<String>,
+    fingerprint_list: Vec<String>,
+    restriction_category: String,
+    message: String,
+}
+
+impl TryFrom<&PluginConf> for ClientCertRestrictionParams {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let step = get_step_conf(value);
+        let params = Self {
+            plugin_step: step,
+            subject_list: get_str_slice_conf(value, "subject_list"),
+            san_list: get_str_slice_conf(value, "san_list"),
+            fingerprint_list: get_str_slice_conf(value, "fingerprint_list")
+                .iter()
+                .map(|item| item.to_lowercase().replace(':', ""))
+                .collect(),
+            restriction_category: get_str_conf(value, "type"),
+            message: get_str_conf(value, "message"),
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream].contains(&params.plugin_step) {
+            return Err(Error::Invalid {
+                category: PluginCategory::ClientCertRestriction.to_string(),
+                message: "Client cert restriction plugin should be executed at request or proxy upstream step".to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl ClientCertRestriction {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!("new client cert restriction proxy plugin, params:{params:?}");
+        let params = ClientCertRestrictionParams::try_from(params)?;
+        let mut message = params.message;
+        if message.is_empty() {
+            message = "Client certificate is forbidden".to_string();
+        }
+        Ok(Self {
+            plugin_step: params.plugin_step,
+            subject_list: params.subject_list,
+            san_list: params.san_list,
+            fingerprint_list: params.fingerprint_list,
+            restriction_category: params.restriction_category,
+            forbidden_resp: HttpResponse {
+                status: StatusCode::FORBIDDEN,
+                body: Bytes::from(message),
+                ..Default::default()
+            },
+        })
+    }
+    fn matched(&self, ctx: &State) -> bool {
+        if let Some(subject) = &ctx.client_cert_subject {
+            if self.subject_list.iter().any(|item| subject.contains(item)) {
+                return true;
+            }
+        }
+        if let Some(sans) = &ctx.client_cert_sans {
+            for san in sans.iter() {
+                // support * suffix for prefix match, e.g. URI:spiffe://mesh/*
+                let found = self.san_list.iter().any(|item| {
+                    if let Some(prefix) = item.strip_suffix('*') {
+                        san.starts_with(prefix)
+                    } else {
+                        san == item
+                    }
+                });
+                if found {
+                    return true;
+                }
+            }
+        }
+        if let Some(fingerprint) = &ctx.client_cert_fingerprint {
+            if self.fingerprint_list.contains(fingerprint) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[async_trait]
+impl ProxyPlugin for ClientCertRestriction {
+    #[inline]
+    fn step(&self) -> String {
+        self.plugin_step.to_string()
+    }
+    #[inline]
+    fn category(&self) -> PluginCategory {
+        PluginCategory::ClientCertRestriction
+    }
+    #[inline]
+    async fn handle(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let found = self.matched(ctx);
+        // deny client cert
+        let allow = if self.restriction_category == "deny" {
+            !found
+        } else {
+            found
+        };
+        if !allow {
+            return Ok(Some(self.forbidden_resp.clone()));
+        }
+        return Ok(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientCertRestriction, ClientCertRestrictionParams};
+    use crate::state::State;
+    use crate::{config::PluginConf, config::PluginStep, plugin::ProxyPlugin};
+    use http::StatusCode;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_client_cert_restriction_params() {
+        let params = ClientCertRestrictionParams::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+subject_list = ["CN=svc-a"]
+san_list = ["URI:spiffe://mesh/*"]
+fingerprint_list = ["AB:CD"]
+type = "allow"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("request", params.plugin_step.to_string());
+        assert_eq!("CN=svc-a", params.subject_list.join(","));
+        assert_eq!("URI:spiffe://mesh/*", params.san_list.join(","));
+        assert_eq!("abcd", params.fingerprint_list.join(","));
+
+        let result = ClientCertRestrictionParams::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+subject_list = ["CN=svc-a"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!("Plugin client_cert_restriction invalid, message: Client cert restriction plugin should be executed at request or proxy upstream step", result.err().unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_restriction() {
+        let allow = ClientCertRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "allow"
+subject_list = ["CN=svc-a"]
+san_list = ["URI:spiffe://mesh/*"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("client_cert_restriction", allow.category().to_string());
+        assert_eq!("request", allow.step().to_string());
+
+        let headers = ["Accept-Encoding: gzip"].join("\r\n");
+        let input_header = format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        // no client certificate
+        let result = allow
+            .handle(PluginStep::Request, &mut session, &mut State::default())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
+
+        let result = allow
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    client_cert_subject: Some(vec!["O=pingap".to_string(), "CN=svc-a".to_string()]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let result = allow
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    client_cert_subject: Some(vec!["CN=svc-b".to_string()]),
+                    client_cert_sans: Some(vec![
+                        "DNS:svc-b".to_string(),
+                        "URI:spiffe://mesh/svc-b".to_string(),
+                    ]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let deny = ClientCertRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "deny"
+fingerprint_list = ["abcd"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let result = deny
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    client_cert_fingerprint: Some("abcd".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
+    }
+}
@@ -127,6 +127,9 @@ impl ProxyPlugin for IpRestriction {
 
         let found = if self.ip_list.contains(&ip) {
             true
+        } else if ip == util::UNIX_CLIENT_IP {
+            // the unix socket peer only matches `unix:` of ip list
+            false
         } else {
             match ip.parse::<IpAddr>() {
                 Ok(addr) => self.ip_net_list.iter().any(|item| item.contains(&addr)),
@@ -304,5 +307,42 @@ ip_list = [
             .await
             .unwrap();
         assert_eq!(true, result.is_none());
+
+        // unix socket peer is not loopback
+        let result = allow
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    client_ip: Some("unix:".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
+
+        let allow = IpRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "allow"
+ip_list = ["unix:"]
+    "###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let result = allow
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    client_ip: Some("unix:".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
     }
 }
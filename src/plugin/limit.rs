@@ -32,6 +32,8 @@ pub enum LimitTag {
     RequestHeader,
     Cookie,
     Query,
+    Ja3,
+    Ja4,
 }
 
 pub struct Limiter {
@@ -61,6 +63,8 @@ impl TryFrom<&PluginConf> for LimiterParams {
             "cookie" => LimitTag::Cookie,
             "header" => LimitTag::RequestHeader,
             "query" => LimitTag::Query,
+            "ja3" => LimitTag::Ja3,
+            "ja4" => LimitTag::Ja4,
             _ => LimitTag::Ip,
         };
         let interval = get_str_conf(value, "interval");
@@ -125,6 +129,8 @@ impl Limiter {
             LimitTag::Cookie => util::get_cookie_value(session.req_header(), &self.key)
                 .unwrap_or_default()
                 .to_string(),
+            LimitTag::Ja3 => ctx.ja3.clone().unwrap_or_default(),
+            LimitTag::Ja4 => ctx.ja4.clone().unwrap_or_default(),
             _ => {
                 let client_ip = util::get_client_ip(session);
                 ctx.client_ip = Some(client_ip.clone());
@@ -348,6 +354,34 @@ max = 10
         assert_eq!(true, ctx.guard.is_some());
     }
     #[tokio::test]
+    async fn test_new_ja4_limiter() {
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "inflight"
+tag = "ja4"
+max = 10
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(LimitTag::Ja4, limiter.tag);
+        let session = new_session().await;
+
+        // no fingerprint, not limit
+        let mut ctx = State::default();
+        limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(true, ctx.guard.is_none());
+
+        let mut ctx = State {
+            ja4: Some("t13d0407h2_39e807bd56df_38dbf9c86be1".to_string()),
+            ..Default::default()
+        };
+        limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(true, ctx.guard.is_some());
+    }
+    #[tokio::test]
     async fn test_inflight_limit() {
         let limiter = Limiter::new(
             &toml::from_str::<PluginConf>(
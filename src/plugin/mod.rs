@@ -27,6 +27,7 @@ use std::str::FromStr;
 mod admin;
 mod basic_auth;
 mod cache;
+mod client_cert_restriction;
 mod compression;
 mod csrf;
 mod directory;
@@ -41,6 +42,7 @@ mod referer_restriction;
 mod request_id;
 mod response_headers;
 mod stats;
+mod tls_fingerprint_restriction;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -227,6 +229,14 @@ pub fn parse_plugins(confs: Vec<(String, PluginConf)>) -> Result<Plugins> {
                 let c = csrf::Csrf::new(conf)?;
                 proxy_plugins.insert(name, Box::new(c));
             }
+            PluginCategory::ClientCertRestriction => {
+                let c = client_cert_restriction::ClientCertRestriction::new(conf)?;
+                proxy_plugins.insert(name, Box::new(c));
+            }
+            PluginCategory::TlsFingerprintRestriction => {
+                let t = tls_fingerprint_restriction::TlsFingerprintRestriction::new(conf)?;
+                proxy_plugins.insert(name, Box::new(t));
+            }
             PluginCategory::Jwt => {
                 let (auth, sign) = jwt::new(conf)?;
                 proxy_plugins.insert(name.clone(), Box::new(auth));
@@ -31,6 +31,11 @@ static PONG_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
     body: Bytes::from_static(b"pong"),
     ..Default::default()
 });
+static DRAINING_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
+    status: StatusCode::SERVICE_UNAVAILABLE,
+    body: Bytes::from_static(b"draining"),
+    ..Default::default()
+});
 
 impl Ping {
     pub fn new(params: &PluginConf) -> Result<Self> {
@@ -63,12 +68,16 @@ impl ProxyPlugin for Ping {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
         }
         if session.req_header().uri.path() == self.path {
+            // the instance should be removed from load balancer
+            if ctx.draining {
+                return Ok(Some(DRAINING_RESPONSE.clone()));
+            }
             return Ok(Some(PONG_RESPONSE.clone()));
         }
         Ok(None)
@@ -115,6 +124,24 @@ path = "/ping"
         assert_eq!(200, resp.status.as_u16());
         assert_eq!(b"pong", resp.body.as_ref());
 
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = ping
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    draining: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let resp = result.unwrap();
+        assert_eq!(503, resp.status.as_u16());
+        assert_eq!(b"draining", resp.body.as_ref());
+
         let result = Ping::new(
             &toml::from_str::<PluginConf>(
                 r###"
@@ -15,6 +15,7 @@
 use super::{get_step_conf, get_str_conf, Error, ProxyPlugin, Result};
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::{HttpResponse, HTTP_HEADER_CONTENT_JSON};
+use crate::proxy::{get_connection_stats, get_tls_handshake_stats};
 use crate::state::{get_hostname, get_start_time, State};
 use crate::util;
 use async_trait::async_trait;
@@ -34,6 +35,12 @@ struct ServerStats {
     accepted: u64,
     location_processing: i32,
     location_accepted: u64,
+    websocket_processing: i32,
+    location_websocket_processing: i32,
+    tls_handshake_resumed: u64,
+    tls_handshake_full: u64,
+    connection_rejected: u64,
+    connection_timeout: u64,
     hostname: String,
     physical_mem_mb: usize,
     physical_mem: String,
@@ -110,11 +117,19 @@ impl ProxyPlugin for Stats {
             }
             let uptime: humantime::Duration =
                 Duration::from_secs(util::now().as_secs() - get_start_time()).into();
+            let (tls_handshake_resumed, tls_handshake_full) = get_tls_handshake_stats();
+            let (connection_rejected, connection_timeout) = get_connection_stats();
             let buf = serde_json::to_vec(&ServerStats {
                 accepted: ctx.accepted,
                 processing: ctx.processing,
                 location_processing: ctx.location_processing,
                 location_accepted: ctx.location_accepted,
+                websocket_processing: ctx.websocket_processing,
+                location_websocket_processing: ctx.location_websocket_processing,
+                tls_handshake_resumed,
+                tls_handshake_full,
+                connection_rejected,
+                connection_timeout,
                 hostname: get_hostname(),
                 physical_mem: ByteSize(physical_mem as u64).to_string_as(true),
                 physical_mem_mb: physical_mem / (1024 * 1024),
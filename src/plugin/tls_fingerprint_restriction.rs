@@ -0,0 +1,249 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{get_step_conf, get_str_conf, get_str_slice_conf, Error, ProxyPlugin, Result};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::state::State;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::StatusCode;
+use log::debug;
+use pingora::proxy::Session;
+
+pub struct TlsFingerprintRestriction {
+    plugin_step: PluginStep,
+    fingerprint_list: Vec<String>,
+    restriction_category: String,
+    forbidden_resp: HttpResponse,
+}
+
+struct TlsFingerprintRestrictionParams {
+    plugin_step: PluginStep,
+    fingerprint_list: Vec<String>,
+    restriction_category: String,
+    message: String,
+}
+
+impl TryFrom<&PluginConf> for TlsFingerprintRestrictionParams {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let step = get_step_conf(value);
+        let params = Self {
+            plugin_step: step,
+            fingerprint_list: get_str_slice_conf(value, "fingerprint_list")
+                .iter()
+                .map(|item| item.to_lowercase())
+                .collect(),
+            restriction_category: get_str_conf(value, "type"),
+            message: get_str_conf(value, "message"),
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream].contains(&params.plugin_step) {
+            return Err(Error::Invalid {
+                category: PluginCategory::TlsFingerprintRestriction.to_string(),
+                message: "Tls fingerprint restriction plugin should be executed at request or proxy upstream step".to_string(),
+            });
+        }
+        Ok(params)
+    }
+}
+
+impl TlsFingerprintRestriction {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!("new tls fingerprint restriction proxy plugin, params:{params:?}");
+        let params = TlsFingerprintRestrictionParams::try_from(params)?;
+        let mut message = params.message;
+        if message.is_empty() {
+            message = "Request is forbidden".to_string();
+        }
+        Ok(Self {
+            plugin_step: params.plugin_step,
+            fingerprint_list: params.fingerprint_list,
+            restriction_category: params.restriction_category,
+            forbidden_resp: HttpResponse {
+                status: StatusCode::FORBIDDEN,
+                body: Bytes::from(message),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl ProxyPlugin for TlsFingerprintRestriction {
+    #[inline]
+    fn step(&self) -> String {
+        self.plugin_step.to_string()
+    }
+    #[inline]
+    fn category(&self) -> PluginCategory {
+        PluginCategory::TlsFingerprintRestriction
+    }
+    #[inline]
+    async fn handle(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        // the fingerprint is missing, deny it whatever the mode is
+        if ctx.ja3.is_none() && ctx.ja4.is_none() {
+            return Ok(Some(self.forbidden_resp.clone()));
+        }
+        // match ja3 or ja4 fingerprint
+        let found = [&ctx.ja3, &ctx.ja4].iter().any(|item| {
+            if let Some(value) = item {
+                self.fingerprint_list.contains(value)
+            } else {
+                false
+            }
+        });
+        // deny fingerprint
+        let allow = if self.restriction_category == "deny" {
+            !found
+        } else {
+            found
+        };
+        if !allow {
+            return Ok(Some(self.forbidden_resp.clone()));
+        }
+        return Ok(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TlsFingerprintRestriction, TlsFingerprintRestrictionParams};
+    use crate::state::State;
+    use crate::{config::PluginConf, config::PluginStep, plugin::ProxyPlugin};
+    use http::StatusCode;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_tls_fingerprint_restriction_params() {
+        let params = TlsFingerprintRestrictionParams::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+fingerprint_list = ["1D93FA7F86B929B9B00A0181D3314D4C"]
+type = "deny"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("request", params.plugin_step.to_string());
+        assert_eq!(
+            "1d93fa7f86b929b9b00a0181d3314d4c",
+            params.fingerprint_list.join(",")
+        );
+
+        let result = TlsFingerprintRestrictionParams::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+step = "response"
+fingerprint_list = []
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!("Plugin tls_fingerprint_restriction invalid, message: Tls fingerprint restriction plugin should be executed at request or proxy upstream step", result.err().unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_tls_fingerprint_restriction() {
+        let deny = TlsFingerprintRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "deny"
+fingerprint_list = [
+    "1d93fa7f86b929b9b00a0181d3314d4c",
+    "t13d0407h2_39e807bd56df_38dbf9c86be1",
+]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("tls_fingerprint_restriction", deny.category().to_string());
+        assert_eq!("request", deny.step().to_string());
+
+        let headers = ["Accept-Encoding: gzip"].join("\r\n");
+        let input_header = format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        // no fingerprint
+        let result = deny
+            .handle(PluginStep::Request, &mut session, &mut State::default())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
+
+        let result = deny
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    ja3: Some("2d93fa7f86b929b9b00a0181d3314d4c".to_string()),
+                    ja4: Some("t13d0407h2_39e807bd56df_38dbf9c86be2".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let result = deny
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    ja4: Some("t13d0407h2_39e807bd56df_38dbf9c86be1".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, result.unwrap().status);
+
+        let allow = TlsFingerprintRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "allow"
+fingerprint_list = ["1d93fa7f86b929b9b00a0181d3314d4c"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let result = allow
+            .handle(
+                PluginStep::Request,
+                &mut session,
+                &mut State {
+                    ja3: Some("1d93fa7f86b929b9b00a0181d3314d4c".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use log::{debug, error};
+use pingora::listeners::TlsSettings;
+use pingora::tls::hash::MessageDigest;
+use pingora::tls::ssl::SslVerifyMode;
+use pingora::tls::x509::store::X509StoreBuilder;
+use pingora::tls::x509::{X509Ref, X509};
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid {message}"))]
+    Invalid { message: String },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub const CLIENT_VERIFY_OFF: &str = "off";
+pub const CLIENT_VERIFY_OPTIONAL: &str = "optional";
+pub const CLIENT_VERIFY_REQUIRED: &str = "required";
+
+/// The identity of verified client certificate,
+/// the entries of subject and sans are kept as list.
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertInfo {
+    pub subject: Vec<String>,
+    pub sans: Vec<String>,
+    pub fingerprint: String,
+}
+
+/// Get the identity of client certificate, the fingerprint is sha256 digest of certificate.
+pub fn new_client_cert_info(cert: &X509Ref) -> Option<ClientCertInfo> {
+    let digest = cert.digest(MessageDigest::sha256()).ok()?;
+    let subject = cert
+        .subject_name()
+        .entries()
+        .map(|entry| {
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|v| v.to_string())
+                .unwrap_or_default();
+            let name = entry.object().nid().short_name().unwrap_or_default();
+            format!("{name}={value}")
+        })
+        .collect::<Vec<_>>();
+    let mut sans = vec![];
+    if let Some(names) = cert.subject_alt_names() {
+        for name in names.iter() {
+            if let Some(value) = name.dnsname() {
+                sans.push(format!("DNS:{value}"));
+            } else if let Some(value) = name.uri() {
+                sans.push(format!("URI:{value}"));
+            } else if let Some(value) = name.email() {
+                sans.push(format!("email:{value}"));
+            } else if let Some(value) = name.ipaddress() {
+                let ip = match value.len() {
+                    4 => <[u8; 4]>::try_from(value)
+                        .map(|v| std::net::IpAddr::from(v).to_string())
+                        .ok(),
+                    16 => <[u8; 16]>::try_from(value)
+                        .map(|v| std::net::IpAddr::from(v).to_string())
+                        .ok(),
+                    _ => None,
+                };
+                if let Some(ip) = ip {
+                    sans.push(format!("IP:{ip}"));
+                }
+            }
+        }
+    }
+    Some(ClientCertInfo {
+        subject,
+        sans,
+        fingerprint: hex::encode(digest),
+    })
+}
+
+/// Set the client certificate verification of tls settings,
+/// the identity of verified certificate is derived from the peer certificate of connection.
+pub fn set_client_cert_verify(
+    tls_settings: &mut TlsSettings,
+    client_ca: &[u8],
+    verify_mode: &str,
+) -> Result<()> {
+    let mode = match verify_mode {
+        CLIENT_VERIFY_OFF => return Ok(()),
+        CLIENT_VERIFY_OPTIONAL => SslVerifyMode::PEER,
+        _ => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+    };
+    let certs = X509::stack_from_pem(client_ca).map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
+    if certs.is_empty() {
+        return Err(Error::Invalid {
+            message: "Client ca is empty".to_string(),
+        });
+    }
+    let mut builder = X509StoreBuilder::new().map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
+    for cert in certs.iter() {
+        builder.add_cert(cert.clone()).map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+        // send the acceptable ca names to client
+        if let Err(e) = tls_settings.add_client_ca(cert) {
+            error!("Add client ca fail, error: {e}");
+        }
+    }
+    tls_settings
+        .set_verify_cert_store(builder.build())
+        .map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+    tls_settings.set_verify_callback(mode, |ok, ctx| {
+        if !ok {
+            debug!("Verify client certificate fail, error: {}", ctx.error());
+        }
+        ok
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::new_client_cert_info;
+    use pingora::tls::x509::X509;
+    use pretty_assertions::assert_eq;
+    use rcgen::{Certificate, CertificateParams, DnType, SanType};
+
+    #[test]
+    fn test_client_cert_info() {
+        let mut params = CertificateParams::new(vec!["svc.mesh.local".to_string()]);
+        params.distinguished_name.push(DnType::CommonName, "svc-a");
+        params
+            .subject_alt_names
+            .push(SanType::URI("spiffe://mesh/svc-a".to_string()));
+        let cert = Certificate::from_params(params).unwrap();
+        let cert = X509::from_pem(cert.serialize_pem().unwrap().as_bytes()).unwrap();
+
+        let info = new_client_cert_info(&cert).unwrap();
+        assert_eq!(64, info.fingerprint.len());
+        assert_eq!(vec!["CN=svc-a".to_string()], info.subject);
+        assert_eq!(
+            vec!["DNS:svc.mesh.local", "URI:spiffe://mesh/svc-a"],
+            info.sans
+        );
+    }
+}
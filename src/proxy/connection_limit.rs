@@ -0,0 +1,467 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use log::debug;
+use once_cell::sync::Lazy;
+use pingora::apps::ServerApp;
+use pingora::protocols::{Digest, GetSocketDigest, SocketDigest, Stream};
+use pingora::server::ShutdownWatch;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static CONNECTION_REJECTED: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(0));
+static CONNECTION_TIMEOUT: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(0));
+
+/// The request activity of downstream connection, it's used for checking
+/// the header read timeout of every request, the requests of http2 are
+/// processed in other tasks, so it's shared by the socket digest.
+#[derive(Debug)]
+struct ConnectionActivity {
+    created_at: Instant,
+    // the count of requests whose header is read and not finished
+    processing: AtomicUsize,
+    // the milliseconds from created time to the last finished request
+    idle_since: AtomicU64,
+}
+
+impl ConnectionActivity {
+    fn new() -> Self {
+        Self {
+            created_at: Instant::now(),
+            processing: AtomicUsize::new(0),
+            idle_since: AtomicU64::new(0),
+        }
+    }
+    fn header_read(&self) {
+        self.processing.fetch_add(1, Ordering::Relaxed);
+    }
+    fn request_done(&self) {
+        let _ = self
+            .processing
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                value.checked_sub(1)
+            });
+        self.idle_since.store(
+            self.created_at.elapsed().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+    }
+    /// Returns the remaining time of reading next request header,
+    /// it's none if there are requests in processing.
+    fn header_read_remaining(&self, timeout: Duration) -> Option<Duration> {
+        if self.processing.load(Ordering::Relaxed) != 0 {
+            return None;
+        }
+        let idle_since = Duration::from_millis(self.idle_since.load(Ordering::Relaxed));
+        let idle = self.created_at.elapsed().saturating_sub(idle_since);
+        Some(timeout.saturating_sub(idle))
+    }
+}
+
+// the address of socket digest -> (socket digest, connection activity),
+// the socket digest is kept, so its address can't be reused before removed
+type ConnectionActivities = HashMap<usize, (Arc<SocketDigest>, Arc<ConnectionActivity>)>;
+static CONNECTION_ACTIVITIES: Lazy<Mutex<ConnectionActivities>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct ConnectionActivityGuard {
+    key: usize,
+}
+
+impl Drop for ConnectionActivityGuard {
+    fn drop(&mut self) {
+        CONNECTION_ACTIVITIES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+    }
+}
+
+fn register_connection_activity(
+    socket_digest: Arc<SocketDigest>,
+) -> (ConnectionActivityGuard, Arc<ConnectionActivity>) {
+    let key = Arc::as_ptr(&socket_digest) as usize;
+    let activity = Arc::new(ConnectionActivity::new());
+    CONNECTION_ACTIVITIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key, (socket_digest, activity.clone()));
+    (ConnectionActivityGuard { key }, activity)
+}
+
+fn get_connection_activity(digest: Option<&Digest>) -> Option<Arc<ConnectionActivity>> {
+    let socket_digest = digest?.socket_digest.as_ref()?;
+    let key = Arc::as_ptr(socket_digest) as usize;
+    CONNECTION_ACTIVITIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+        .map(|(_, activity)| activity.clone())
+}
+
+/// Wait until the connection has no request in processing and
+/// the next request header is not read in time.
+async fn wait_header_read_timeout(activity: &ConnectionActivity, timeout: Duration) {
+    loop {
+        match activity.header_read_remaining(timeout) {
+            Some(remaining) if remaining.is_zero() => return,
+            Some(remaining) => tokio::time::sleep(remaining).await,
+            // check again after the timeout if the requests are processing
+            None => tokio::time::sleep(timeout).await,
+        }
+    }
+}
+
+/// Process the connection and close it if the request header is not read in time.
+/// Returns false if the connection is closed because of timeout.
+async fn process_with_header_read_timeout<F>(
+    process: F,
+    activity: &ConnectionActivity,
+    timeout: Duration,
+) -> bool
+where
+    F: Future<Output = ()>,
+{
+    tokio::select! {
+        _ = process => true,
+        _ = wait_header_read_timeout(activity, timeout) => false,
+    }
+}
+
+/// Get the count of rejected and timed out downstream connections.
+pub fn get_connection_stats() -> (u64, u64) {
+    (
+        CONNECTION_REJECTED.load(Ordering::Relaxed),
+        CONNECTION_TIMEOUT.load(Ordering::Relaxed),
+    )
+}
+
+/// Increase the count of timed out downstream connections,
+/// it's used for the body read timeout and the slow transfer rate.
+pub fn inc_connection_timeout() {
+    CONNECTION_TIMEOUT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Mark the request header of connection has been read,
+/// the header read timeout is paused until the request is done.
+pub fn set_header_read(digest: Option<&Digest>) {
+    if let Some(activity) = get_connection_activity(digest) {
+        activity.header_read();
+    }
+}
+
+/// Mark the request of connection is done,
+/// the header read timeout of next request starts from now.
+pub fn set_request_done(digest: Option<&Digest>) {
+    if let Some(activity) = get_connection_activity(digest) {
+        activity.request_done();
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ConnectionLimitOptions {
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub header_read_timeout: Option<Duration>,
+}
+
+/// The server app which limits the downstream connections before
+/// the http processing of the wrapped app.
+pub struct ConnectionLimitApp<A> {
+    name: String,
+    app: Arc<A>,
+    options: ConnectionLimitOptions,
+    connections: AtomicUsize,
+    ip_connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+struct ConnectionGuard<'a, A> {
+    limit: &'a ConnectionLimitApp<A>,
+    ip: Option<IpAddr>,
+}
+
+impl<A> Drop for ConnectionGuard<'_, A> {
+    fn drop(&mut self) {
+        self.limit.connections.fetch_sub(1, Ordering::Relaxed);
+        if let Some(ip) = &self.ip {
+            let mut ip_connections = self
+                .limit
+                .ip_connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if let Some(count) = ip_connections.get_mut(ip) {
+                *count -= 1;
+                if *count == 0 {
+                    ip_connections.remove(ip);
+                }
+            }
+        }
+    }
+}
+
+impl<A> ConnectionLimitApp<A> {
+    pub fn new(name: &str, app: A, options: ConnectionLimitOptions) -> Self {
+        Self {
+            name: name.to_string(),
+            app: Arc::new(app),
+            options,
+            connections: AtomicUsize::new(0),
+            ip_connections: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Get the count of current downstream connections.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+    /// Acquire a connection of the ip, returns none if it exceeds the limit.
+    /// The connection will be released when the guard is dropped.
+    fn acquire(&self, ip: Option<IpAddr>) -> Option<ConnectionGuard<'_, A>> {
+        let count = self.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut guard = ConnectionGuard {
+            limit: self,
+            ip: None,
+        };
+        if count > self.options.max_connections.unwrap_or(usize::MAX) {
+            return None;
+        }
+        if let (Some(ip), Some(max)) = (ip, self.options.max_connections_per_ip) {
+            let mut ip_connections = self
+                .ip_connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            let count = ip_connections.entry(ip).or_insert(0);
+            if *count >= max {
+                return None;
+            }
+            *count += 1;
+            guard.ip = Some(ip);
+        }
+        Some(guard)
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for ConnectionLimitApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        let ip = stream.get_socket_digest().and_then(|digest| {
+            digest
+                .peer_addr()
+                .and_then(|addr| addr.as_inet())
+                .map(|addr| addr.ip())
+        });
+        let guard = self.acquire(ip);
+        if guard.is_none() {
+            debug!("Server({}) rejects connection of {ip:?}", self.name);
+            CONNECTION_REJECTED.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        // the activity is shared with the requests of connection by socket digest
+        let activity = if self.options.header_read_timeout.is_some() {
+            stream.get_socket_digest().map(register_connection_activity)
+        } else {
+            None
+        };
+        let process = async {
+            let mut reuse = self.app.process_new(stream, shutdown).await;
+            // the idle time of reused connection is also limited by header read timeout
+            while let Some(stream) = reuse {
+                reuse = self.app.process_new(stream, shutdown).await;
+            }
+        };
+        if let (Some(timeout), Some((_guard, activity))) =
+            (self.options.header_read_timeout, activity)
+        {
+            if !process_with_header_read_timeout(process, &activity, timeout).await {
+                debug!("Server({}) read header of {ip:?} timeout", self.name);
+                CONNECTION_TIMEOUT.fetch_add(1, Ordering::Relaxed);
+            }
+        } else {
+            process.await;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_connection_activity, get_connection_stats, process_with_header_read_timeout,
+        register_connection_activity, set_header_read, set_request_done, ConnectionActivity,
+        ConnectionLimitApp, ConnectionLimitOptions,
+    };
+    use async_trait::async_trait;
+    use pingora::apps::ServerApp;
+    use pingora::protocols::{Digest, SocketDigest, Stream};
+    use pingora::server::ShutdownWatch;
+    use pretty_assertions::assert_eq;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_test::io::Builder;
+
+    /// The app returns the stream for reuse until the count is reached.
+    struct ReuseApp {
+        count: AtomicUsize,
+        max: usize,
+    }
+
+    #[async_trait]
+    impl ServerApp for ReuseApp {
+        async fn process_new(
+            self: &Arc<Self>,
+            stream: Stream,
+            _shutdown: &ShutdownWatch,
+        ) -> Option<Stream> {
+            let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+            if count < self.max {
+                Some(stream)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_process_new() {
+        let (_tx, shutdown) = tokio::sync::watch::channel(false);
+        let limit = Arc::new(ConnectionLimitApp::new(
+            "test",
+            ReuseApp {
+                count: AtomicUsize::new(0),
+                max: 3,
+            },
+            ConnectionLimitOptions {
+                max_connections: Some(1),
+                header_read_timeout: Some(Duration::from_secs(1)),
+                ..Default::default()
+            },
+        ));
+        // the reused stream is processed until closed
+        let stream: Stream = Box::new(Builder::new().build());
+        assert_eq!(true, limit.process_new(stream, &shutdown).await.is_none());
+        assert_eq!(3, limit.app.count.load(Ordering::Relaxed));
+        assert_eq!(0, limit.connections());
+
+        // exceeds the limit of server
+        let (rejected, _) = get_connection_stats();
+        let guard = limit.acquire(None);
+        let stream: Stream = Box::new(Builder::new().build());
+        assert_eq!(true, limit.process_new(stream, &shutdown).await.is_none());
+        assert_eq!(3, limit.app.count.load(Ordering::Relaxed));
+        assert_eq!(true, get_connection_stats().0 > rejected);
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn test_header_read_timeout() {
+        let socket_digest = Arc::new(SocketDigest::from_raw_fd(1));
+        let digest = Digest {
+            socket_digest: Some(socket_digest.clone()),
+            ..Default::default()
+        };
+        let (guard, activity) = register_connection_activity(socket_digest);
+        let timeout = Duration::from_millis(50);
+
+        // the header of first request is not read
+        let done = process_with_header_read_timeout(
+            tokio::time::sleep(Duration::from_secs(1)),
+            &activity,
+            timeout,
+        )
+        .await;
+        assert_eq!(false, done);
+
+        // the request is processing
+        set_header_read(Some(&digest));
+        assert_eq!(1, activity.processing.load(Ordering::Relaxed));
+        let done = process_with_header_read_timeout(
+            tokio::time::sleep(Duration::from_millis(120)),
+            &activity,
+            timeout,
+        )
+        .await;
+        assert_eq!(true, done);
+
+        // the header of keepalive request is not read
+        set_request_done(Some(&digest));
+        assert_eq!(0, activity.processing.load(Ordering::Relaxed));
+        let done = process_with_header_read_timeout(
+            tokio::time::sleep(Duration::from_secs(1)),
+            &activity,
+            timeout,
+        )
+        .await;
+        assert_eq!(false, done);
+
+        drop(guard);
+        assert_eq!(true, get_connection_activity(Some(&digest)).is_none());
+
+        let activity = ConnectionActivity::new();
+        // the count of processing is not less than 0
+        activity.request_done();
+        assert_eq!(0, activity.processing.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let limit = ConnectionLimitApp::new(
+            "test",
+            (),
+            ConnectionLimitOptions {
+                max_connections: Some(3),
+                max_connections_per_ip: Some(2),
+                ..Default::default()
+            },
+        );
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "192.168.1.1".parse().unwrap();
+
+        let first = limit.acquire(Some(ip));
+        assert_eq!(true, first.is_some());
+        let second = limit.acquire(Some(ip));
+        assert_eq!(true, second.is_some());
+        // exceeds the limit of ip
+        assert_eq!(true, limit.acquire(Some(ip)).is_none());
+        assert_eq!(2, limit.connections());
+
+        let third = limit.acquire(Some(other_ip));
+        assert_eq!(true, third.is_some());
+        // exceeds the limit of server
+        assert_eq!(true, limit.acquire(None).is_none());
+        assert_eq!(3, limit.connections());
+
+        drop(first);
+        assert_eq!(2, limit.connections());
+        assert_eq!(true, limit.acquire(Some(ip)).is_some());
+
+        drop(second);
+        drop(third);
+        assert_eq!(0, limit.connections());
+        assert_eq!(true, limit.ip_connections.lock().unwrap().is_empty());
+    }
+}
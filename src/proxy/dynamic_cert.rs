@@ -12,44 +12,551 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::acme::{
+    get_cert_info, get_self_signed_cert, get_self_signed_name, get_tls_alpn_challenge_cert,
+};
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
-use log::debug;
+use log::{debug, error};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use pingora::listeners::TlsSettings;
 use pingora::tls::ext;
+use pingora::tls::hash::MessageDigest;
+use pingora::tls::nid::Nid;
 use pingora::tls::pkey::{PKey, Private};
-use pingora::tls::x509::X509;
+use pingora::tls::ssl::{select_next_proto, AlpnError, NameType, SslRef};
+use pingora::tls::x509::{X509Ref, X509};
+use serde::Serialize;
 use snafu::Snafu;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Invalid {message}"))]
     Invalid { message: String },
+    #[snafu(display("Io error {source}, {file}"))]
+    Io {
+        source: std::io::Error,
+        file: String,
+    },
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[derive(Debug, Clone)]
-pub struct DynamicCert {
+// the alpn protocol of acme tls-alpn-01 challenge(RFC8737)
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+// the host of self signed certificate for the handshake without sni
+const SELF_SIGNED_DEFAULT_HOST: &str = "localhost";
+
+#[derive(Debug)]
+struct TlsCertificate {
     cert: X509,
+    chain: Vec<X509>,
     key: PKey<Private>,
+    domains: Vec<String>,
+    // sha256 digest(hex) of certificate
+    fingerprint: String,
 }
 
-impl DynamicCert {
-    pub fn new(cert: &[u8], key: &[u8]) -> Result<Box<Self>> {
-        let cert = X509::from_pem(cert).map_err(|e| Error::Invalid {
+/// Get the dns names of certificate from subject alternative names,
+/// if it is empty, get the common name of subject.
+fn get_cert_domains(cert: &X509) -> Vec<String> {
+    let mut domains = vec![];
+    if let Some(names) = cert.subject_alt_names() {
+        for name in names.iter() {
+            if let Some(dns) = name.dnsname() {
+                domains.push(dns.to_lowercase());
+            }
+        }
+    }
+    if domains.is_empty() {
+        for entry in cert.subject_name().entries_by_nid(Nid::COMMONNAME) {
+            if let Ok(value) = entry.data().as_utf8() {
+                domains.push(value.to_string().to_lowercase());
+            }
+        }
+    }
+    domains
+}
+
+/// Get the sha256 digest(hex) of certificate.
+pub fn get_cert_fingerprint(cert: &X509Ref) -> String {
+    cert.digest(MessageDigest::sha256())
+        .map(hex::encode)
+        .unwrap_or_default()
+}
+
+impl TlsCertificate {
+    fn new(cert: &[u8], key: &[u8]) -> Result<Self> {
+        let mut certs = X509::stack_from_pem(cert).map_err(|e| Error::Invalid {
             message: e.to_string(),
         })?;
+        if certs.is_empty() {
+            return Err(Error::Invalid {
+                message: "Certificate is empty".to_string(),
+            });
+        }
+        let cert = certs.remove(0);
         let key = PKey::private_key_from_pem(key).map_err(|e| Error::Invalid {
             message: e.to_string(),
         })?;
-        Ok(Box::new(DynamicCert { cert, key }))
+        let domains = get_cert_domains(&cert);
+        let fingerprint = get_cert_fingerprint(&cert);
+        Ok(Self {
+            cert,
+            chain: certs,
+            key,
+            domains,
+            fingerprint,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ServerCertificates {
+    // the default certificate, it is used when no sni or no matched certificate
+    default: Arc<TlsCertificate>,
+    // exact domain -> certificate
+    domains: HashMap<String, Arc<TlsCertificate>>,
+    // wildcard domain(without `*.`) -> certificate
+    wildcard_domains: HashMap<String, Arc<TlsCertificate>>,
+}
+
+type CertificateStore = HashMap<String, Arc<ServerCertificates>>;
+static CERTIFICATE_STORE: Lazy<ArcSwap<CertificateStore>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+/// Update the certificates of server in the store,
+/// the new certificates will be used for the next tls handshake.
+pub fn try_update_certificates(name: &str, certificates: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+    let value = ServerCertificates::new(certificates)?;
+    let mut store: CertificateStore = CERTIFICATE_STORE.load().as_ref().clone();
+    store.insert(name.to_string(), Arc::new(value));
+    CERTIFICATE_STORE.store(Arc::new(store));
+    Ok(())
+}
+
+/// Get the certificates of server, the first one is the default certificate.
+/// Each item is (fingerprint, certificate, issuer certificate).
+pub fn get_certificates(name: &str) -> Vec<(String, X509, Option<X509>)> {
+    let mut certificates = vec![];
+    if let Some(value) = CERTIFICATE_STORE.load().get(name) {
+        let mut fingerprints = vec![];
+        let mut list = vec![value.default.clone()];
+        list.extend(value.domains.values().cloned());
+        list.extend(value.wildcard_domains.values().cloned());
+        for item in list {
+            if fingerprints.contains(&item.fingerprint) {
+                continue;
+            }
+            fingerprints.push(item.fingerprint.clone());
+            certificates.push((
+                item.fingerprint.clone(),
+                item.cert.clone(),
+                item.chain.first().cloned(),
+            ));
+        }
+    }
+    certificates
+}
+
+/// The information of loaded certificate, it's used for admin api.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CertificateInfo {
+    pub server: String,
+    pub fingerprint: String,
+    pub domains: Vec<String>,
+    pub issuer: String,
+    pub not_before: i64,
+    pub not_after: i64,
+    // static or acme
+    pub source: String,
+}
+
+/// Get the information of all loaded certificates, it's sorted by server name.
+pub fn get_certificate_info_list() -> Vec<CertificateInfo> {
+    let mut names: Vec<String> = CERTIFICATE_STORE.load().keys().cloned().collect();
+    names.sort();
+    let mut list = vec![];
+    for name in names {
+        for (fingerprint, cert, _) in get_certificates(&name) {
+            let mut info = CertificateInfo {
+                server: name.clone(),
+                fingerprint,
+                domains: get_cert_domains(&cert),
+                source: "static".to_string(),
+                ..Default::default()
+            };
+            let pem = cert.to_pem().unwrap_or_default();
+            if let Ok(cert_info) = get_cert_info(&pem) {
+                info.issuer = cert_info.issuer;
+                info.not_before = cert_info.not_before;
+                info.not_after = cert_info.not_after;
+            }
+            list.push(info);
+        }
+    }
+    list
+}
+
+/// Validate the certificate and its private key, the key should match the certificate.
+pub fn validate_certificate(cert: &[u8], key: &[u8]) -> Result<()> {
+    let cert = TlsCertificate::new(cert, key)?;
+    let public_key = cert.cert.public_key().map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
+    if !public_key.public_eq(&cert.key) {
+        return Err(Error::Invalid {
+            message: "Private key does not match the certificate".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Check whether the certificates of server are in the store.
+pub fn has_certificates(name: &str) -> bool {
+    CERTIFICATE_STORE.load().contains_key(name)
+}
+
+// the max count of self signed certificates in memory
+const SELF_SIGNED_CACHE_SIZE: usize = 128;
+
+// the self signed certificates of host, the least recently used one is evicted
+static SELF_SIGNED_CERTIFICATES: Lazy<Mutex<LruCache<String, Arc<TlsCertificate>>>> =
+    Lazy::new(|| {
+        Mutex::new(LruCache::new(
+            NonZeroUsize::new(SELF_SIGNED_CACHE_SIZE).unwrap(),
+        ))
+    });
+
+/// Get the self signed certificate of host, only localhost and the configured
+/// domains are allowed. It's generated in blocking thread if not exists,
+/// so the handshakes of other connections are not blocked.
+async fn get_self_signed_certificate(host: &str) -> Option<Arc<TlsCertificate>> {
+    let host = host.to_lowercase();
+    if let Some(cert) = SELF_SIGNED_CERTIFICATES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&host)
+    {
+        return Some(cert.clone());
+    }
+    if get_self_signed_name(&host).is_none() {
+        debug!("Host is not allowed for self signed certificate, host: {host}");
+        return None;
+    }
+    let value = host.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        get_self_signed_cert(&value)
+            .map_err(|e| e.to_string())
+            .and_then(|(cert, key)| TlsCertificate::new(&cert, &key).map_err(|e| e.to_string()))
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    match result {
+        Ok(cert) => {
+            let cert = Arc::new(cert);
+            SELF_SIGNED_CERTIFICATES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .put(host, cert.clone());
+            Some(cert)
+        }
+        Err(e) => {
+            error!("Get self signed certificate fail, host: {host}, error: {e}");
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DynamicCert {
+    name: String,
+    // generate the self signed certificate for sni
+    self_signed: bool,
+}
+
+/// Load the certificates from directory, the certificate file should be `.crt` or `.pem`,
+/// and the key file should be the same name with `.key` extension.
+pub fn load_certificates_from_dir(dir: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::Io {
+        source: e,
+        file: dir.to_string(),
+    })?;
+    let mut files = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let ext = path
+            .extension()
+            .map(|item| item.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if ext != "crt" && ext != "pem" {
+            continue;
+        }
+        let key_file = path.with_extension("key");
+        if !Path::new(&key_file).exists() {
+            debug!("Key of certificate {path:?} is not found");
+            continue;
+        }
+        files.push((path, key_file));
+    }
+    // sort by file name, make the default certificate stable
+    files.sort();
+    let mut certificates = vec![];
+    for (cert_file, key_file) in files {
+        let cert = std::fs::read(&cert_file).map_err(|e| Error::Io {
+            source: e,
+            file: cert_file.to_string_lossy().to_string(),
+        })?;
+        let key = std::fs::read(&key_file).map_err(|e| Error::Io {
+            source: e,
+            file: key_file.to_string_lossy().to_string(),
+        })?;
+        certificates.push((cert, key));
+    }
+    Ok(certificates)
+}
+
+impl ServerCertificates {
+    /// Create the server certificates from the certificate list,
+    /// the first one of the list is the default certificate.
+    fn new(certificates: &[(Vec<u8>, Vec<u8>)]) -> Result<Self> {
+        let mut default = None;
+        let mut domains = HashMap::new();
+        let mut wildcard_domains = HashMap::new();
+        for (cert, key) in certificates.iter() {
+            let cert = Arc::new(TlsCertificate::new(cert, key)?);
+            for domain in cert.domains.iter() {
+                if let Some(value) = domain.strip_prefix("*.") {
+                    wildcard_domains
+                        .entry(value.to_string())
+                        .or_insert(cert.clone());
+                } else {
+                    domains.entry(domain.to_string()).or_insert(cert.clone());
+                }
+            }
+            if default.is_none() {
+                default = Some(cert);
+            }
+        }
+        let default = default.ok_or(Error::Invalid {
+            message: "Certificate list is empty".to_string(),
+        })?;
+        Ok(ServerCertificates {
+            default,
+            domains,
+            wildcard_domains,
+        })
+    }
+    /// Find the certificate which matches the sni, exact match first, then wildcard.
+    fn find(&self, sni: Option<&str>) -> Option<&TlsCertificate> {
+        let sni = sni?.to_lowercase();
+        if let Some(cert) = self.domains.get(&sni) {
+            return Some(cert.as_ref());
+        }
+        let (_, parent) = sni.split_once('.')?;
+        self.wildcard_domains.get(parent).map(|cert| cert.as_ref())
+    }
+    /// Select the certificate by sni, and fall back to the default certificate.
+    fn select(&self, sni: Option<&str>) -> &TlsCertificate {
+        self.find(sni).unwrap_or(self.default.as_ref())
+    }
+}
+
+impl DynamicCert {
+    /// Create a dynamic cert of server, the certificates are saved in the store
+    /// and they can be replaced without restart.
+    /// The certificates may be empty if the server only uses self signed certificates.
+    pub fn new(name: &str, certificates: &[(Vec<u8>, Vec<u8>)]) -> Result<Box<Self>> {
+        if !certificates.is_empty() {
+            try_update_certificates(name, certificates)?;
+        }
+        Ok(Box::new(DynamicCert {
+            name: name.to_string(),
+            self_signed: false,
+        }))
+    }
+    /// Enable the self signed certificate, each host has its own certificate
+    /// which is signed by the development ca.
+    pub fn enable_self_signed(&mut self) {
+        self.self_signed = true;
+    }
+}
+
+/// Set the alpn select callback which supports `acme-tls/1` protocol,
+/// it replaces the callback of h2, so h2 and http/1.1 are also supported.
+pub fn set_acme_tls_alpn(tls_settings: &mut TlsSettings, enabled_h2: bool) {
+    // the protocols in wire format, acme-tls/1 is only offered by acme server
+    let mut protocols = b"\x0aacme-tls/1".to_vec();
+    if enabled_h2 {
+        protocols.extend_from_slice(b"\x02h2");
+    }
+    protocols.extend_from_slice(b"\x08http/1.1");
+    tls_settings.set_alpn_select_callback(move |_, client_protocols| {
+        select_next_proto(&protocols, client_protocols).ok_or(AlpnError::NOACK)
+    });
+}
+
+fn use_certificate(ssl: &mut SslRef, cert: &TlsCertificate) {
+    if let Err(e) = ext::ssl_use_certificate(ssl, &cert.cert) {
+        error!("Use certificate fail, error: {e}");
+    }
+    if let Err(e) = ext::ssl_use_private_key(ssl, &cert.key) {
+        error!("Use private key fail, error: {e}");
+    }
+    for item in cert.chain.iter() {
+        if let Err(e) = ext::ssl_add_chain_cert(ssl, item) {
+            error!("Add chain certificate fail, error: {e}");
+        }
     }
 }
 
 #[async_trait]
 impl pingora::listeners::TlsAccept for DynamicCert {
-    async fn certificate_callback(&self, ssl: &mut pingora::tls::ssl::SslRef) {
-        // TODO add more debug log
-        ext::ssl_use_certificate(ssl, &self.cert).unwrap();
-        ext::ssl_use_private_key(ssl, &self.key).unwrap();
-        debug!("ssl: {ssl:?}");
+    async fn certificate_callback(&self, ssl: &mut SslRef) {
+        let sni = ssl
+            .servername(NameType::HOST_NAME)
+            .map(|item| item.to_string());
+        // the alpn is selected before certificate callback
+        if ssl.selected_alpn_protocol() == Some(ACME_TLS_ALPN) {
+            let sni = sni.unwrap_or_default();
+            debug!("Acme tls alpn challenge, sni: {sni}");
+            if let Some((cert, key)) = get_tls_alpn_challenge_cert(&sni).await {
+                match TlsCertificate::new(&cert, &key) {
+                    Ok(cert) => use_certificate(ssl, &cert),
+                    Err(e) => error!("Parse tls alpn challenge fail, error: {e}"),
+                };
+            }
+            return;
+        }
+        let server_certificates = CERTIFICATE_STORE.load().get(&self.name).cloned();
+        // the configured certificates take precedence over the self signed ones
+        if let Some(cert) = server_certificates
+            .as_ref()
+            .and_then(|item| item.find(sni.as_deref()))
+        {
+            debug!("Sni: {sni:?}, certificate domains: {:?}", cert.domains);
+            use_certificate(ssl, cert);
+            return;
+        }
+        if self.self_signed {
+            // the certificate of localhost is used if there is no sni
+            let host = sni.as_deref().unwrap_or(SELF_SIGNED_DEFAULT_HOST);
+            if let Some(cert) = get_self_signed_certificate(host).await {
+                debug!("Sni: {sni:?}, self signed certificate");
+                use_certificate(ssl, &cert);
+                return;
+            }
+        }
+        if let Some(server_certificates) = &server_certificates {
+            let cert = server_certificates.select(sni.as_deref());
+            debug!("Sni: {sni:?}, certificate domains: {:?}", cert.domains);
+            use_certificate(ssl, cert);
+            return;
+        }
+        // the handshake fails without certificate
+        debug!("Sni: {sni:?}, no certificate is matched");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_certificate_info_list, get_certificates, has_certificates, try_update_certificates,
+        validate_certificate, ServerCertificates, CERTIFICATE_STORE,
+    };
+    use pretty_assertions::assert_eq;
+    use rcgen::{Certificate, CertificateParams};
+
+    fn new_certificate(domains: &[&str]) -> (Vec<u8>, Vec<u8>) {
+        let params = CertificateParams::new(
+            domains
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>(),
+        );
+        let cert = Certificate::from_params(params).unwrap();
+        (
+            cert.serialize_pem().unwrap().into_bytes(),
+            cert.serialize_private_key_pem().into_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_dynamic_cert_select() {
+        let cert = ServerCertificates::new(&[
+            new_certificate(&["pingap.io"]),
+            new_certificate(&["*.pingap.io"]),
+            new_certificate(&["api.pingap.io", "admin.pingap.io"]),
+        ])
+        .unwrap();
+
+        assert_eq!(vec!["pingap.io"], cert.select(None).domains);
+        assert_eq!(vec!["pingap.io"], cert.select(Some("github.com")).domains);
+        // the default certificate is not used for find
+        assert_eq!(true, cert.find(None).is_none());
+        assert_eq!(true, cert.find(Some("github.com")).is_none());
+        assert_eq!(
+            vec!["*.pingap.io"],
+            cert.find(Some("www.pingap.io")).unwrap().domains
+        );
+        assert_eq!(
+            vec!["api.pingap.io", "admin.pingap.io"],
+            cert.select(Some("Admin.Pingap.io")).domains
+        );
+        assert_eq!(
+            vec!["*.pingap.io"],
+            cert.select(Some("www.pingap.io")).domains
+        );
+        assert_eq!(
+            vec!["pingap.io"],
+            cert.select(Some("a.b.pingap.io")).domains
+        );
+
+        assert_eq!(
+            "Invalid Certificate list is empty",
+            ServerCertificates::new(&[]).err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_update_certificates() {
+        assert_eq!(false, has_certificates("pingap"));
+        try_update_certificates("pingap", &[new_certificate(&["pingap.io"])]).unwrap();
+        assert_eq!(true, has_certificates("pingap"));
+        let certificates = get_certificates("pingap");
+        assert_eq!(1, certificates.len());
+        assert_eq!(64, certificates[0].0.len());
+        assert_eq!(true, certificates[0].2.is_none());
+        try_update_certificates("pingap", &[new_certificate(&["github.com"])]).unwrap();
+        let store = CERTIFICATE_STORE.load();
+        assert_eq!(
+            vec!["github.com"],
+            store.get("pingap").unwrap().select(None).domains
+        );
+
+        let list = get_certificate_info_list();
+        let info = list.iter().find(|item| item.server == "pingap").unwrap();
+        assert_eq!(vec!["github.com"], info.domains);
+        assert_eq!("static", info.source);
+        assert_eq!(true, info.not_after > info.not_before);
+    }
+
+    #[test]
+    fn test_validate_certificate() {
+        let (cert, key) = new_certificate(&["pingap.io"]);
+        assert_eq!(true, validate_certificate(&cert, &key).is_ok());
+        let (_, other_key) = new_certificate(&["pingap.io"]);
+        assert_eq!(
+            "Invalid Private key does not match the certificate",
+            validate_certificate(&cert, &other_key)
+                .err()
+                .unwrap()
+                .to_string()
+        );
     }
 }
@@ -0,0 +1,280 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::location::get_location;
+use super::server::get_server_locations;
+use crate::config::{PingapConf, PluginConf};
+use crate::plugin::get_builtin_proxy_plugins;
+use crate::util;
+use http::Method;
+use pingora::http::RequestHeader;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid error {message}"))]
+    Invalid { message: String },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RouteExplainParams {
+    pub server: String,
+    pub host: Option<String>,
+    pub path: String,
+    pub method: Option<String>,
+    pub headers: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RouteCandidate {
+    pub name: String,
+    pub weight: u16,
+    pub path: String,
+    pub host: String,
+    pub matched: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RoutePlugin {
+    pub name: String,
+    pub category: String,
+    pub step: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RouteUpstream {
+    pub name: String,
+    pub algo: String,
+    pub addrs: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RouteExplain {
+    pub server: String,
+    pub host: String,
+    pub path: String,
+    pub method: String,
+    pub candidates: Vec<RouteCandidate>,
+    pub matched: Option<String>,
+    pub rewrite_path: Option<String>,
+    pub plugins: Vec<RoutePlugin>,
+    pub upstream: Option<RouteUpstream>,
+}
+
+fn get_plugin_conf(conf: &PingapConf, name: &str) -> Option<PluginConf> {
+    if let Some(plugin) = conf.plugins.get(name) {
+        return Some(plugin.clone());
+    }
+    get_builtin_proxy_plugins()
+        .into_iter()
+        .find(|(builtin_name, _)| builtin_name == name)
+        .map(|(_, plugin)| plugin)
+}
+
+fn get_plugin_value(conf: &Option<PluginConf>, key: &str, default_value: &str) -> String {
+    conf.as_ref()
+        .and_then(|conf| conf.get(key))
+        .and_then(|value| value.as_str())
+        .unwrap_or(default_value)
+        .to_string()
+}
+
+/// Build the request header from params, the host is set as host header.
+fn new_request_header(params: &RouteExplainParams) -> Result<RequestHeader> {
+    let method = params.method.clone().unwrap_or("GET".to_string());
+    let method =
+        Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+    let mut req_header =
+        RequestHeader::build(method, params.path.as_bytes(), None).map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+    for header in params.headers.clone().unwrap_or_default().iter() {
+        let (name, value) = header.split_once(':').ok_or(Error::Invalid {
+            message: format!("header({header}) is invalid"),
+        })?;
+        req_header
+            .append_header(name.trim().to_string(), value.trim())
+            .map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })?;
+    }
+    if let Some(host) = &params.host {
+        req_header
+            .insert_header(http::header::HOST, host)
+            .map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })?;
+    }
+    Ok(req_header)
+}
+
+/// Explain how the request will be routed by the running server,
+/// returns the ordered candidate locations, the matched location,
+/// the plugins and the upstream of it.
+/// The candidates are taken from the running router and locations,
+/// the running config is used for the plugins and upstream.
+pub fn explain_route(conf: &PingapConf, params: &RouteExplainParams) -> Result<RouteExplain> {
+    let locations = get_server_locations(&params.server).ok_or(Error::Invalid {
+        message: format!("server({}) is not found", params.server),
+    })?;
+    let mut req_header = new_request_header(params)?;
+    let host = util::get_host(&req_header).unwrap_or_default().to_string();
+    let path = req_header.uri.path().to_string();
+    let mut result = RouteExplain {
+        server: params.server.clone(),
+        host: host.clone(),
+        path: path.clone(),
+        method: req_header.method.to_string(),
+        ..Default::default()
+    };
+
+    let mut matched_location = None;
+    for (name, weight) in locations.iter() {
+        let lo = if let Some(lo) = get_location(name) {
+            lo
+        } else {
+            continue;
+        };
+        let matched = matched_location.is_none() && lo.matched(&host, &path);
+        if matched {
+            matched_location = Some(lo.clone());
+        }
+        result.candidates.push(RouteCandidate {
+            name: name.to_string(),
+            weight: *weight,
+            path: lo.path.clone(),
+            host: lo.hosts.join(","),
+            matched,
+        });
+    }
+
+    let lo = if let Some(lo) = matched_location {
+        lo
+    } else {
+        return Ok(result);
+    };
+    result.matched = Some(lo.name.clone());
+
+    if lo.rewrite(&mut req_header) {
+        result.rewrite_path = Some(req_header.uri.to_string());
+    }
+
+    for name in lo.plugins.clone().unwrap_or_default().iter() {
+        let plugin_conf = get_plugin_conf(conf, name);
+        result.plugins.push(RoutePlugin {
+            name: name.to_string(),
+            category: get_plugin_value(&plugin_conf, "category", ""),
+            step: get_plugin_value(&plugin_conf, "step", "request"),
+        });
+    }
+
+    if let Some(upstream_conf) = conf.upstreams.get(&lo.upstream) {
+        result.upstream = Some(RouteUpstream {
+            name: lo.upstream.clone(),
+            algo: upstream_conf
+                .algo
+                .clone()
+                .unwrap_or("round_robin".to_string()),
+            addrs: upstream_conf.addrs.clone(),
+        });
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain_route, RouteExplainParams};
+    use crate::config::PingapConf;
+    use crate::proxy::{try_init_locations, try_init_server_locations, try_init_upstreams};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_explain_route() {
+        let toml_data = include_bytes!("../../conf/pingap.toml");
+        let mut conf = PingapConf::try_from(toml_data.as_ref()).unwrap();
+        try_init_upstreams(&conf.upstreams).unwrap();
+        try_init_locations(&conf.locations).unwrap();
+        try_init_server_locations(&conf.servers, &conf.locations).unwrap();
+        // the candidates are taken from the running locations instead of config
+        if let Some(lo) = conf.locations.get_mut("lo") {
+            lo.weight = Some(1);
+            lo.host = Some("pingap.io".to_string());
+        }
+
+        let result = explain_route(
+            &conf,
+            &RouteExplainParams {
+                server: "test".to_string(),
+                path: "/vicanso/pingap?size=1".to_string(),
+                method: Some("post".to_string()),
+                headers: Some(vec!["Host: pingap.io:6188".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!("pingap.io", result.host);
+        assert_eq!("/vicanso/pingap", result.path);
+        assert_eq!("POST", result.method);
+        assert_eq!(1, result.candidates.len());
+        assert_eq!(641, result.candidates[0].weight);
+        assert_eq!("", result.candidates[0].host);
+        assert_eq!(true, result.candidates[0].matched);
+        assert_eq!("lo", result.matched.unwrap_or_default());
+        assert_eq!(None, result.rewrite_path);
+        assert_eq!(
+            "pingap:requestId:request_id,stats:stats",
+            result
+                .plugins
+                .iter()
+                .map(|item| format!("{}:{}", item.name, item.category))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let upstream = result.upstream.unwrap();
+        assert_eq!("charts", upstream.name);
+        assert_eq!("hash:cookie", upstream.algo);
+        assert_eq!(vec!["127.0.0.1:5000".to_string()], upstream.addrs);
+
+        let result = explain_route(
+            &conf,
+            &RouteExplainParams {
+                server: "unknown".to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Invalid error server(unknown) is not found",
+            result.err().unwrap().to_string()
+        );
+
+        let result = explain_route(
+            &conf,
+            &RouteExplainParams {
+                server: "test".to_string(),
+                path: "/".to_string(),
+                headers: Some(vec!["Host".to_string()]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Invalid error header(Host) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+}
@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::{LocationConf, PluginStep};
-use crate::http_extra::{convert_header_value, convert_headers, HttpHeader};
+use crate::config::{
+    load_error_template, parse_error_page, parse_error_template, ErrorPage, LocationConf,
+    PluginStep,
+};
+use crate::http_extra::{convert_header_value, convert_headers, is_ssl_client_tag, HttpHeader};
 use crate::plugin::{get_proxy_plugin, get_response_plugin};
 use crate::state::State;
 use crate::util;
@@ -26,8 +29,9 @@ use regex::Regex;
 use snafu::{ResultExt, Snafu};
 use std::collections::HashMap;
 use std::fmt;
-use std::sync::atomic::{AtomicI32, AtomicU64};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use substring::Substring;
 
 #[derive(Debug, Snafu)]
@@ -39,6 +43,16 @@ pub enum Error {
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Convert the error of config, the message of invalid error is not wrapped again.
+fn convert_config_error(e: crate::config::Error) -> Error {
+    match e {
+        crate::config::Error::Invalid { message } => Error::Invalid { message },
+        _ => Error::Invalid {
+            message: e.to_string(),
+        },
+    }
+}
+
 struct RegexPath {
     value: Regex,
 }
@@ -85,17 +99,25 @@ fn new_path_selector(path: &str) -> Result<PathSelector> {
 
 pub struct Location {
     pub name: String,
-    path: String,
+    pub path: String,
     path_selector: PathSelector,
-    hosts: Vec<String>,
+    pub hosts: Vec<String>,
     reg_rewrite: Option<(Regex, String)>,
     proxy_add_headers: Option<Vec<HttpHeader>>,
     proxy_set_headers: Option<Vec<HttpHeader>>,
-    plugins: Option<Vec<String>>,
+    pub plugins: Option<Vec<String>>,
     pub accepted: AtomicU64,
     pub processing: AtomicI32,
     pub upstream: String,
     client_max_body_size: usize,
+    error_pages: HashMap<u16, ErrorPage>,
+    pub error_format: Option<String>,
+    error_templates: HashMap<u16, String>,
+    pub intercept_errors: bool,
+    enabled_websocket: bool,
+    websocket_max_connections: i32,
+    pub websocket_idle_timeout: Option<Duration>,
+    pub websocket_processing: AtomicI32,
 }
 
 impl fmt::Display for Location {
@@ -149,6 +171,24 @@ impl Location {
 
         let path = conf.path.clone().unwrap_or_default();
 
+        let mut error_pages = HashMap::new();
+        for item in conf.error_page.clone().unwrap_or_default().iter() {
+            let (status_list, page) = parse_error_page(item).map_err(convert_config_error)?;
+            for status in status_list {
+                error_pages.insert(status, page.clone());
+            }
+        }
+
+        let mut error_templates = HashMap::new();
+        for item in conf.error_templates.clone().unwrap_or_default().iter() {
+            let (status_list, template) =
+                parse_error_template(item).map_err(convert_config_error)?;
+            let template = load_error_template(&template).map_err(convert_config_error)?;
+            for status in status_list {
+                error_templates.insert(status, template.clone());
+            }
+        }
+
         let lo = Location {
             name: name.to_string(),
             path_selector: new_path_selector(&path)?,
@@ -162,6 +202,14 @@ impl Location {
             proxy_add_headers: format_headers(&conf.proxy_add_headers)?,
             proxy_set_headers: format_headers(&conf.proxy_set_headers)?,
             client_max_body_size: conf.client_max_body_size.unwrap_or_default().as_u64() as usize,
+            error_pages,
+            error_format: conf.error_format.clone(),
+            error_templates,
+            intercept_errors: conf.intercept_errors.unwrap_or_default(),
+            enabled_websocket: conf.enabled_websocket.unwrap_or(true),
+            websocket_max_connections: conf.websocket_max_connections.unwrap_or_default() as i32,
+            websocket_idle_timeout: conf.websocket_idle_timeout,
+            websocket_processing: AtomicI32::new(0),
         };
         debug!("Location {lo}");
 
@@ -213,6 +261,48 @@ impl Location {
         }
         Ok(())
     }
+    /// Acquire a websocket connection of location, it will return error
+    /// if websocket is disabled or the connections exceed the limit.
+    /// Returns the processing count of websocket connections.
+    #[inline]
+    pub fn websocket_acquire(&self) -> pingora::Result<i32> {
+        if !self.enabled_websocket {
+            return Err(util::new_internal_error(
+                403,
+                "Websocket is not allowed".to_string(),
+            ));
+        }
+        let processing = self.websocket_processing.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.websocket_max_connections > 0 && processing > self.websocket_max_connections {
+            self.websocket_processing.fetch_sub(1, Ordering::Relaxed);
+            return Err(util::new_internal_error(
+                503,
+                "Too many websocket connections".to_string(),
+            ));
+        }
+        Ok(processing)
+    }
+    /// Release the websocket connection of location.
+    #[inline]
+    pub fn websocket_release(&self) {
+        self.websocket_processing.fetch_sub(1, Ordering::Relaxed);
+    }
+    /// Get the html error template of the status.
+    #[inline]
+    pub fn get_error_template(&self, status: u16) -> Option<&String> {
+        if self.error_templates.is_empty() {
+            return None;
+        }
+        self.error_templates.get(&status)
+    }
+    /// Get the error page fallback of the status.
+    #[inline]
+    pub fn get_error_page(&self, status: u16) -> Option<&ErrorPage> {
+        if self.error_pages.is_empty() {
+            return None;
+        }
+        self.error_pages.get(&status)
+    }
     /// Rewrite the path by the rule and returns true.
     /// If the rule is not exists, returns false.
     #[inline]
@@ -242,12 +332,24 @@ impl Location {
         ctx: &State,
         header: &mut RequestHeader,
     ) {
+        // the client certificate headers are only set by proxy,
+        // the copy of client is removed
+        for arr in [&self.proxy_set_headers, &self.proxy_add_headers]
+            .into_iter()
+            .flatten()
+        {
+            for (k, v) in arr {
+                if is_ssl_client_tag(v) {
+                    header.remove_header(k);
+                }
+            }
+        }
         if let Some(arr) = &self.proxy_set_headers {
             for (k, v) in arr {
                 if let Some(v) = convert_header_value(v, session, ctx) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.insert_header(k, v);
-                } else {
+                } else if !is_ssl_client_tag(v) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.insert_header(k, v);
                 }
@@ -258,7 +360,7 @@ impl Location {
                 if let Some(v) = convert_header_value(v, session, ctx) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.append_header(k, v);
-                } else {
+                } else if !is_ssl_client_tag(v) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.append_header(k, v);
                 }
@@ -336,9 +438,10 @@ pub fn try_init_locations(confs: &HashMap<String, LocationConf>) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::{format_headers, new_path_selector, Location, PathSelector};
-    use crate::config::{LocationConf, PluginStep};
+    use crate::config::{parse_error_page, ErrorPage, LocationConf, PluginStep};
     use crate::plugin::initialize_test_plugins;
     use crate::state::State;
+    use crate::util;
     use bytesize::ByteSize;
     use http::Method;
     use pingora::http::{RequestHeader, ResponseHeader};
@@ -401,6 +504,24 @@ mod tests {
         assert_eq!(true, lo.matched("pingap", ""));
         assert_eq!(false, lo.matched("", "/api"));
 
+        // ipv6 host with port
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some(upstream_name.to_string()),
+                host: Some("[::1]".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut req_header = RequestHeader::build("GET", b"/api", None).unwrap();
+        req_header.insert_header("Host", "[::1]:6188").unwrap();
+        let host = util::get_host(&req_header).unwrap_or_default();
+        assert_eq!(true, lo.matched(host, "/api"));
+        req_header.insert_header("Host", "[::2]:6188").unwrap();
+        let host = util::get_host(&req_header).unwrap_or_default();
+        assert_eq!(false, lo.matched(host, "/api"));
+
         // regex
         let lo = Location::new(
             "lo",
@@ -480,6 +601,119 @@ mod tests {
         assert_eq!("/api/me?abc=1", req_header.uri.to_string());
     }
 
+    #[test]
+    fn test_websocket_acquire() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                websocket_max_connections: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(1, lo.websocket_acquire().unwrap());
+        assert_eq!(
+            " HTTPStatus context: Too many websocket connections cause:  InternalError",
+            lo.websocket_acquire().err().unwrap().to_string()
+        );
+        lo.websocket_release();
+        assert_eq!(1, lo.websocket_acquire().unwrap());
+
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                enabled_websocket: Some(false),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            " HTTPStatus context: Websocket is not allowed cause:  InternalError",
+            lo.websocket_acquire().err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_error_page() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                error_page: Some(vec![
+                    "404 @static".to_string(),
+                    "502,503 =Service maintenance".to_string(),
+                ]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&ErrorPage::Location("static".to_string())),
+            lo.get_error_page(404)
+        );
+        assert_eq!(
+            Some(&ErrorPage::Body("Service maintenance".to_string())),
+            lo.get_error_page(503)
+        );
+        assert_eq!(None, lo.get_error_page(500));
+
+        let result = Location::new(
+            "lo",
+            &LocationConf {
+                error_page: Some(vec!["404 static".to_string()]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Invalid error error page 404 static is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_error_template() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                error_templates: Some(vec!["502,503 <h1>{{status}} {{host}}</h1>".to_string()]),
+                intercept_errors: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(true, lo.intercept_errors);
+        assert_eq!(
+            "<h1>{{status}} {{host}}</h1>",
+            lo.get_error_template(503).unwrap()
+        );
+        assert_eq!(None, lo.get_error_template(404));
+
+        let result = Location::new(
+            "lo",
+            &LocationConf {
+                error_templates: Some(vec!["abc".to_string()]),
+                ..Default::default()
+            },
+        );
+        // the invalid error of config is not wrapped again
+        assert_eq!(
+            "Invalid error error template abc is invalid",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Location::new(
+            "lo",
+            &LocationConf {
+                error_templates: Some(vec!["404 file:/pingap-not-exists/404.html".to_string()]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(true, result.is_err());
+    }
+
     #[tokio::test]
     async fn test_insert_header() {
         let upstream_name = "charts";
@@ -510,6 +744,70 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_client_cert_header() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                proxy_set_headers: Some(vec!["X-Client-Subject: $ssl_client_s_dn".to_string()]),
+                proxy_add_headers: Some(vec!["X-Client-San: $ssl_client_san".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let headers = [""].join("\r\n");
+        let input_header = format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        // the headers of client are removed
+        let mut req_header = RequestHeader::build_no_case(Method::GET, b"", None).unwrap();
+        req_header
+            .insert_header("X-Client-Subject", "CN=admin")
+            .unwrap();
+        req_header
+            .insert_header("X-Client-San", "DNS:admin")
+            .unwrap();
+        lo.set_append_proxy_headers(&session, &State::default(), &mut req_header);
+        assert_eq!(true, req_header.headers.get("X-Client-Subject").is_none());
+        assert_eq!(true, req_header.headers.get("X-Client-San").is_none());
+
+        let mut req_header = RequestHeader::build_no_case(Method::GET, b"", None).unwrap();
+        req_header
+            .insert_header("X-Client-San", "DNS:admin")
+            .unwrap();
+        lo.set_append_proxy_headers(
+            &session,
+            &State {
+                client_cert_subject: Some(vec!["CN=svc-a, O=admin".to_string()]),
+                client_cert_sans: Some(vec!["DNS:svc-a".to_string()]),
+                ..Default::default()
+            },
+            &mut req_header,
+        );
+        assert_eq!(
+            r#""CN=svc-a, O=admin""#,
+            req_header
+                .headers
+                .get("X-Client-Subject")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        assert_eq!(
+            vec![r#""DNS:svc-a""#],
+            req_header
+                .headers
+                .get_all("X-Client-San")
+                .iter()
+                .map(|item| item.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_client_body_size_limit() {
         let upstream_name = "charts";
@@ -288,6 +288,8 @@ impl Parser {
                 TagCategory::Remote => {
                     if let Some(addr) = &ctx.remote_addr {
                         buf.extend(addr.as_bytes());
+                    } else if util::is_unix_peer(session) {
+                        buf.extend(util::UNIX_CLIENT_IP.as_bytes());
                     }
                 }
                 TagCategory::ClientIp => {
@@ -408,6 +410,13 @@ impl Parser {
                                     buf.extend(b"false");
                                 }
                             }
+                            "websocket" => {
+                                if ctx.websocket {
+                                    buf.extend(b"true");
+                                } else {
+                                    buf.extend(b"false");
+                                }
+                            }
                             "upstream_addr" => buf.extend(ctx.upstream_address.as_bytes()),
                             "processing" => {
                                 buf.extend(itoa::Buffer::new().format(ctx.processing).as_bytes())
@@ -445,6 +454,31 @@ impl Parser {
                                     buf.extend(value.as_bytes());
                                 }
                             }
+                            "client_cert_subject" => {
+                                if let Some(value) = &ctx.client_cert_subject {
+                                    buf.extend(util::to_sf_string_list(value).as_bytes());
+                                }
+                            }
+                            "client_cert_sans" => {
+                                if let Some(value) = &ctx.client_cert_sans {
+                                    buf.extend(util::to_sf_string_list(value).as_bytes());
+                                }
+                            }
+                            "client_cert_fingerprint" => {
+                                if let Some(value) = &ctx.client_cert_fingerprint {
+                                    buf.extend(value.as_bytes());
+                                }
+                            }
+                            "ja3" => {
+                                if let Some(value) = &ctx.ja3 {
+                                    buf.extend(value.as_bytes());
+                                }
+                            }
+                            "ja4" => {
+                                if let Some(value) = &ctx.ja4 {
+                                    buf.extend(value.as_bytes());
+                                }
+                            }
                             "compression_time" => {
                                 if let Some(value) = &ctx.compression_stat {
                                     buf.extend(format!("{:?}", value.duration).as_bytes());
@@ -12,19 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod client_cert;
+mod connection_limit;
 mod dynamic_cert;
+mod explain;
 mod location;
 mod logger;
+mod ocsp;
 mod server;
 mod server_conf;
+mod stream;
+mod tls_connection;
+mod tls_fingerprint;
+mod tls_session;
 mod upstream;
 
 // for bench
 #[allow(unused_imports)]
 pub use location::Location;
 
+pub use connection_limit::get_connection_stats;
+pub use dynamic_cert::{get_certificate_info_list, validate_certificate, CertificateInfo};
+pub use explain::{explain_route, RouteExplainParams};
 pub use location::try_init_locations;
 pub use logger::Parser;
+pub use ocsp::new_ocsp_stapling_service;
 pub use server::*;
 pub use server_conf::ServerConf;
+pub use stream::StreamServer;
+pub use tls_session::{get_tls_handshake_stats, new_ticket_key_rotation_service};
 pub use upstream::{new_upstream_health_check_task, try_init_upstreams};
@@ -0,0 +1,368 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::dynamic_cert::{get_cert_fingerprint, get_certificates};
+use crate::service::{CommonServiceTask, ServiceTask};
+use crate::util;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use log::{debug, error, info, warn};
+use once_cell::sync::Lazy;
+use pingora::listeners::TlsSettings;
+use pingora::tls::error::ErrorStack;
+use pingora::tls::hash::MessageDigest;
+use pingora::tls::ocsp::{
+    OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus,
+};
+use pingora::tls::stack::Stack;
+use pingora::tls::x509::store::X509StoreBuilder;
+use pingora::tls::x509::verify::X509VerifyFlags;
+use pingora::tls::x509::X509;
+use snafu::Snafu;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid {message}"))]
+    Invalid { message: String },
+    #[snafu(display("Request error {source}"))]
+    Request { source: reqwest::Error },
+    #[snafu(display("Io error {source}, {file}"))]
+    Io {
+        source: std::io::Error,
+        file: String,
+    },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+// the max clock skew(seconds) of ocsp response validity
+const OCSP_MAX_CLOCK_SKEW: u32 = 5 * 60;
+
+#[derive(Debug, Clone, Default)]
+struct OcspStaple {
+    response: Vec<u8>,
+    // unix timestamp(seconds) of next update, 0 means unknown
+    next_update: i64,
+    updated_at: i64,
+}
+
+// server name -> pre-fetched ocsp response file of default certificate
+static OCSP_SERVERS: Lazy<ArcSwap<HashMap<String, Option<String>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+// certificate fingerprint -> ocsp staple
+static OCSP_STAPLES: Lazy<ArcSwap<HashMap<String, Arc<OcspStaple>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+impl OcspStaple {
+    fn is_expired(&self, now: i64) -> bool {
+        self.next_update > 0 && now > self.next_update
+    }
+}
+
+fn update_ocsp_staple(fingerprint: &str, staple: OcspStaple) {
+    let mut staples = OCSP_STAPLES.load().as_ref().clone();
+    staples.insert(fingerprint.to_string(), Arc::new(staple));
+    OCSP_STAPLES.store(Arc::new(staples));
+}
+
+/// Enable ocsp stapling for the tls settings of server,
+/// the ocsp responses are fetched by background service.
+pub fn set_ocsp_stapling(
+    tls_settings: &mut TlsSettings,
+    name: &str,
+    response_file: Option<String>,
+) -> Result<()> {
+    let mut servers = OCSP_SERVERS.load().as_ref().clone();
+    servers.insert(name.to_string(), response_file);
+    OCSP_SERVERS.store(Arc::new(servers));
+
+    tls_settings
+        .set_status_callback(|ssl| {
+            let fingerprint = if let Some(cert) = ssl.certificate() {
+                get_cert_fingerprint(cert)
+            } else {
+                return Ok(false);
+            };
+            if let Some(staple) = OCSP_STAPLES.load().get(&fingerprint) {
+                // the expired response should not be stapled
+                if !staple.is_expired(util::now().as_secs() as i64) {
+                    ssl.set_ocsp_status(&staple.response)?;
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        })
+        .map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })
+}
+
+/// Parse the asn1 time, e.g. `Jun  1 12:00:00 2024 GMT`.
+fn parse_asn1_time(value: &str) -> Option<i64> {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    NaiveDateTime::parse_from_str(&value, "%b %d %H:%M:%S %Y GMT")
+        .map(|value| value.and_utc().timestamp())
+        .ok()
+}
+
+/// Refresh the staple when it is over half of the validity period,
+/// or it is older than one hour if next update is unknown.
+fn should_refresh(staple: &OcspStaple, now: i64) -> bool {
+    if staple.next_update <= 0 {
+        return now - staple.updated_at > 3600;
+    }
+    now > staple.updated_at + (staple.next_update - staple.updated_at) / 2
+}
+
+async fn fetch_ocsp_response(cert: &X509, issuer: &X509) -> Result<OcspStaple> {
+    let url = if let Some(url) = cert
+        .ocsp_responders()
+        .ok()
+        .and_then(|list| list.iter().next().map(|item| item.to_string()))
+    {
+        url
+    } else {
+        return Err(Error::Invalid {
+            message: "Ocsp responder is not found".to_string(),
+        });
+    };
+    let new_cert_id = || {
+        OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer).map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })
+    };
+    let mut req = OcspRequest::new().map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
+    req.add_id(new_cert_id()?).map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
+    let body = req.to_der().map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
+    let resp = reqwest::Client::new()
+        .post(&url)
+        .header("Content-Type", "application/ocsp-request")
+        .timeout(Duration::from_secs(30))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| Error::Request { source: e })?;
+    let buf = resp
+        .bytes()
+        .await
+        .map_err(|e| Error::Request { source: e })?;
+    let staple = verify_ocsp_response(&buf, cert, issuer)?;
+    debug!("Ocsp response is verified, ocsp responder: {url}");
+    Ok(staple)
+}
+
+/// Verify the ocsp response by the issuer of certificate, the response should be
+/// successful, signed by the issuer(or its delegated responder) and in the validity period.
+fn verify_ocsp_response(buf: &[u8], cert: &X509, issuer: &X509) -> Result<OcspStaple> {
+    let invalid = |e: ErrorStack| Error::Invalid {
+        message: e.to_string(),
+    };
+    let ocsp = OcspResponse::from_der(buf).map_err(invalid)?;
+    if ocsp.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(Error::Invalid {
+            message: format!("Ocsp response status is {:?}", ocsp.status()),
+        });
+    }
+    let basic = ocsp.basic().map_err(invalid)?;
+
+    let mut certs = Stack::new().map_err(invalid)?;
+    certs.push(issuer.clone()).map_err(invalid)?;
+    let mut builder = X509StoreBuilder::new().map_err(invalid)?;
+    builder.add_cert(issuer.clone()).map_err(invalid)?;
+    // the issuer is usually an intermediate certificate
+    builder
+        .set_flags(X509VerifyFlags::PARTIAL_CHAIN)
+        .map_err(invalid)?;
+    let store = builder.build();
+    basic
+        .verify(&certs, &store, OcspFlag::empty())
+        .map_err(|e| Error::Invalid {
+            message: format!("Ocsp response verify fail, {e}"),
+        })?;
+
+    let cert_id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer).map_err(invalid)?;
+    let status = if let Some(status) = basic.find_status(&cert_id) {
+        status
+    } else {
+        return Err(Error::Invalid {
+            message: "Ocsp status of certificate is not found".to_string(),
+        });
+    };
+    if status.status == OcspCertStatus::UNKNOWN {
+        return Err(Error::Invalid {
+            message: "Ocsp status of certificate is unknown".to_string(),
+        });
+    }
+    if status.status == OcspCertStatus::REVOKED {
+        warn!("Certificate is revoked");
+    }
+    // check this update and next update, allow 5 minutes clock skew
+    status
+        .check_validity(OCSP_MAX_CLOCK_SKEW, None)
+        .map_err(|e| Error::Invalid {
+            message: format!("Ocsp response is expired or not yet valid, {e}"),
+        })?;
+    let next_update = parse_asn1_time(&status.next_update.to_string()).unwrap_or_default();
+    Ok(OcspStaple {
+        response: buf.to_vec(),
+        next_update,
+        updated_at: util::now().as_secs() as i64,
+    })
+}
+
+fn load_ocsp_response_file(file: &str, cert: &X509, issuer: &X509) -> Result<OcspStaple> {
+    let path = util::resolve_path(file);
+    let buf = std::fs::read(&path).map_err(|e| Error::Io {
+        source: e,
+        file: path.clone(),
+    })?;
+    verify_ocsp_response(&buf, cert, issuer)
+}
+
+struct OcspStapling {}
+
+#[async_trait]
+impl ServiceTask for OcspStapling {
+    async fn run(&self) -> Option<bool> {
+        let now = util::now().as_secs() as i64;
+        let servers = OCSP_SERVERS.load();
+        for (name, response_file) in servers.iter() {
+            for (index, (fingerprint, cert, issuer)) in
+                get_certificates(name).into_iter().enumerate()
+            {
+                let issuer = if let Some(issuer) = issuer {
+                    issuer
+                } else {
+                    debug!("Issuer of certificate is not found, server: {name}");
+                    continue;
+                };
+                // the pre-fetched response file is only for the default certificate
+                if index == 0 {
+                    if let Some(file) = response_file {
+                        match load_ocsp_response_file(file, &cert, &issuer) {
+                            Ok(staple) => update_ocsp_staple(&fingerprint, staple),
+                            Err(e) => error!("Load ocsp response fail, server: {name}, {e}"),
+                        };
+                        continue;
+                    }
+                }
+                if let Some(staple) = OCSP_STAPLES.load().get(&fingerprint) {
+                    if !should_refresh(staple, now) {
+                        continue;
+                    }
+                }
+                match fetch_ocsp_response(&cert, &issuer).await {
+                    Ok(staple) => {
+                        info!(
+                            "Fetch ocsp response success, server: {name}, next update: {}",
+                            staple.next_update
+                        );
+                        update_ocsp_staple(&fingerprint, staple);
+                    }
+                    Err(e) => error!("Fetch ocsp response fail, server: {name}, {e}"),
+                };
+            }
+        }
+        None
+    }
+    fn description(&self) -> String {
+        let servers: Vec<String> = OCSP_SERVERS.load().keys().cloned().collect();
+        format!("servers: {servers:?}")
+    }
+}
+
+pub fn new_ocsp_stapling_service() -> CommonServiceTask {
+    CommonServiceTask::new(
+        "Ocsp stapling",
+        Duration::from_secs(10 * 60),
+        OcspStapling {},
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_asn1_time, should_refresh, verify_ocsp_response, OcspStaple};
+    use pingora::tls::x509::X509;
+    use pretty_assertions::assert_eq;
+    use rcgen::{Certificate, CertificateParams};
+
+    #[test]
+    fn test_parse_asn1_time() {
+        assert_eq!(
+            Some(1717243200),
+            parse_asn1_time("Jun  1 12:00:00 2024 GMT")
+        );
+        assert_eq!(
+            Some(1718971200),
+            parse_asn1_time("Jun 21 12:00:00 2024 GMT")
+        );
+        assert_eq!(None, parse_asn1_time("2024-06-01"));
+    }
+
+    #[test]
+    fn test_ocsp_staple_expired() {
+        let staple = OcspStaple {
+            next_update: 1000,
+            ..Default::default()
+        };
+        assert_eq!(false, staple.is_expired(1000));
+        assert_eq!(true, staple.is_expired(1001));
+        assert_eq!(false, OcspStaple::default().is_expired(1001));
+    }
+
+    #[test]
+    fn test_verify_ocsp_response() {
+        let new_cert = || {
+            let cert =
+                Certificate::from_params(CertificateParams::new(vec!["pingap.io".to_string()]))
+                    .unwrap();
+            X509::from_pem(cert.serialize_pem().unwrap().as_bytes()).unwrap()
+        };
+        let cert = new_cert();
+        let issuer = new_cert();
+        assert_eq!(
+            true,
+            verify_ocsp_response(b"invalid", &cert, &issuer).is_err()
+        );
+    }
+
+    #[test]
+    fn test_should_refresh() {
+        let staple = OcspStaple {
+            next_update: 1000,
+            updated_at: 0,
+            ..Default::default()
+        };
+        assert_eq!(false, should_refresh(&staple, 400));
+        assert_eq!(true, should_refresh(&staple, 600));
+
+        let staple = OcspStaple {
+            next_update: 0,
+            updated_at: 0,
+            ..Default::default()
+        };
+        assert_eq!(false, should_refresh(&staple, 3600));
+        assert_eq!(true, should_refresh(&staple, 3601));
+    }
+}
@@ -12,18 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::dynamic_cert::DynamicCert;
+use super::client_cert::{set_client_cert_verify, CLIENT_VERIFY_REQUIRED};
+use super::connection_limit::{
+    inc_connection_timeout, set_header_read, set_request_done, ConnectionLimitApp,
+    ConnectionLimitOptions,
+};
+use super::dynamic_cert::{
+    get_certificate_info_list, has_certificates, load_certificates_from_dir, set_acme_tls_alpn,
+    try_update_certificates, CertificateInfo, DynamicCert,
+};
 use super::logger::Parser;
+use super::ocsp::set_ocsp_stapling;
+use super::tls_connection::{get_tls_connection_info, TlsConnectionApp};
+use super::tls_fingerprint::set_tls_fingerprint_callback;
+use super::tls_session::{set_tls_session, TlsSessionOptions};
 use super::upstream::get_upstream;
 use super::ServerConf;
 use crate::acme::get_cert_info;
 use crate::acme::CertInfo;
-use crate::acme::{get_lets_encrypt_cert, handle_lets_encrypt};
+use crate::acme::{
+    get_lets_encrypt_cert, handle_lets_encrypt, is_tls_alpn_challenge, new_placeholder_cert,
+};
 use crate::config;
-use crate::config::PluginStep;
-use crate::http_extra::{HttpResponse, HTTP_HEADER_NAME_X_REQUEST_ID};
+use crate::config::{ErrorPage, PluginStep, TLS_AUTO_SELF_SIGNED};
+use crate::http_extra::{
+    get_error_format, ErrorBody, HttpResponse, ERROR_FORMAT_AUTO, HTTP_HEADER_NAME_X_REQUEST_ID,
+    HTTP_HEADER_NO_STORE,
+};
 use crate::plugin::get_proxy_plugin;
 use crate::proxy::location::get_location;
+use crate::state;
 use crate::state::CompressionStat;
 use crate::state::State;
 use crate::util;
@@ -43,16 +61,18 @@ use pingora::listeners::{TcpSocketOptions, TlsSettings};
 use pingora::modules::http::compression::ResponseCompression;
 use pingora::protocols::http::error_resp;
 use pingora::protocols::Digest;
-use pingora::proxy::{http_proxy_service, HttpProxy};
+use pingora::proxy::{http_proxy, HttpProxy};
 use pingora::proxy::{ProxyHttp, Session};
 use pingora::server::configuration;
 use pingora::services::listening::Service;
 use pingora::upstreams::peer::{HttpPeer, Peer};
 use snafu::Snafu;
 use std::collections::HashMap;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
 use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -61,7 +81,8 @@ pub enum Error {
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-type ServerLocations = HashMap<String, Arc<Vec<String>>>;
+// server -> the ordered locations with the weight for sorting
+type ServerLocations = HashMap<String, Arc<Vec<(String, u16)>>>;
 static LOCATION_MAP: Lazy<ArcSwap<ServerLocations>> =
     Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
 
@@ -76,15 +97,18 @@ pub fn try_init_server_locations(
     let mut server_locations = HashMap::new();
     for (name, server) in servers.iter() {
         if let Some(items) = &server.locations {
-            let mut items = items.clone();
-            items.sort_by_key(|item| {
-                let weight = if let Some(weight) = location_weights.get(item.as_str()) {
-                    weight.to_owned()
-                } else {
-                    0
-                };
-                std::cmp::Reverse(weight)
-            });
+            let mut items: Vec<(String, u16)> = items
+                .iter()
+                .map(|item| {
+                    let weight = if let Some(weight) = location_weights.get(item.as_str()) {
+                        weight.to_owned()
+                    } else {
+                        0
+                    };
+                    (item.clone(), weight)
+                })
+                .collect();
+            items.sort_by_key(|(_, weight)| std::cmp::Reverse(*weight));
             server_locations.insert(name.to_string(), Arc::new(items));
         }
     }
@@ -93,7 +117,7 @@ pub fn try_init_server_locations(
 }
 
 #[inline]
-fn get_server_locations(name: &str) -> Option<Arc<Vec<String>>> {
+pub(super) fn get_server_locations(name: &str) -> Option<Arc<Vec<(String, u16)>>> {
     LOCATION_MAP.load().get(name).cloned()
 }
 
@@ -103,26 +127,165 @@ pub struct Server {
     addr: String,
     accepted: AtomicU64,
     processing: AtomicI32,
+    websocket_processing: AtomicI32,
     log_parser: Option<Parser>,
     error_template: String,
+    error_format: String,
+    error_templates: HashMap<u16, String>,
     threads: Option<usize>,
-    tls_cert: Option<Vec<u8>>,
-    tls_key: Option<Vec<u8>>,
+    certificates: Vec<(Vec<u8>, Vec<u8>)>,
+    tls_client_ca: Option<Vec<u8>>,
+    tls_client_verify: Option<String>,
+    enabled_ocsp_stapling: bool,
+    tls_ocsp_response_file: Option<String>,
+    tls_session: TlsSessionOptions,
+    enabled_tls_fingerprint: bool,
     tls_cipher_list: Option<String>,
     tls_ciphersuites: Option<String>,
     tls_min_version: Option<String>,
     tls_max_version: Option<String>,
     enbaled_h2: bool,
     lets_encrypt_enabled: bool,
-    tls_from_lets_encrypt: bool,
+    // handle the `acme-tls/1` protocol for tls-alpn-01 challenge
+    tls_alpn_challenge: bool,
+    // generate the self signed certificate for each host
+    tls_self_signed: bool,
     tcp_socket_options: Option<TcpSocketOptions>,
+    unix_socket_mode: Option<u32>,
+    connection_limit: ConnectionLimitOptions,
+    body_read_timeout: Option<Duration>,
+    min_transfer_rate: Option<u64>,
+}
+
+/// Returns true if the certificates of server are self signed.
+fn is_self_signed(conf: &ServerConf) -> bool {
+    conf.tls_auto.as_deref() == Some(TLS_AUTO_SELF_SIGNED)
+}
+
+/// Load the static certificates of cert, certificate list and dir.
+/// The error of loading certificates from dir is returned,
+/// otherwise the service which should be tls will listen as plaintext.
+pub(super) fn load_static_certificates(
+    tls_cert: Option<(Vec<u8>, Vec<u8>)>,
+    tls_certificates: Vec<(Vec<u8>, Vec<u8>)>,
+    tls_certificate_dir: Option<&str>,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut certificates = vec![];
+    if let Some(tls_cert) = tls_cert {
+        certificates.push(tls_cert);
+    }
+    certificates.extend(tls_certificates);
+    if let Some(dir) = tls_certificate_dir {
+        let list = load_certificates_from_dir(dir).map_err(|e| Error::Common {
+            category: "tls".to_string(),
+            message: format!("load certificates from dir({dir}) fail, {e}"),
+        })?;
+        if list.is_empty() {
+            return Err(Error::Common {
+                category: "tls".to_string(),
+                message: format!("no certificate is found in dir({dir})"),
+            });
+        }
+        certificates.extend(list);
+    }
+    Ok(certificates)
+}
+
+/// Get the certificates of server, they are loaded from `tls_cert`,
+/// `tls_certificates`, `tls_certificate_dir` and lets encrypt.
+fn get_server_certificates(conf: &ServerConf) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut certificates = load_static_certificates(
+        conf.tls_cert
+            .as_ref()
+            .map(|cert| (cert.clone(), conf.tls_key.clone().unwrap_or_default())),
+        conf.tls_certificates.clone(),
+        conf.tls_certificate_dir.as_deref(),
+    )?;
+    // the self signed certificates are generated for sni in tls handshake,
+    // so they are not the default certificate of configured ones
+    if is_self_signed(conf) {
+        return Ok(certificates);
+    }
+    if let (true, Some(value)) = (certificates.is_empty(), &conf.lets_encrypt) {
+        match get_lets_encrypt_cert(&conf.lets_encrypt_group) {
+            Ok(cert_info) => {
+                certificates.push((cert_info.get_cert(), cert_info.get_key()));
+            }
+            Err(e) => error!("get lets encrypt cert fail, {e}"),
+        };
+        // tls-alpn-01 challenge needs the server listening tls before the cert is issued
+        if certificates.is_empty() && is_tls_alpn_challenge(&config::get_current_config().basic) {
+            let domains: Vec<String> = value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect();
+            match new_placeholder_cert(&domains) {
+                Ok(cert) => certificates.push(cert),
+                Err(e) => error!("new placeholder cert fail, {e}"),
+            };
+        }
+    }
+    Ok(certificates)
+}
+
+/// Get the information of all loaded certificates, the source of certificate
+/// is self_signed or acme if the server doesn't use static certificates.
+pub fn get_certificate_list(confs: &[ServerConf]) -> Vec<CertificateInfo> {
+    let mut sources = HashMap::new();
+    for conf in confs.iter() {
+        if conf.tls_cert.is_some()
+            || !conf.tls_certificates.is_empty()
+            || conf.tls_certificate_dir.is_some()
+        {
+            continue;
+        }
+        if is_self_signed(conf) {
+            sources.insert(conf.name.as_str(), TLS_AUTO_SELF_SIGNED);
+        } else if conf.lets_encrypt.is_some() {
+            sources.insert(conf.name.as_str(), "acme");
+        }
+    }
+    get_certificate_info_list()
+        .into_iter()
+        .map(|mut item| {
+            if let Some(source) = sources.get(item.server.as_str()) {
+                item.source = source.to_string();
+            }
+            item
+        })
+        .collect()
+}
+
+/// Reload the certificates of tls servers without restart,
+/// the server which is not listening on tls will be ignored.
+/// Returns the name list of updated servers.
+pub fn try_reload_certificates(confs: &[ServerConf]) -> Result<Vec<String>> {
+    let mut updated_servers = vec![];
+    for conf in confs.iter() {
+        if !has_certificates(&conf.name) {
+            continue;
+        }
+        let certificates = get_server_certificates(conf)?;
+        if certificates.is_empty() {
+            continue;
+        }
+        try_update_certificates(&conf.name, &certificates).map_err(|e| Error::Common {
+            category: "tls".to_string(),
+            message: e.to_string(),
+        })?;
+        updated_servers.push(conf.name.clone());
+    }
+    Ok(updated_servers)
 }
 
 pub struct ServerServices {
-    pub tls_cert_info: Option<CertInfo>,
-    pub lb: Service<HttpProxy<Server>>,
+    pub tls_cert_info_list: Vec<CertInfo>,
+    pub lb: Service<ConnectionLimitApp<TlsConnectionApp<HttpProxy<Server>>>>,
 }
 
+const MIN_TRANSFER_RATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
 const META_DEFAULTS: CacheMetaDefaults = CacheMetaDefaults::new(|_| Some(1), 1, 1);
 
 impl Server {
@@ -146,20 +309,41 @@ impl Server {
             admin: conf.admin,
             accepted: AtomicU64::new(0),
             processing: AtomicI32::new(0),
+            websocket_processing: AtomicI32::new(0),
             addr: conf.addr.clone(),
             log_parser: p,
             error_template: conf.error_template.clone(),
-            tls_key: conf.tls_key.clone(),
-            tls_cert: conf.tls_cert.clone(),
+            error_format: conf
+                .error_format
+                .clone()
+                .unwrap_or(ERROR_FORMAT_AUTO.to_string()),
+            error_templates: conf.error_templates.clone(),
+            certificates: get_server_certificates(conf)?,
+            tls_client_ca: conf.tls_client_ca.clone(),
+            tls_client_verify: conf.tls_client_verify.clone(),
+            enabled_ocsp_stapling: conf.enabled_ocsp_stapling,
+            tls_ocsp_response_file: conf.tls_ocsp_response_file.clone(),
+            tls_session: conf.tls_session.clone(),
+            enabled_tls_fingerprint: conf.enabled_tls_fingerprint,
             tls_cipher_list: conf.tls_cipher_list.clone(),
             tls_ciphersuites: conf.tls_ciphersuites.clone(),
             tls_min_version: conf.tls_min_version.clone(),
             tls_max_version: conf.tls_max_version.clone(),
             threads: conf.threads,
             lets_encrypt_enabled: false,
+            tls_self_signed: is_self_signed(conf),
+            tls_alpn_challenge: conf.lets_encrypt.is_some()
+                && is_tls_alpn_challenge(&config::get_current_config().basic),
             enbaled_h2: conf.enbaled_h2,
             tcp_socket_options,
-            tls_from_lets_encrypt: conf.lets_encrypt.is_some(),
+            unix_socket_mode: conf.unix_socket_mode,
+            connection_limit: ConnectionLimitOptions {
+                max_connections: conf.max_connections,
+                max_connections_per_ip: conf.max_connections_per_ip,
+                header_read_timeout: conf.header_read_timeout,
+            },
+            body_read_timeout: conf.body_read_timeout,
+            min_transfer_rate: conf.min_transfer_rate,
         };
         Ok(s)
     }
@@ -170,43 +354,35 @@ impl Server {
 
     /// New all background services and add a TCP/TLS listening endpoint.
     pub fn run(self, conf: &Arc<configuration::ServerConf>) -> Result<ServerServices> {
-        let tls_from_lets_encrypt = self.tls_from_lets_encrypt;
         let addr = self.addr.clone();
         let tcp_socket_options = self.tcp_socket_options.clone();
 
         // tls
-        let mut tls_cert = self.tls_cert.clone();
-        let mut tls_key = self.tls_key.clone();
-        if tls_cert.is_none() && tls_from_lets_encrypt {
-            match get_lets_encrypt_cert() {
-                Ok(cert_info) => {
-                    tls_cert = Some(cert_info.get_cert());
-                    tls_key = Some(cert_info.get_key());
-                }
-                Err(e) => error!("get lets encrypt cert fail, {e}"),
-            };
-        }
-        let is_tls = tls_cert.is_some();
-        let mut tls_cert_info = None;
+        let certificates = self.certificates.clone();
+        let is_tls = !certificates.is_empty() || self.tls_self_signed;
+        let mut tls_cert_info_list = vec![];
         let dynamic_cert = if is_tls {
-            let cert = tls_cert.unwrap_or_default();
-            if let Ok(info) = get_cert_info(&cert) {
-                tls_cert_info = Some(info)
+            for (cert, _) in certificates.iter() {
+                if let Ok(info) = get_cert_info(cert) {
+                    tls_cert_info_list.push(info);
+                }
             }
 
-            Some(
-                DynamicCert::new(&cert, &tls_key.unwrap_or_default()).map_err(|e| {
-                    Error::Common {
-                        category: "tls".to_string(),
-                        message: e.to_string(),
-                    }
-                })?,
-            )
+            let mut dynamic_cert =
+                DynamicCert::new(&self.name, &certificates).map_err(|e| Error::Common {
+                    category: "tls".to_string(),
+                    message: e.to_string(),
+                })?;
+            if self.tls_self_signed {
+                dynamic_cert.enable_self_signed();
+            }
+            Some(dynamic_cert)
         } else {
             None
         };
 
         let enbaled_h2 = self.enbaled_h2;
+        let tls_alpn_challenge = self.tls_alpn_challenge;
         let mut threads = self.threads;
         if threads.unwrap_or_default() == 0 {
             threads = Some(1);
@@ -215,14 +391,49 @@ impl Server {
             "Server({}) is linsten on:{addr}, threads:{threads:?}, tls:{is_tls}",
             &self.name
         );
+        let client_ca = self.tls_client_ca.clone();
+        // verify client certificate if client ca is set
+        let client_verify = self
+            .tls_client_verify
+            .clone()
+            .unwrap_or(CLIENT_VERIFY_REQUIRED.to_string());
+        let enabled_ocsp_stapling = self.enabled_ocsp_stapling;
+        let ocsp_response_file = self.tls_ocsp_response_file.clone();
+        let name = self.name.clone();
+        let tls_session = self.tls_session.clone();
+        let enabled_tls_fingerprint = self.enabled_tls_fingerprint;
         let cipher_list = self.tls_cipher_list.clone();
         let ciphersuites = self.tls_ciphersuites.clone();
         let tls_min_version = self.tls_min_version.clone();
         let tls_max_version = self.tls_max_version.clone();
-        let mut lb = http_proxy_service(conf, self);
+        let unix_socket_mode = self.unix_socket_mode;
+        let connection_limit = self.connection_limit.clone();
+        let app = ConnectionLimitApp::new(
+            &name,
+            TlsConnectionApp::new(http_proxy(conf, self)),
+            connection_limit,
+        );
+        let mut lb = Service::new(format!("Pingap Server({name})"), app);
         lb.threads = threads;
         // support listen multi adddress
         for addr in addr.split(',') {
+            // unix socket
+            if let Some(path) = util::get_unix_socket_path(addr) {
+                if dynamic_cert.is_some() {
+                    return Err(Error::Common {
+                        category: "unix".to_string(),
+                        message: "tls is not supported for unix socket".to_string(),
+                    });
+                }
+                if path.starts_with('\0') {
+                    return Err(Error::Common {
+                        category: "unix".to_string(),
+                        message: "abstract socket is not supported".to_string(),
+                    });
+                }
+                lb.add_uds(&path, unix_socket_mode.map(Permissions::from_mode));
+                continue;
+            }
             // tls
             if let Some(dynamic_cert) = &dynamic_cert {
                 let mut tls_settings =
@@ -236,6 +447,33 @@ impl Server {
                 if enbaled_h2 {
                     tls_settings.enable_h2();
                 }
+                if tls_alpn_challenge {
+                    set_acme_tls_alpn(&mut tls_settings, enbaled_h2);
+                }
+                set_tls_session(&mut tls_settings, &name, &tls_session).map_err(|e| {
+                    Error::Common {
+                        category: "tls".to_string(),
+                        message: e.to_string(),
+                    }
+                })?;
+                if enabled_tls_fingerprint {
+                    set_tls_fingerprint_callback(&mut tls_settings);
+                }
+                if enabled_ocsp_stapling {
+                    set_ocsp_stapling(&mut tls_settings, &name, ocsp_response_file.clone())
+                        .map_err(|e| Error::Common {
+                            category: "tls".to_string(),
+                            message: e.to_string(),
+                        })?;
+                }
+                if let Some(client_ca) = &client_ca {
+                    set_client_cert_verify(&mut tls_settings, client_ca, &client_verify).map_err(
+                        |e| Error::Common {
+                            category: "tls".to_string(),
+                            message: e.to_string(),
+                        },
+                    )?;
+                }
                 if let Some(cipher_list) = &cipher_list {
                     if let Err(e) = tls_settings.set_cipher_list(cipher_list) {
                         error!("Set cipher list fail, error:{e:?}");
@@ -272,7 +510,10 @@ impl Server {
                 lb.add_tcp(addr);
             }
         }
-        Ok(ServerServices { tls_cert_info, lb })
+        Ok(ServerServices {
+            tls_cert_info_list,
+            lb,
+        })
     }
     async fn serve_admin(&self, session: &mut Session, ctx: &mut State) -> pingora::Result<()> {
         if let Some(plugin) = get_proxy_plugin(util::ADMIN_SERVER_PLUGIN.as_str()) {
@@ -289,6 +530,131 @@ impl Server {
         }
         Ok(())
     }
+    /// Retry the request with the upstream of error page location,
+    /// it's used for the failure of connecting or proxying to upstream.
+    /// Returns true if the request should be retried.
+    fn try_proxy_error_page(&self, session: &mut Session, ctx: &mut State, code: u16) -> bool {
+        // avoid error page loop
+        if ctx.error_page_location.is_some() {
+            return false;
+        }
+        let name = if let Some(ErrorPage::Location(name)) =
+            get_location(&ctx.location).and_then(|lo| lo.get_error_page(code).cloned())
+        {
+            name
+        } else {
+            return false;
+        };
+        // the location without upstream is served by plugins in fail_to_proxy
+        let lo = match get_location(&name) {
+            Some(lo) if get_upstream(&lo.upstream).is_some() => lo,
+            _ => return false,
+        };
+        debug!("Error page {code} is proxied by location {name}");
+        let header = session.req_header_mut();
+        // the path is rewritten from the original uri instead of the rewritten one
+        if let Some(uri) = &ctx.original_uri {
+            header.set_uri(uri.clone());
+        }
+        lo.rewrite(header);
+        ctx.error_page_location = Some(name);
+        ctx.error_page_status = Some(code);
+        true
+    }
+    /// Serve the error page fallback of location, the plugins of target location
+    /// will be executed without a client round trip.
+    /// Returns the status of response if the error page is served.
+    async fn serve_error_page(
+        &self,
+        session: &mut Session,
+        ctx: &mut State,
+        code: u16,
+    ) -> Option<u16> {
+        // avoid error page loop
+        if ctx.error_page_location.is_some() {
+            return None;
+        }
+        let page = get_location(&ctx.location)?.get_error_page(code)?.clone();
+        match page {
+            ErrorPage::Location(name) => {
+                let lo = get_location(&name)?;
+                debug!("Error page {code} is served by location {name}");
+                ctx.error_page_location = Some(name);
+                match lo
+                    .exec_proxy_plugins(session, ctx, PluginStep::Request)
+                    .await
+                {
+                    Ok(true) => Some(ctx.status.map_or(code, |status| status.as_u16())),
+                    Ok(false) => None,
+                    Err(e) => {
+                        error!("Serve error page fail, error: {e}");
+                        None
+                    }
+                }
+            }
+            ErrorPage::Body(body) => {
+                let status = StatusCode::from_u16(code).ok()?;
+                let resp = HttpResponse {
+                    status,
+                    body: body.into(),
+                    headers: Some(vec![HTTP_HEADER_NO_STORE.clone()]),
+                    ..Default::default()
+                };
+                match resp.send(session).await {
+                    Ok(size) => {
+                        ctx.status = Some(status);
+                        ctx.response_body_size = size;
+                        Some(code)
+                    }
+                    Err(e) => {
+                        error!("Serve error page fail, error: {e}");
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Get the response status of error.
+fn get_error_status(e: &pingora::Error) -> u16 {
+    match e.etype() {
+        pingora::HTTPStatus(code) => *code,
+        _ => match e.esource() {
+            pingora::ErrorSource::Upstream => 502,
+            pingora::ErrorSource::Downstream => match e.etype() {
+                pingora::ErrorType::WriteError | pingora::ErrorType::ReadError => 500,
+                // client close the connection
+                pingora::ErrorType::ConnectionClosed => 499,
+                pingora::ErrorType::ReadTimedout => 408,
+                _ => 400,
+            },
+            pingora::ErrorSource::Internal | pingora::ErrorSource::Unset => 500,
+        },
+    }
+}
+
+/// Returns true if the average transfer rate is lower than the min rate,
+/// it's checked after the grace period.
+#[inline]
+fn is_transfer_rate_too_low(size: usize, elapsed: Duration, min_rate: u64) -> bool {
+    elapsed > MIN_TRANSFER_RATE_GRACE_PERIOD
+        && (size as f64 / elapsed.as_secs_f64()) < min_rate as f64
+}
+
+#[inline]
+fn set_tls_connection_info(digest: &Digest, ctx: &mut State) {
+    if let Some(info) = get_tls_connection_info(digest) {
+        if let Some(cert) = &info.client_cert {
+            ctx.client_cert_subject = Some(cert.subject.clone());
+            ctx.client_cert_sans = Some(cert.sans.clone());
+            ctx.client_cert_fingerprint = Some(cert.fingerprint.clone());
+        }
+        if let Some(fingerprint) = &info.fingerprint {
+            ctx.ja3 = Some(fingerprint.ja3.clone());
+            ctx.ja4 = Some(fingerprint.ja4.clone());
+        }
+    }
 }
 
 #[inline]
@@ -326,12 +692,14 @@ impl ProxyHttp for Server {
     where
         Self::CTX: Send + Sync,
     {
+        // the request header is read, pause the header read timeout
+        set_header_read(session.digest());
         let mut location = None;
         let header = session.req_header_mut();
         let host = util::get_host(header).unwrap_or_default();
         if let Some(locations) = get_server_locations(&self.name) {
             let path = header.uri.path();
-            for name in locations.iter() {
+            for (name, _) in locations.iter() {
                 if let Some(lo) = get_location(name) {
                     if lo.matched(host, path) {
                         location = Some(lo);
@@ -360,10 +728,22 @@ impl ProxyHttp for Server {
             let (established, tls_version) = get_digest_detail(digest);
             ctx.established = established;
             ctx.tls_version = tls_version;
+            // the client certificate and fingerprint of connection,
+            // they are the same for the requests of any alpn
+            set_tls_connection_info(digest, ctx);
         };
         ctx.processing = self.processing.fetch_add(1, Ordering::Relaxed) + 1;
         ctx.accepted = self.accepted.fetch_add(1, Ordering::Relaxed) + 1;
         ctx.remote_addr = util::get_remote_addr(session);
+        // disable keepalive in draining mode,
+        // so the client will reconnect to other instance
+        if state::is_draining(&self.name) {
+            ctx.draining = true;
+            session.set_keepalive(None);
+        }
+        if let Some(timeout) = self.body_read_timeout {
+            session.set_read_timeout(timeout);
+        }
         if self.admin {
             self.serve_admin(session, ctx).await?;
             return Ok(true);
@@ -392,7 +772,10 @@ impl ProxyHttp for Server {
         let lo = location.unwrap();
 
         debug!("Location {} is matched", lo.name);
-        lo.rewrite(header);
+        let uri = header.uri.clone();
+        if lo.rewrite(header) {
+            ctx.original_uri = Some(uri);
+        }
 
         // body limit
         lo.client_body_size_limit(Some(header), ctx)?;
@@ -401,6 +784,16 @@ impl ProxyHttp for Server {
         ctx.location_accepted = lo.accepted.fetch_add(1, Ordering::Relaxed) + 1;
         ctx.location_processing = lo.processing.fetch_add(1, Ordering::Relaxed) + 1;
 
+        if util::is_websocket_upgrade(header) {
+            ctx.location_websocket_processing = lo.websocket_acquire()?;
+            ctx.websocket = true;
+            ctx.websocket_processing =
+                self.websocket_processing.fetch_add(1, Ordering::Relaxed) + 1;
+        } else {
+            ctx.location_websocket_processing = lo.websocket_processing.load(Ordering::Relaxed);
+            ctx.websocket_processing = self.websocket_processing.load(Ordering::Relaxed);
+        }
+
         let done = lo
             .exec_proxy_plugins(session, ctx, PluginStep::Request)
             .await?;
@@ -435,13 +828,21 @@ impl ProxyHttp for Server {
         session: &mut Session,
         ctx: &mut State,
     ) -> pingora::Result<Box<HttpPeer>> {
-        let peer = if let Some(lo) = get_location(&ctx.location) {
+        // the upstream of error page location is used for retry
+        let name = ctx.error_page_location.as_ref().unwrap_or(&ctx.location);
+        let peer = if let Some(lo) = get_location(name) {
             let up = get_upstream(&lo.upstream).ok_or(util::new_internal_error(
                 503,
                 format!("No upstream({}:{})", lo.name, lo.upstream),
             ))?;
             ctx.upstream_connected = up.connected();
-            up.new_http_peer(session, ctx)
+            up.new_http_peer(session, ctx).map(|mut peer| {
+                // the idle timeout of websocket replaces the read timeout
+                if ctx.websocket && lo.websocket_idle_timeout.is_some() {
+                    peer.options.read_timeout = lo.websocket_idle_timeout;
+                }
+                peer
+            })
         } else {
             None
         }
@@ -481,6 +882,18 @@ impl ProxyHttp for Server {
     {
         if let Some(buf) = body {
             ctx.payload_size += buf.len();
+            if let Some(min_rate) = self.min_transfer_rate {
+                // the time of reading header and connecting upstream is excluded,
+                // the wait of first chunk is limited by body read timeout
+                let started_at = *ctx.request_body_started_at.get_or_insert_with(Instant::now);
+                if is_transfer_rate_too_low(ctx.payload_size, started_at.elapsed(), min_rate) {
+                    inc_connection_timeout();
+                    return Err(util::new_internal_error(
+                        408,
+                        "Request body transfer rate is too low".to_string(),
+                    ));
+                }
+            }
             if let Some(lo) = get_location(&ctx.location) {
                 lo.client_body_size_limit(None, ctx)?;
             }
@@ -566,7 +979,38 @@ impl ProxyHttp for Server {
             }
         }
 
+        // access log for websocket upgrade
+        if ctx.websocket && upstream_response.status == StatusCode::SWITCHING_PROTOCOLS {
+            if let Some(p) = &self.log_parser {
+                info!("{}", p.format(session, ctx));
+            }
+        }
+
+        // the response of error page location uses the original status
+        if let Some(code) = ctx.error_page_status {
+            if let Ok(status) = StatusCode::from_u16(code) {
+                upstream_response.set_status(status)?;
+                ctx.status = Some(status);
+            }
+        }
+
         if let Some(lo) = get_location(&ctx.location) {
+            let status = upstream_response.status.as_u16();
+            // intercept the upstream error response,
+            // it will be served by error page in fail_to_proxy
+            if ctx.error_page_location.is_none()
+                && status >= 400
+                && (lo.intercept_errors || lo.get_error_page(status).is_some())
+            {
+                return Err(util::new_internal_error(
+                    status,
+                    upstream_response
+                        .status
+                        .canonical_reason()
+                        .unwrap_or("Upstream error")
+                        .to_string(),
+                ));
+            }
             lo.exec_response_plugins(session, ctx, upstream_response, PluginStep::Response)
                 .await?;
         }
@@ -641,6 +1085,40 @@ impl ProxyHttp for Server {
         Ok(None)
     }
 
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        if self.try_proxy_error_page(session, ctx, get_error_status(&e)) {
+            e.set_retry(true);
+        }
+        e
+    }
+
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<pingora::Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<pingora::Error> {
+        let mut e = e.more_context(format!("Peer: {peer}"));
+        // the response of upstream is not sent to client if it's intercepted
+        let intercepted = matches!(e.etype(), pingora::HTTPStatus(_));
+        if (ctx.status.is_none() || intercepted)
+            && self.try_proxy_error_page(session, ctx, get_error_status(&e))
+        {
+            e.set_retry(true);
+        } else {
+            e.retry.decide_reuse(client_reused);
+        }
+        e
+    }
+
     async fn fail_to_proxy(
         &self,
         session: &mut Session,
@@ -650,36 +1128,66 @@ impl ProxyHttp for Server {
     where
         Self::CTX: Send + Sync,
     {
-        let server_session = session.as_mut();
+        let code = get_error_status(e);
+        if code == 408 {
+            inc_connection_timeout();
+        }
+        if let Some(status) = self.serve_error_page(session, ctx, code).await {
+            return status;
+        }
 
-        let code = match e.etype() {
-            pingora::HTTPStatus(code) => *code,
-            _ => match e.esource() {
-                pingora::ErrorSource::Upstream => 502,
-                pingora::ErrorSource::Downstream => match e.etype() {
-                    pingora::ErrorType::WriteError | pingora::ErrorType::ReadError => 500,
-                    // client close the connection
-                    pingora::ErrorType::ConnectionClosed => 499,
-                    _ => 400,
-                },
-                pingora::ErrorSource::Internal | pingora::ErrorSource::Unset => 500,
-            },
+        // the error format and template of location has higher priority
+        let lo = get_location(&ctx.location);
+        let mut format = lo
+            .as_ref()
+            .and_then(|lo| lo.error_format.clone())
+            .unwrap_or(self.error_format.clone());
+        if format == ERROR_FORMAT_AUTO {
+            let accept = session
+                .get_header(http::header::ACCEPT)
+                .map(|v| v.to_str().unwrap_or_default())
+                .unwrap_or_default();
+            format = get_error_format(accept).to_string();
+        }
+        let template = lo
+            .as_ref()
+            .and_then(|lo| lo.get_error_template(code))
+            .or(self.error_templates.get(&code))
+            .unwrap_or(&self.error_template);
+        // the detail of error may contain the address of upstream and other
+        // internal information, so it's only logged
+        error!(
+            "Server({}) fail to proxy, request_id:{}, status:{code}, error:{e}",
+            self.name,
+            ctx.request_id.clone().unwrap_or_default()
+        );
+        let message = StatusCode::from_u16(code)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or("Unknown Error")
+            .to_string();
+        let body = ErrorBody {
+            status: code,
+            code: e.etype().as_str().to_string(),
+            message,
+            request_id: ctx.request_id.clone().unwrap_or_default(),
+            host: util::get_host(session.req_header())
+                .unwrap_or_default()
+                .to_string(),
+            time: chrono::Local::now().to_rfc3339(),
         };
-        // TODO better error handler(e.g. json response)
+        let (content_type, buf) = body.render(&format, template);
+
+        let server_session = session.as_mut();
         let mut resp = match code {
             502 => error_resp::HTTP_502_RESPONSE.clone(),
             400 => error_resp::HTTP_400_RESPONSE.clone(),
             _ => error_resp::gen_error_response(code),
         };
 
-        let content = self
-            .error_template
-            .replace("{{version}}", util::get_pkg_version())
-            .replace("{{content}}", &e.to_string());
-        let buf = Bytes::from(content);
         ctx.status = Some(StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
         ctx.response_body_size = buf.len();
-        let _ = resp.insert_header(http::header::CONTENT_TYPE, "text/html; charset=utf-8");
+        let _ = resp.insert_header(content_type.0, content_type.1);
         let _ = resp.insert_header(http::header::CONTENT_LENGTH, buf.len().to_string());
 
         // TODO: we shouldn't be closing downstream connections on internally generated errors
@@ -704,9 +1212,17 @@ impl ProxyHttp for Server {
     where
         Self::CTX: Send + Sync,
     {
+        // the header read timeout of next request starts from now
+        set_request_done(session.digest());
         self.processing.fetch_sub(1, Ordering::Relaxed);
+        if ctx.websocket {
+            self.websocket_processing.fetch_sub(1, Ordering::Relaxed);
+        }
         if let Some(lo) = get_location(&ctx.location) {
             lo.processing.fetch_sub(1, Ordering::Relaxed);
+            if ctx.websocket {
+                lo.websocket_release();
+            }
         }
         if ctx.status.is_none() {
             if let Some(header) = session.response_written() {
@@ -733,7 +1249,7 @@ impl ProxyHttp for Server {
 mod tests {
     use super::Server;
     use crate::config::PingapConf;
-    use crate::proxy::server::get_digest_detail;
+    use crate::proxy::server::{get_digest_detail, get_error_status, is_transfer_rate_too_low};
     use crate::proxy::{
         try_init_locations, try_init_server_locations, try_init_upstreams, ServerConf,
     };
@@ -748,6 +1264,41 @@ mod tests {
     use std::time::{Duration, SystemTime};
     use tokio_test::io::Builder;
 
+    #[test]
+    fn test_is_transfer_rate_too_low() {
+        // in grace period
+        assert_eq!(
+            false,
+            is_transfer_rate_too_low(10, Duration::from_secs(3), 1024)
+        );
+        assert_eq!(
+            true,
+            is_transfer_rate_too_low(10, Duration::from_secs(6), 1024)
+        );
+        assert_eq!(
+            false,
+            is_transfer_rate_too_low(10 * 1024, Duration::from_secs(6), 1024)
+        );
+    }
+
+    #[test]
+    fn test_get_error_status() {
+        assert_eq!(
+            404,
+            get_error_status(&crate::util::new_internal_error(404, "".to_string()))
+        );
+        let mut e = pingora::Error::new(pingora::ErrorType::ConnectRefused);
+        e.set_esource(pingora::ErrorSource::Upstream);
+        assert_eq!(502, get_error_status(&e));
+        let mut e = pingora::Error::new(pingora::ErrorType::ReadTimedout);
+        e.set_esource(pingora::ErrorSource::Downstream);
+        assert_eq!(408, get_error_status(&e));
+        assert_eq!(
+            500,
+            get_error_status(&pingora::Error::new(pingora::ErrorType::InternalError))
+        );
+    }
+
     #[test]
     fn test_get_digest_detail() {
         let digest = Digest {
@@ -780,6 +1331,16 @@ mod tests {
         Server::new(&confs[0]).unwrap()
     }
 
+    #[test]
+    fn test_new_server_certificate_dir_fail() {
+        let result = Server::new(&ServerConf {
+            name: "test".to_string(),
+            tls_certificate_dir: Some("/pingap-not-exists".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(true, result.is_err());
+    }
+
     #[test]
     fn test_new_server() {
         let server = new_server();
@@ -826,6 +1387,35 @@ mod tests {
         assert_eq!(false, done);
     }
 
+    #[tokio::test]
+    async fn test_request_filter_websocket_fallthrough() {
+        let server = new_server();
+
+        // plain request and the request without `Connection: upgrade`
+        // are proxied as normal http request
+        for headers in [
+            vec![""],
+            vec!["Upgrade: websocket"],
+            vec!["Connection: keep-alive"],
+        ] {
+            let headers = headers.join("\r\n");
+            let input_header = format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+
+            let mut ctx = State {
+                location: "lo".to_string(),
+                ..Default::default()
+            };
+            let done = server.request_filter(&mut session, &mut ctx).await.unwrap();
+            assert_eq!(false, done);
+            assert_eq!(false, ctx.websocket);
+            assert_eq!(0, ctx.websocket_processing);
+            assert_eq!(0, ctx.location_websocket_processing);
+        }
+    }
+
     #[tokio::test]
     async fn test_cache_key_callback() {
         let server = new_server();
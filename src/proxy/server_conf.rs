@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::PingapConf;
+use super::tls_session::TlsSessionOptions;
+use crate::config::{load_error_template, parse_error_template, PingapConf};
 use crate::util;
 use base64::{engine::general_purpose::STANDARD, Engine};
 use pingora::protocols::l4::ext::TcpKeepalive;
+use std::collections::HashMap;
 use std::fmt;
 
 static ERROR_TEMPLATE: &str = include_str!("../../error.html");
@@ -29,15 +31,33 @@ pub struct ServerConf {
     pub locations: Vec<String>,
     pub tls_cert: Option<Vec<u8>>,
     pub tls_key: Option<Vec<u8>>,
+    pub tls_certificates: Vec<(Vec<u8>, Vec<u8>)>,
+    pub tls_certificate_dir: Option<String>,
+    pub tls_auto: Option<String>,
+    pub tls_client_ca: Option<Vec<u8>>,
+    pub tls_client_verify: Option<String>,
+    pub enabled_ocsp_stapling: bool,
+    pub tls_ocsp_response_file: Option<String>,
+    pub tls_session: TlsSessionOptions,
+    pub enabled_tls_fingerprint: bool,
     pub tls_cipher_list: Option<String>,
     pub tls_ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
     pub threads: Option<usize>,
     pub error_template: String,
+    pub error_format: Option<String>,
+    pub error_templates: HashMap<u16, String>,
     pub lets_encrypt: Option<String>,
+    pub lets_encrypt_group: String,
     pub tcp_keepalive: Option<TcpKeepalive>,
     pub tcp_fastopen: Option<usize>,
+    pub unix_socket_mode: Option<u32>,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub header_read_timeout: Option<Duration>,
+    pub body_read_timeout: Option<Duration>,
+    pub min_transfer_rate: Option<u64>,
     pub enbaled_h2: bool,
 }
 
@@ -49,7 +69,11 @@ impl fmt::Display for ServerConf {
         write!(
             f,
             "tls:{} ",
-            self.tls_cert.is_some() || self.lets_encrypt.is_some()
+            self.tls_cert.is_some()
+                || !self.tls_certificates.is_empty()
+                || self.tls_certificate_dir.is_some()
+                || self.tls_auto.is_some()
+                || self.lets_encrypt.is_some()
         )?;
         write!(f, "threads:{:?} ", self.threads)?;
         write!(f, "lets_encrypt:{:?} ", self.lets_encrypt)?;
@@ -59,6 +83,15 @@ impl fmt::Display for ServerConf {
     }
 }
 
+// load config validate base64, so ignore error
+pub(super) fn convert_pem_or_base64(value: &str) -> Vec<u8> {
+    if util::is_pem(value) {
+        value.as_bytes().to_vec()
+    } else {
+        STANDARD.decode(value).unwrap_or_default()
+    }
+}
+
 impl From<PingapConf> for Vec<ServerConf> {
     fn from(conf: PingapConf) -> Self {
         let mut upstreams = vec![];
@@ -73,32 +106,36 @@ impl From<PingapConf> for Vec<ServerConf> {
         locations.sort_by_key(|b| std::cmp::Reverse(b.1.get_weight()));
         let mut servers = vec![];
         for (name, item) in conf.servers {
-            let mut tls_cert = None;
-            let mut tls_key = None;
-            // load config validate base64
-            // so ignore error
-            if let Some(value) = &item.tls_cert {
-                if util::is_pem(value) {
-                    tls_cert = Some(value.as_bytes().to_vec());
-                } else {
-                    let buf = STANDARD.decode(value).unwrap_or_default();
-                    tls_cert = Some(buf);
-                }
-            }
-            if let Some(value) = &item.tls_key {
-                if util::is_pem(value) {
-                    tls_key = Some(value.as_bytes().to_vec());
-                } else {
-                    let buf = STANDARD.decode(value).unwrap_or_default();
-                    tls_key = Some(buf);
-                }
-            }
+            let tls_cert = item.tls_cert.as_ref().map(|v| convert_pem_or_base64(v));
+            let tls_key = item.tls_key.as_ref().map(|v| convert_pem_or_base64(v));
+            let tls_certificates = item
+                .tls_certificates
+                .unwrap_or_default()
+                .iter()
+                .map(|v| {
+                    (
+                        convert_pem_or_base64(&v.cert),
+                        convert_pem_or_base64(&v.key),
+                    )
+                })
+                .collect();
 
             let mut error_template = conf.basic.error_template.clone().unwrap_or_default();
             if error_template.is_empty() {
                 error_template = ERROR_TEMPLATE.to_string();
             }
 
+            let mut error_templates = HashMap::new();
+            for value in item.error_templates.clone().unwrap_or_default().iter() {
+                // the config is validated, so ignore invalid template
+                if let Ok((status_list, template)) = parse_error_template(value) {
+                    let template = load_error_template(&template).unwrap_or_default();
+                    for status in status_list {
+                        error_templates.insert(status, template.clone());
+                    }
+                }
+            }
+
             let mut threads = item.threads;
             if threads.is_none() {
                 threads = conf.basic.threads;
@@ -127,11 +164,37 @@ impl From<PingapConf> for Vec<ServerConf> {
                 None
             };
 
+            // each server has its own certificate if group is not set
+            let lets_encrypt_group = item
+                .lets_encrypt_group
+                .clone()
+                .unwrap_or_else(|| name.clone());
+
             servers.push(ServerConf {
                 name,
                 admin: false,
                 tls_cert,
                 tls_key,
+                tls_certificates,
+                tls_certificate_dir: item.tls_certificate_dir,
+                tls_auto: item.tls_auto,
+                tls_client_ca: item
+                    .tls_client_ca
+                    .as_ref()
+                    .map(|v| convert_pem_or_base64(v)),
+                tls_client_verify: item.tls_client_verify,
+                enabled_ocsp_stapling: item.enabled_ocsp_stapling.unwrap_or_default()
+                    || item.tls_ocsp_response_file.is_some(),
+                tls_ocsp_response_file: item.tls_ocsp_response_file,
+                enabled_tls_fingerprint: item.enabled_tls_fingerprint.unwrap_or_default(),
+                tls_session: TlsSessionOptions {
+                    cache_size: item.tls_session_cache_size,
+                    timeout: item.tls_session_timeout,
+                    enabled_ticket: item.enabled_tls_session_ticket.unwrap_or(true),
+                    ticket_keys: item.tls_ticket_keys.unwrap_or_default(),
+                    ticket_key_file: item.tls_ticket_key_file,
+                    ticket_key_rotation: item.tls_ticket_key_rotation,
+                },
                 tls_cipher_list: item.tls_cipher_list.clone(),
                 tls_ciphersuites: item.tls_ciphersuites.clone(),
                 tls_min_version: item.tls_min_version.clone(),
@@ -141,10 +204,21 @@ impl From<PingapConf> for Vec<ServerConf> {
                 locations: item.locations.unwrap_or_default(),
                 threads,
                 lets_encrypt: item.lets_encrypt,
+                lets_encrypt_group,
                 enbaled_h2: item.enabled_h2.unwrap_or(true),
                 tcp_keepalive,
                 tcp_fastopen: item.tcp_fastopen,
+                unix_socket_mode: item
+                    .unix_socket_mode
+                    .and_then(|v| u32::from_str_radix(&v, 8).ok()),
                 error_template,
+                error_format: item.error_format,
+                error_templates,
+                max_connections: item.max_connections,
+                max_connections_per_ip: item.max_connections_per_ip,
+                header_read_timeout: item.header_read_timeout,
+                body_read_timeout: item.body_read_timeout,
+                min_transfer_rate: item.min_transfer_rate.map(|v| v.as_u64()),
             });
         }
 
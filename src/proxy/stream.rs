@@ -0,0 +1,699 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::connection_limit::{ConnectionLimitApp, ConnectionLimitOptions};
+use super::dynamic_cert::DynamicCert;
+use super::server::load_static_certificates;
+use super::server_conf::convert_pem_or_base64;
+use super::upstream::get_upstream;
+use crate::config::{parse_sni_upstream, StreamConf};
+use async_trait::async_trait;
+use log::{debug, error, info};
+use pingora::apps::ServerApp;
+use pingora::connectors::TransportConnector;
+use pingora::listeners::TlsSettings;
+use pingora::protocols::{GetSocketDigest, Ssl, Stream};
+use pingora::server::ShutdownWatch;
+use pingora::services::listening::Service;
+use pingora::tls::ssl::NameType;
+use pingora::upstreams::peer::Peer;
+use snafu::Snafu;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error {category} {message}"))]
+    Common { category: String, message: String },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const TLS_RECORD_HANDSHAKE: u8 = 0x16;
+const TLS_HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const TLS_RECORD_HEADER_SIZE: usize = 5;
+const TLS_RECORD_MAX_SIZE: usize = 16 * 1024;
+const COPY_BUFFER_SIZE: usize = 16 * 1024;
+// the client hello is sent immediately after connected,
+// so the timeout of reading it should be short
+const DEFAULT_CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(3);
+
+static DEFAULT_STREAM_LOG: &str =
+    "{remote} {sni} {upstream} {upstream_addr} {bytes_in} {bytes_out} {duration}ms {status}";
+
+fn read_u8(data: &[u8], offset: usize) -> Option<usize> {
+    data.get(offset).map(|v| *v as usize)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<usize> {
+    if data.len() < offset + 2 {
+        return None;
+    }
+    Some(((data[offset] as usize) << 8) | data[offset + 1] as usize)
+}
+
+/// Parse the server name from the first tls record(client hello),
+/// it is used for routing tls stream without termination.
+fn parse_client_hello_sni(data: &[u8]) -> Option<String> {
+    if data.first() != Some(&TLS_RECORD_HANDSHAKE)
+        || data.get(TLS_RECORD_HEADER_SIZE) != Some(&TLS_HANDSHAKE_CLIENT_HELLO)
+    {
+        return None;
+    }
+    // record header(5) + handshake header(4) + version(2) + random(32)
+    let mut offset = TLS_RECORD_HEADER_SIZE + 4 + 2 + 32;
+    // session id
+    offset += 1 + read_u8(data, offset)?;
+    // cipher suites
+    offset += 2 + read_u16(data, offset)?;
+    // compression methods
+    offset += 1 + read_u8(data, offset)?;
+    let extensions_end = (offset + 2 + read_u16(data, offset)?).min(data.len());
+    offset += 2;
+    while offset + 4 <= extensions_end {
+        let ext_type = read_u16(data, offset)?;
+        let ext_size = read_u16(data, offset + 2)?;
+        offset += 4;
+        if ext_type == 0 {
+            // server name list size(2) + name type(1) + name size(2)
+            let size = read_u16(data, offset + 3)?;
+            let start = offset + 5;
+            let value = data.get(start..start + size)?;
+            // the sni is from peer, only the characters of domain are allowed
+            if value.is_empty()
+                || !value
+                    .iter()
+                    .all(|ch| ch.is_ascii_alphanumeric() || b".-_*".contains(ch))
+            {
+                return None;
+            }
+            return Some(String::from_utf8_lossy(value).to_lowercase());
+        }
+        offset += ext_size;
+    }
+    None
+}
+
+/// Read the first tls record from stream,
+/// it will be forwarded to upstream after the sni is parsed.
+async fn read_client_hello(stream: &mut Stream) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; TLS_RECORD_HEADER_SIZE + TLS_RECORD_MAX_SIZE];
+    let mut size = 0;
+    loop {
+        let n = stream.read(&mut buf[size..]).await?;
+        if n == 0 {
+            break;
+        }
+        size += n;
+        if size < TLS_RECORD_HEADER_SIZE {
+            continue;
+        }
+        // not a tls handshake or the whole record is read
+        if buf[0] != TLS_RECORD_HANDSHAKE
+            || size >= TLS_RECORD_HEADER_SIZE + read_u16(&buf, 3).unwrap_or_default()
+            || size == buf.len()
+        {
+            break;
+        }
+    }
+    buf.truncate(size);
+    Ok(buf)
+}
+
+/// Match the sni domain, `*.example.com` matches the sub domain of one level.
+fn is_domain_match(domain: &str, sni: &str) -> bool {
+    if let Some(suffix) = domain.strip_prefix('*') {
+        if let Some(prefix) = sni.strip_suffix(suffix) {
+            return !prefix.is_empty() && !prefix.contains('.');
+        }
+        return false;
+    }
+    domain == sni
+}
+
+#[derive(Default)]
+struct StreamLog {
+    remote: String,
+    sni: String,
+    upstream: String,
+    upstream_addr: String,
+    bytes_in: u64,
+    bytes_out: u64,
+    duration: u128,
+    status: String,
+}
+
+impl StreamLog {
+    fn get_value(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "{remote}" => self.remote.clone(),
+            "{sni}" => self.sni.clone(),
+            "{upstream}" => self.upstream.clone(),
+            "{upstream_addr}" => self.upstream_addr.clone(),
+            "{bytes_in}" => self.bytes_in.to_string(),
+            "{bytes_out}" => self.bytes_out.to_string(),
+            "{duration}" => self.duration.to_string(),
+            "{status}" => self.status.clone(),
+            _ => return None,
+        };
+        // the values may be from peer, escape the control characters
+        Some(value.escape_default().to_string())
+    }
+    /// Format the log by template, the template is scanned only once,
+    /// so the values can't be treated as the tags of template.
+    fn format(&self, template: &str) -> String {
+        let mut buf = String::with_capacity(template.len() + 64);
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            buf.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            if let Some(end) = tail.find('}') {
+                let key = &tail[..end + 1];
+                if let Some(value) = self.get_value(key) {
+                    buf.push_str(&value);
+                } else {
+                    buf.push_str(key);
+                }
+                rest = &tail[end + 1..];
+            } else {
+                buf.push_str(tail);
+                rest = "";
+            }
+        }
+        buf.push_str(rest);
+        buf
+    }
+}
+
+/// Copy data between client and upstream until both sides are closed,
+/// the idle timeout is reset whenever any data is read.
+/// The bytes are counted as the copy runs, so they are kept even if it fails.
+async fn copy_bidirectional_with_idle<A, B>(
+    client: &mut A,
+    upstream: &mut B,
+    idle_timeout: Option<Duration>,
+    bytes_in: &mut u64,
+    bytes_out: &mut u64,
+) -> std::io::Result<()>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut client_buf = vec![0; COPY_BUFFER_SIZE];
+    let mut upstream_buf = vec![0; COPY_BUFFER_SIZE];
+    let mut client_done = false;
+    let mut upstream_done = false;
+    while !client_done || !upstream_done {
+        let read = async {
+            tokio::select! {
+                result = client.read(&mut client_buf), if !client_done => (true, result),
+                result = upstream.read(&mut upstream_buf), if !upstream_done => (false, result),
+            }
+        };
+        let (from_client, result) = if let Some(timeout) = idle_timeout {
+            tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "idle timeout"))?
+        } else {
+            read.await
+        };
+        let size = result?;
+        if from_client {
+            if size == 0 {
+                client_done = true;
+                upstream.shutdown().await?;
+            } else {
+                upstream.write_all(&client_buf[..size]).await?;
+                *bytes_in += size as u64;
+            }
+        } else if size == 0 {
+            upstream_done = true;
+            client.shutdown().await?;
+        } else {
+            client.write_all(&upstream_buf[..size]).await?;
+            *bytes_out += size as u64;
+        }
+    }
+    Ok(())
+}
+
+pub struct StreamServer {
+    name: String,
+    addr: String,
+    upstream: Option<String>,
+    sni_upstreams: Vec<(String, String)>,
+    certificates: Vec<(Vec<u8>, Vec<u8>)>,
+    access_log: Option<String>,
+    threads: Option<usize>,
+    client_hello_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    connection_limit: ConnectionLimitOptions,
+    connector: TransportConnector,
+}
+
+impl StreamServer {
+    /// Create a new server for tcp/tls stream proxy.
+    pub fn new(name: &str, conf: &StreamConf) -> Result<Self> {
+        let mut sni_upstreams = vec![];
+        for item in conf.sni_upstreams.clone().unwrap_or_default() {
+            let value = parse_sni_upstream(&item).map_err(|e| Error::Common {
+                category: "sni".to_string(),
+                message: e.to_string(),
+            })?;
+            sni_upstreams.push(value);
+        }
+        let tls_cert = conf.tls_cert.as_ref().map(|cert| {
+            (
+                convert_pem_or_base64(cert),
+                convert_pem_or_base64(&conf.tls_key.clone().unwrap_or_default()),
+            )
+        });
+        let tls_certificates = conf
+            .tls_certificates
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|item| {
+                (
+                    convert_pem_or_base64(&item.cert),
+                    convert_pem_or_base64(&item.key),
+                )
+            })
+            .collect();
+        let certificates = load_static_certificates(
+            tls_cert,
+            tls_certificates,
+            conf.tls_certificate_dir.as_deref(),
+        )
+        .map_err(|e| Error::Common {
+            category: "tls".to_string(),
+            message: e.to_string(),
+        })?;
+        let access_log = conf.access_log.as_ref().map(|value| {
+            if value.is_empty() || value == "default" {
+                DEFAULT_STREAM_LOG.to_string()
+            } else {
+                value.to_string()
+            }
+        });
+        let mut threads = conf.threads;
+        if threads.unwrap_or_default() == 0 {
+            threads = Some(1);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            addr: conf.addr.clone(),
+            upstream: conf.upstream.clone(),
+            sni_upstreams,
+            certificates,
+            access_log,
+            threads,
+            client_hello_timeout: conf
+                .client_hello_timeout
+                .unwrap_or(DEFAULT_CLIENT_HELLO_TIMEOUT),
+            idle_timeout: conf.idle_timeout,
+            total_timeout: conf.total_timeout,
+            connection_limit: ConnectionLimitOptions {
+                max_connections: conf.max_connections,
+                max_connections_per_ip: conf.max_connections_per_ip,
+                ..Default::default()
+            },
+            connector: TransportConnector::new(None),
+        })
+    }
+    /// Get the upstream name of stream by sni, the default upstream is used if no sni matched.
+    fn get_upstream_name(&self, sni: &str) -> Option<String> {
+        if !sni.is_empty() {
+            for (domain, upstream) in self.sni_upstreams.iter() {
+                if is_domain_match(domain, sni) {
+                    return Some(upstream.clone());
+                }
+            }
+        }
+        self.upstream.clone()
+    }
+    async fn proxy(&self, stream: &mut Stream, log: &mut StreamLog) -> Result<()> {
+        let is_tls = !self.certificates.is_empty();
+        let mut client_hello = vec![];
+        if is_tls {
+            if let Some(ssl) = stream.get_ssl() {
+                log.sni = ssl
+                    .servername(NameType::HOST_NAME)
+                    .unwrap_or_default()
+                    .to_lowercase();
+            }
+        } else if !self.sni_upstreams.is_empty() {
+            // peek the client hello for tls passthrough
+            client_hello =
+                tokio::time::timeout(self.client_hello_timeout, read_client_hello(stream))
+                    .await
+                    .map_err(|e| Error::Common {
+                        category: "client_hello".to_string(),
+                        message: e.to_string(),
+                    })?
+                    .map_err(|e| Error::Common {
+                        category: "client_hello".to_string(),
+                        message: e.to_string(),
+                    })?;
+            log.sni = parse_client_hello_sni(&client_hello).unwrap_or_default();
+        }
+        let upstream_name = self
+            .get_upstream_name(&log.sni)
+            .ok_or_else(|| Error::Common {
+                category: "upstream".to_string(),
+                message: format!("upstream of sni({}) is not found", log.sni),
+            })?;
+        log.upstream.clone_from(&upstream_name);
+        let up = get_upstream(&upstream_name).ok_or_else(|| Error::Common {
+            category: "upstream".to_string(),
+            message: format!("upstream({upstream_name}) is not found"),
+        })?;
+        let peer = up
+            .new_stream_peer(&log.remote)
+            .ok_or_else(|| Error::Common {
+                category: "upstream".to_string(),
+                message: format!("no available backend of upstream({upstream_name})"),
+            })?;
+        log.upstream_addr = peer.address().to_string();
+        let mut upstream_stream =
+            self.connector
+                .new_stream(&peer)
+                .await
+                .map_err(|e| Error::Common {
+                    category: "connect".to_string(),
+                    message: e.to_string(),
+                })?;
+        if !client_hello.is_empty() {
+            log.bytes_in += client_hello.len() as u64;
+            upstream_stream
+                .write_all(&client_hello)
+                .await
+                .map_err(|e| Error::Common {
+                    category: "write".to_string(),
+                    message: e.to_string(),
+                })?;
+        }
+        // the bytes of log are updated even if the copy fails(e.g. idle timeout)
+        copy_bidirectional_with_idle(
+            stream,
+            &mut upstream_stream,
+            self.idle_timeout,
+            &mut log.bytes_in,
+            &mut log.bytes_out,
+        )
+        .await
+        .map_err(|e| Error::Common {
+            category: "copy".to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    /// Add a TCP/TLS listening endpoint for stream proxy.
+    /// The connections of stream are limited by the same app of http server.
+    pub fn run(self) -> Result<Service<ConnectionLimitApp<StreamServer>>> {
+        let addr = self.addr.clone();
+        let threads = self.threads;
+        let dynamic_cert = if self.certificates.is_empty() {
+            None
+        } else {
+            // the name of certificate store should not conflict with server
+            Some(
+                DynamicCert::new(&format!("stream:{}", self.name), &self.certificates).map_err(
+                    |e| Error::Common {
+                        category: "tls".to_string(),
+                        message: e.to_string(),
+                    },
+                )?,
+            )
+        };
+        info!(
+            "Stream({}) is linsten on:{addr}, threads:{threads:?}, tls:{}",
+            self.name,
+            dynamic_cert.is_some()
+        );
+        let name = self.name.clone();
+        let connection_limit = self.connection_limit.clone();
+        let app = ConnectionLimitApp::new(&name, self, connection_limit);
+        let mut service = Service::new(format!("Stream {name}"), app);
+        service.threads = threads;
+        for addr in addr.split(',') {
+            if let Some(dynamic_cert) = &dynamic_cert {
+                let tls_settings =
+                    TlsSettings::with_callbacks(dynamic_cert.clone()).map_err(|e| {
+                        Error::Common {
+                            category: "tls".to_string(),
+                            message: e.to_string(),
+                        }
+                    })?;
+                service.add_tls_with_settings(addr, None, tls_settings);
+            } else {
+                service.add_tcp(addr);
+            }
+        }
+        Ok(service)
+    }
+}
+
+#[async_trait]
+impl ServerApp for StreamServer {
+    async fn process_new(
+        self: &Arc<Self>,
+        mut stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        let started_at = Instant::now();
+        let mut log = StreamLog {
+            status: "ok".to_string(),
+            ..Default::default()
+        };
+        if let Some(digest) = stream.get_socket_digest() {
+            if let Some(addr) = digest.peer_addr().and_then(|addr| addr.as_inet()) {
+                log.remote = addr.ip().to_string();
+            }
+        }
+        debug!(
+            "Stream({}) accept connection from {}",
+            self.name, log.remote
+        );
+        let mut shutdown = shutdown.clone();
+        let proxy = async {
+            if let Some(timeout) = self.total_timeout {
+                tokio::time::timeout(timeout, self.proxy(&mut stream, &mut log))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(Error::Common {
+                            category: "timeout".to_string(),
+                            message: "total timeout".to_string(),
+                        })
+                    })
+            } else {
+                self.proxy(&mut stream, &mut log).await
+            }
+        };
+        let result = tokio::select! {
+            result = proxy => result,
+            // the stream is closed when the server is shutting down
+            _ = async {
+                if shutdown.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            } => Err(Error::Common {
+                category: "shutdown".to_string(),
+                message: "server is shutting down".to_string(),
+            }),
+        };
+        if let Err(e) = result {
+            error!("Stream({}) proxy fail, {e}", self.name);
+            log.status = e.to_string();
+        }
+        log.duration = started_at.elapsed().as_millis();
+        if let Some(template) = &self.access_log {
+            info!("{}", log.format(template));
+        }
+        if let Err(e) = stream.shutdown().await {
+            debug!("Stream({}) shutdown fail, {e}", self.name);
+        }
+        // the stream should not be reused
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        copy_bidirectional_with_idle, is_domain_match, parse_client_hello_sni, StreamLog,
+        StreamServer,
+    };
+    use crate::config::StreamConf;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn new_client_hello(sni: &str) -> Vec<u8> {
+        let name = sni.as_bytes();
+        let mut ext = vec![0x00, 0x00];
+        ext.extend(((name.len() + 5) as u16).to_be_bytes());
+        ext.extend(((name.len() + 3) as u16).to_be_bytes());
+        ext.push(0x00);
+        ext.extend((name.len() as u16).to_be_bytes());
+        ext.extend(name);
+
+        let mut body = vec![0x03, 0x03];
+        body.extend([0; 32]);
+        // session id
+        body.push(0x00);
+        // cipher suites
+        body.extend([0x00, 0x02, 0x13, 0x01]);
+        // compression methods
+        body.extend([0x01, 0x00]);
+        body.extend((ext.len() as u16).to_be_bytes());
+        body.extend(ext);
+
+        let mut handshake = vec![0x01, 0x00];
+        handshake.extend((body.len() as u16).to_be_bytes());
+        handshake.extend(body);
+
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend((handshake.len() as u16).to_be_bytes());
+        record.extend(handshake);
+        record
+    }
+
+    #[test]
+    fn test_parse_client_hello_sni() {
+        assert_eq!(
+            "pg.example.com",
+            parse_client_hello_sni(&new_client_hello("PG.example.com")).unwrap()
+        );
+        assert_eq!(true, parse_client_hello_sni(b"PING\r\n").is_none());
+        let data = new_client_hello("pg.example.com");
+        assert_eq!(true, parse_client_hello_sni(&data[..50]).is_none());
+        // invalid characters of sni
+        assert_eq!(
+            true,
+            parse_client_hello_sni(&new_client_hello("pg.example.com\n{upstream}")).is_none()
+        );
+    }
+
+    #[test]
+    fn test_is_domain_match() {
+        assert_eq!(true, is_domain_match("pg.example.com", "pg.example.com"));
+        assert_eq!(true, is_domain_match("*.example.com", "redis.example.com"));
+        assert_eq!(false, is_domain_match("*.example.com", "a.b.example.com"));
+        assert_eq!(false, is_domain_match("*.example.com", "example.com"));
+    }
+
+    #[test]
+    fn test_stream_server() {
+        let server = StreamServer::new(
+            "pg",
+            &StreamConf {
+                addr: "127.0.0.1:5432".to_string(),
+                upstream: Some("postgres".to_string()),
+                sni_upstreams: Some(vec!["*.example.com redis".to_string()]),
+                access_log: Some("default".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            "redis",
+            server.get_upstream_name("cache.example.com").unwrap()
+        );
+        assert_eq!("postgres", server.get_upstream_name("").unwrap());
+
+        let log = StreamLog {
+            remote: "10.1.1.1".to_string(),
+            sni: "cache.example.com".to_string(),
+            upstream: "redis".to_string(),
+            upstream_addr: "127.0.0.1:6379".to_string(),
+            bytes_in: 100,
+            bytes_out: 2048,
+            duration: 30,
+            status: "ok".to_string(),
+        };
+        assert_eq!(
+            "10.1.1.1 cache.example.com redis 127.0.0.1:6379 100 2048 30ms ok",
+            log.format(server.access_log.as_ref().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_stream_log_format() {
+        let log = StreamLog {
+            remote: "10.1.1.1".to_string(),
+            status: "upstream of sni({upstream}\n) is not found".to_string(),
+            upstream: "redis".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            r#"10.1.1.1 upstream of sni({upstream}\n) is not found {unknown} {sni"#,
+            log.format("{remote} {status} {unknown} {sni")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_bidirectional_with_idle() {
+        let (mut client, mut client_peer) = tokio::io::duplex(1024);
+        let (mut upstream, mut upstream_peer) = tokio::io::duplex(1024);
+        let task = tokio::spawn(async move {
+            let mut bytes_in = 0;
+            let mut bytes_out = 0;
+            let result = copy_bidirectional_with_idle(
+                &mut client_peer,
+                &mut upstream_peer,
+                Some(Duration::from_millis(100)),
+                &mut bytes_in,
+                &mut bytes_out,
+            )
+            .await;
+            (result, bytes_in, bytes_out)
+        });
+        client.write_all(b"PING").await.unwrap();
+        let mut buf = [0; 4];
+        upstream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"PING", &buf);
+        upstream.write_all(b"PONG!").await.unwrap();
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"PONG!", &buf);
+
+        // no data is transferred, the bytes are kept when it fails
+        let (result, bytes_in, bytes_out) = task.await.unwrap();
+        assert_eq!("idle timeout", result.unwrap_err().to_string());
+        assert_eq!((4, 5), (bytes_in, bytes_out));
+
+        let (mut client, mut client_peer) = tokio::io::duplex(1024);
+        let (mut upstream, mut upstream_peer) = tokio::io::duplex(1024);
+        client.write_all(b"PING").await.unwrap();
+        drop(client);
+        upstream.shutdown().await.unwrap();
+        let mut bytes_in = 0;
+        let mut bytes_out = 0;
+        copy_bidirectional_with_idle(
+            &mut client_peer,
+            &mut upstream_peer,
+            None,
+            &mut bytes_in,
+            &mut bytes_out,
+        )
+        .await
+        .unwrap();
+        assert_eq!((4, 0), (bytes_in, bytes_out));
+        let mut buf = vec![];
+        upstream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(b"PING".to_vec(), buf);
+    }
+}
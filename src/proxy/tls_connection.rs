@@ -0,0 +1,170 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::client_cert::{new_client_cert_info, ClientCertInfo};
+use super::tls_fingerprint::{get_tls_fingerprint, TlsFingerprint};
+use once_cell::sync::Lazy;
+use pingora::protocols::{Digest, GetSocketDigest, SocketDigest, Ssl, Stream};
+use pingora::tls::x509::X509VerifyResult;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The tls information of downstream connection, it's derived from
+/// the ssl after handshake, so it's the same for the requests of any alpn.
+#[derive(Debug, Default)]
+pub struct TlsConnectionInfo {
+    pub client_cert: Option<ClientCertInfo>,
+    pub fingerprint: Option<TlsFingerprint>,
+}
+
+// the address of socket digest -> (socket digest, tls information),
+// the socket digest is kept, so its address can't be reused before removed
+type TlsConnections = HashMap<usize, (Arc<SocketDigest>, Arc<TlsConnectionInfo>)>;
+static TLS_CONNECTIONS: Lazy<Mutex<TlsConnections>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The tls information of connection is removed when the guard is dropped.
+pub struct TlsConnectionGuard {
+    key: usize,
+}
+
+impl Drop for TlsConnectionGuard {
+    fn drop(&mut self) {
+        TLS_CONNECTIONS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+    }
+}
+
+#[inline]
+fn get_key(socket_digest: &Arc<SocketDigest>) -> usize {
+    Arc::as_ptr(socket_digest) as usize
+}
+
+/// Register the tls information of downstream connection,
+/// it should be kept until the connection is closed.
+pub fn register_tls_connection(stream: &Stream) -> Option<TlsConnectionGuard> {
+    let ssl = stream.get_ssl()?;
+    let socket_digest = stream.get_socket_digest()?;
+    // the peer certificate is kept in the session, so it's also
+    // available for the resumed session
+    let client_cert = if ssl.verify_result() == X509VerifyResult::OK {
+        ssl.peer_certificate()
+            .and_then(|cert| new_client_cert_info(&cert))
+    } else {
+        None
+    };
+    let info = TlsConnectionInfo {
+        client_cert,
+        fingerprint: get_tls_fingerprint(ssl),
+    };
+    let key = get_key(&socket_digest);
+    TLS_CONNECTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key, (socket_digest, Arc::new(info)));
+    Some(TlsConnectionGuard { key })
+}
+
+/// Get the tls information of downstream connection by the digest of session.
+pub fn get_tls_connection_info(digest: &Digest) -> Option<Arc<TlsConnectionInfo>> {
+    let key = get_key(digest.socket_digest.as_ref()?);
+    TLS_CONNECTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+        .map(|(_, info)| info.clone())
+}
+
+/// The server app which keeps the tls information of downstream
+/// connection for the http processing of the wrapped app.
+pub struct TlsConnectionApp<A> {
+    app: Arc<A>,
+}
+
+impl<A> TlsConnectionApp<A> {
+    pub fn new(app: A) -> Self {
+        Self { app: Arc::new(app) }
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for TlsConnectionApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        // the tls information is kept until the connection is closed
+        let _tls_connection = register_tls_connection(&stream);
+        let mut reuse = self.app.process_new(stream, shutdown).await;
+        while let Some(stream) = reuse {
+            reuse = self.app.process_new(stream, shutdown).await;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_key, get_tls_connection_info, TlsConnectionGuard, TLS_CONNECTIONS};
+    use super::{ClientCertInfo, TlsConnectionInfo};
+    use pingora::protocols::{Digest, SocketDigest};
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tls_connection_info() {
+        let socket_digest = Arc::new(SocketDigest::from_raw_fd(1));
+        let digest = Digest {
+            socket_digest: Some(socket_digest.clone()),
+            ..Default::default()
+        };
+        assert_eq!(true, get_tls_connection_info(&digest).is_none());
+
+        let key = get_key(&socket_digest);
+        TLS_CONNECTIONS.lock().unwrap().insert(
+            key,
+            (
+                socket_digest.clone(),
+                Arc::new(TlsConnectionInfo {
+                    client_cert: Some(ClientCertInfo {
+                        subject: vec!["CN=svc-a".to_string()],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+            ),
+        );
+        let guard = TlsConnectionGuard { key };
+        let info = get_tls_connection_info(&digest).unwrap();
+        assert_eq!(
+            vec!["CN=svc-a".to_string()],
+            info.client_cert.as_ref().unwrap().subject
+        );
+
+        // other connection
+        let other = Digest {
+            socket_digest: Some(Arc::new(SocketDigest::from_raw_fd(1))),
+            ..Default::default()
+        };
+        assert_eq!(true, get_tls_connection_info(&other).is_none());
+
+        drop(guard);
+        assert_eq!(true, get_tls_connection_info(&digest).is_none());
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use foreign_types::ForeignTypeRef;
+use log::error;
+use once_cell::sync::Lazy;
+use openssl_sys::{
+    OPENSSL_free, SSL_client_hello_get0_ciphers, SSL_client_hello_get0_ext,
+    SSL_client_hello_get0_legacy_version, SSL_client_hello_get1_extensions_present,
+};
+use pingora::listeners::TlsSettings;
+use pingora::tls::ex_data::Index;
+use pingora::tls::hash::{hash, MessageDigest};
+use pingora::tls::ssl::{ClientHelloResponse, Ssl, SslRef};
+use std::os::raw::{c_int, c_uchar, c_void};
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// The JA3 and JA4 fingerprints of tls client.
+#[derive(Debug, Clone, Default)]
+pub struct TlsFingerprint {
+    pub ja3: String,
+    pub ja4: String,
+}
+
+/// The fields of client hello for computing fingerprint.
+#[derive(Debug, Default)]
+struct ClientHello {
+    version: u16,
+    ciphers: Vec<u16>,
+    extensions: Vec<u16>,
+    supported_groups: Vec<u16>,
+    ec_point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    alpn: Option<Vec<u8>>,
+    sni: bool,
+}
+
+static TLS_FINGERPRINT_INDEX: Lazy<Option<Index<Ssl, TlsFingerprint>>> = Lazy::new(|| {
+    Ssl::new_ex_index()
+        .map_err(|e| error!("New tls fingerprint index fail, error: {e}"))
+        .ok()
+});
+
+#[inline]
+fn is_grease(value: u16) -> bool {
+    (value & 0x0f0f) == 0x0a0a && (value >> 8) == (value & 0xff)
+}
+
+fn read_u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|item| u16::from_be_bytes([item[0], item[1]]))
+        .collect()
+}
+
+/// Skip the length prefix of extension data.
+fn skip_prefix(data: &[u8], size: usize) -> &[u8] {
+    if data.len() < size {
+        return &[];
+    }
+    &data[size..]
+}
+
+fn join_values<T: ToString>(values: &[T], sep: &str) -> String {
+    values
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+fn sha256_prefix(value: &str) -> String {
+    if value.is_empty() {
+        return "000000000000".to_string();
+    }
+    let digest = hash(MessageDigest::sha256(), value.as_bytes())
+        .map(hex::encode)
+        .unwrap_or_default();
+    digest.chars().take(12).collect()
+}
+
+impl ClientHello {
+    fn set_extension(&mut self, ext_type: u16, data: &[u8]) {
+        match ext_type {
+            EXT_SERVER_NAME => self.sni = true,
+            EXT_SUPPORTED_GROUPS => {
+                self.supported_groups = read_u16_list(skip_prefix(data, 2));
+            }
+            EXT_EC_POINT_FORMATS => {
+                self.ec_point_formats = skip_prefix(data, 1).to_vec();
+            }
+            EXT_SIGNATURE_ALGORITHMS => {
+                self.signature_algorithms = read_u16_list(skip_prefix(data, 2));
+            }
+            EXT_SUPPORTED_VERSIONS => {
+                self.supported_versions = read_u16_list(skip_prefix(data, 1));
+            }
+            EXT_ALPN => {
+                // list length(2) + protocol length(1) + protocol
+                let data = skip_prefix(data, 2);
+                if let Some(size) = data.first() {
+                    let size = *size as usize;
+                    if data.len() > size {
+                        self.alpn = Some(data[1..size + 1].to_vec());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    /// The JA3 fingerprint, md5 of `version,ciphers,extensions,groups,point formats`.
+    fn ja3(&self) -> String {
+        let not_grease = |item: &&u16| !is_grease(**item);
+        let value = format!(
+            "{},{},{},{},{}",
+            self.version,
+            join_values(
+                &self.ciphers.iter().filter(not_grease).collect::<Vec<_>>(),
+                "-"
+            ),
+            join_values(
+                &self
+                    .extensions
+                    .iter()
+                    .filter(not_grease)
+                    .collect::<Vec<_>>(),
+                "-"
+            ),
+            join_values(
+                &self
+                    .supported_groups
+                    .iter()
+                    .filter(not_grease)
+                    .collect::<Vec<_>>(),
+                "-"
+            ),
+            join_values(&self.ec_point_formats, "-"),
+        );
+        hash(MessageDigest::md5(), value.as_bytes())
+            .map(hex::encode)
+            .unwrap_or_default()
+    }
+    /// The JA4 fingerprint of tls over tcp.
+    fn ja4(&self) -> String {
+        let ciphers: Vec<u16> = self
+            .ciphers
+            .iter()
+            .filter(|item| !is_grease(**item))
+            .cloned()
+            .collect();
+        let extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .filter(|item| !is_grease(**item))
+            .cloned()
+            .collect();
+        let version = self
+            .supported_versions
+            .iter()
+            .filter(|item| !is_grease(**item))
+            .max()
+            .cloned()
+            .unwrap_or(self.version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let sni = if self.sni { "d" } else { "i" };
+        let alpn = match &self.alpn {
+            Some(alpn) if !alpn.is_empty() => {
+                let first = alpn[0];
+                let last = alpn[alpn.len() - 1];
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    let first = format!("{first:02x}");
+                    let last = format!("{last:02x}");
+                    format!("{}{}", &first[0..1], &last[1..2])
+                }
+            }
+            _ => "00".to_string(),
+        };
+        let part_a = format!(
+            "t{version}{sni}{:02}{:02}{alpn}",
+            ciphers.len().min(99),
+            extensions.len().min(99)
+        );
+
+        let mut ciphers: Vec<String> = ciphers.iter().map(|item| format!("{item:04x}")).collect();
+        ciphers.sort();
+        let part_b = sha256_prefix(&ciphers.join(","));
+
+        let mut extensions: Vec<String> = extensions
+            .iter()
+            .filter(|item| ![EXT_SERVER_NAME, EXT_ALPN].contains(item))
+            .map(|item| format!("{item:04x}"))
+            .collect();
+        extensions.sort();
+        let mut value = extensions.join(",");
+        if !self.signature_algorithms.is_empty() {
+            let signature_algorithms: Vec<String> = self
+                .signature_algorithms
+                .iter()
+                .map(|item| format!("{item:04x}"))
+                .collect();
+            value = format!("{value}_{}", signature_algorithms.join(","));
+        }
+        let part_c = sha256_prefix(&value);
+
+        format!("{part_a}_{part_b}_{part_c}")
+    }
+}
+
+/// Read the client hello from ssl, it should be called in client hello callback.
+fn get_client_hello(ssl: &SslRef) -> ClientHello {
+    let ptr = ssl.as_ptr();
+    let mut client_hello = ClientHello::default();
+    if ptr.is_null() {
+        return client_hello;
+    }
+    // SAFETY: the ssl is alive and in the client hello callback, so the
+    // buffers returned by openssl are valid until the callback returns.
+    // Every pointer is checked for null and read with the length from openssl.
+    unsafe {
+        client_hello.version = SSL_client_hello_get0_legacy_version(ptr) as u16;
+
+        let mut ciphers: *const c_uchar = std::ptr::null();
+        let size = SSL_client_hello_get0_ciphers(ptr, &mut ciphers);
+        if !ciphers.is_null() && size > 0 {
+            client_hello.ciphers = read_u16_list(std::slice::from_raw_parts(ciphers, size));
+        }
+
+        let mut extensions: *mut c_int = std::ptr::null_mut();
+        let mut size = 0;
+        if SSL_client_hello_get1_extensions_present(ptr, &mut extensions, &mut size) == 1 {
+            if !extensions.is_null() {
+                if size > 0 {
+                    client_hello.extensions = std::slice::from_raw_parts(extensions, size)
+                        .iter()
+                        .map(|item| *item as u16)
+                        .collect();
+                }
+                // the list is allocated by openssl, it should be freed by caller
+                OPENSSL_free(extensions as *mut c_void);
+            }
+        }
+        for ext_type in client_hello.extensions.clone() {
+            let mut data: *const c_uchar = std::ptr::null();
+            let mut size = 0;
+            if SSL_client_hello_get0_ext(ptr, ext_type as _, &mut data, &mut size) == 1
+                && !data.is_null()
+                && size > 0
+            {
+                client_hello.set_extension(ext_type, std::slice::from_raw_parts(data, size));
+            }
+        }
+    }
+    client_hello
+}
+
+/// Compute the fingerprints of client hello in handshake, and save them in ssl.
+pub fn set_tls_fingerprint_callback(tls_settings: &mut TlsSettings) {
+    tls_settings.set_client_hello_callback(|ssl, _alert| {
+        if let Some(index) = *TLS_FINGERPRINT_INDEX {
+            let client_hello = get_client_hello(ssl);
+            ssl.set_ex_data(
+                index,
+                TlsFingerprint {
+                    ja3: client_hello.ja3(),
+                    ja4: client_hello.ja4(),
+                },
+            );
+        }
+        Ok(ClientHelloResponse::SUCCESS)
+    });
+}
+
+/// Get the tls fingerprint saved in ssl after handshake,
+/// it's none if the fingerprint callback is not set.
+pub fn get_tls_fingerprint(ssl: &SslRef) -> Option<TlsFingerprint> {
+    let index = (*TLS_FINGERPRINT_INDEX)?;
+    ssl.ex_data(index).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_grease, ClientHello};
+    use pretty_assertions::assert_eq;
+
+    fn new_client_hello() -> ClientHello {
+        let mut client_hello = ClientHello {
+            version: 0x0303,
+            ciphers: vec![0x0a0a, 0x1301, 0x1302, 0x1303, 0xc02b],
+            extensions: vec![
+                0x1a1a, 0x0000, 0x0017, 0x000a, 0x000b, 0x000d, 0x0010, 0x002b,
+            ],
+            ..Default::default()
+        };
+        client_hello.set_extension(0x0000, &[]);
+        client_hello.set_extension(0x000a, &[0x00, 0x06, 0x2a, 0x2a, 0x00, 0x1d, 0x00, 0x17]);
+        client_hello.set_extension(0x000b, &[0x01, 0x00]);
+        client_hello.set_extension(0x000d, &[0x00, 0x04, 0x04, 0x03, 0x08, 0x04]);
+        client_hello.set_extension(0x0010, &[0x00, 0x03, 0x02, 0x68, 0x32]);
+        client_hello.set_extension(0x002b, &[0x04, 0x03, 0x04, 0x03, 0x03]);
+        client_hello
+    }
+
+    #[test]
+    fn test_is_grease() {
+        assert_eq!(true, is_grease(0x0a0a));
+        assert_eq!(true, is_grease(0xfafa));
+        assert_eq!(false, is_grease(0x0a1a));
+        assert_eq!(false, is_grease(0x1301));
+    }
+
+    #[test]
+    fn test_client_hello() {
+        let client_hello = new_client_hello();
+        assert_eq!(true, client_hello.sni);
+        assert_eq!(vec![0x2a2a, 0x001d, 0x0017], client_hello.supported_groups);
+        assert_eq!(vec![0x00], client_hello.ec_point_formats);
+        assert_eq!(vec![0x0403, 0x0804], client_hello.signature_algorithms);
+        assert_eq!(vec![0x0304, 0x0303], client_hello.supported_versions);
+        assert_eq!(b"h2".to_vec(), client_hello.alpn.clone().unwrap());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let client_hello = new_client_hello();
+        // md5 of "771,4865-4866-4867-49195,0-23-10-11-13-16-43,29-23,0"
+        assert_eq!("1d93fa7f86b929b9b00a0181d3314d4c", client_hello.ja3());
+        assert_eq!("t13d0407h2_39e807bd56df_38dbf9c86be1", client_hello.ja4());
+    }
+}
@@ -0,0 +1,530 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::service::{CommonServiceTask, ServiceTask};
+use crate::util;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use foreign_types::ForeignType;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use openssl_sys::{
+    EVP_DecryptInit_ex, EVP_EncryptInit_ex, EVP_aes_256_cbc, EVP_sha256, HMAC_Init_ex, RAND_bytes,
+    SSL_CTX_callback_ctrl, SSL_CTX_ctrl, SSL_CTX_up_ref, SSL_get_SSL_CTX, EVP_CIPHER_CTX, HMAC_CTX,
+    SSL, SSL_CTX,
+};
+use pingora::listeners::TlsSettings;
+use pingora::tls::ssl::{SslContext, SslOptions};
+use snafu::Snafu;
+use std::collections::HashMap;
+use std::os::raw::{c_int, c_long, c_uchar, c_void};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid {message}"))]
+    Invalid { message: String },
+    #[snafu(display("Io error {source}, {file}"))]
+    Io {
+        source: std::io::Error,
+        file: String,
+    },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+// The same as openssl, name(16) + hmac key(32) + aes key(32)
+const TICKET_KEY_SIZE: usize = 80;
+// keep the previous keys for decrypting the tickets after rotation
+const MAX_TICKET_KEYS: usize = 3;
+const DEFAULT_TICKET_KEY_ROTATION: Duration = Duration::from_secs(3600);
+
+const SSL_CTRL_SESS_ACCEPT_GOOD: c_int = 25;
+const SSL_CTRL_SESS_HIT: c_int = 27;
+const SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB: c_int = 72;
+
+extern "C" {
+    fn SSL_CTX_set_timeout(ctx: *mut SSL_CTX, t: c_long) -> c_long;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TicketKey {
+    name: [u8; 16],
+    hmac_key: [u8; 32],
+    aes_key: [u8; 32],
+}
+
+impl TryFrom<&[u8]> for TicketKey {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != TICKET_KEY_SIZE {
+            return Err(Error::Invalid {
+                message: format!("Ticket key should be {TICKET_KEY_SIZE} bytes"),
+            });
+        }
+        let mut key = TicketKey {
+            name: [0; 16],
+            hmac_key: [0; 32],
+            aes_key: [0; 32],
+        };
+        key.name.copy_from_slice(&value[0..16]);
+        key.hmac_key.copy_from_slice(&value[16..48]);
+        key.aes_key.copy_from_slice(&value[48..80]);
+        Ok(key)
+    }
+}
+
+impl TicketKey {
+    fn new_random() -> Result<Self> {
+        let mut buf = [0_u8; TICKET_KEY_SIZE];
+        let result = unsafe { RAND_bytes(buf.as_mut_ptr(), TICKET_KEY_SIZE as c_int) };
+        if result != 1 {
+            return Err(Error::Invalid {
+                message: "Generate random ticket key fail".to_string(),
+            });
+        }
+        TicketKey::try_from(buf.as_slice())
+    }
+}
+
+/// Parse the base64 ticket keys, the first one is used for encryption.
+fn parse_ticket_keys(values: &[String]) -> Result<Vec<TicketKey>> {
+    let mut keys = vec![];
+    for value in values.iter() {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let buf = STANDARD.decode(value).map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+        keys.push(TicketKey::try_from(buf.as_slice())?);
+    }
+    Ok(keys)
+}
+
+fn load_ticket_key_file(file: &str) -> Result<Vec<TicketKey>> {
+    let path = util::resolve_path(file);
+    let data = std::fs::read_to_string(&path).map_err(|e| Error::Io {
+        source: e,
+        file: path.clone(),
+    })?;
+    let values: Vec<String> = data.lines().map(|item| item.to_string()).collect();
+    parse_ticket_keys(&values)
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsSessionOptions {
+    pub cache_size: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub enabled_ticket: bool,
+    pub ticket_keys: Vec<String>,
+    pub ticket_key_file: Option<String>,
+    pub ticket_key_rotation: Option<Duration>,
+}
+
+impl Default for TlsSessionOptions {
+    fn default() -> Self {
+        TlsSessionOptions {
+            cache_size: None,
+            timeout: None,
+            enabled_ticket: true,
+            ticket_keys: vec![],
+            ticket_key_file: None,
+            ticket_key_rotation: None,
+        }
+    }
+}
+
+struct TicketKeys {
+    keys: Vec<TicketKey>,
+    // the file of ticket keys, it will be reloaded when rotation
+    file: Option<String>,
+    // the keys are generated randomly
+    generated: bool,
+    rotation: Duration,
+    rotated_at: u64,
+}
+
+struct TlsContext {
+    // the reference of ssl context is held, so its address
+    // can't be reused by other context before removed
+    ctx: SslContext,
+    name: String,
+}
+
+// address of ssl context -> (ssl context, server name)
+static TLS_CONTEXTS: Lazy<ArcSwap<HashMap<usize, Arc<TlsContext>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+// server name -> ticket keys
+static TICKET_KEYS: Lazy<ArcSwap<HashMap<String, Arc<TicketKeys>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(HashMap::new()));
+
+fn update_ticket_keys(name: &str, keys: TicketKeys) {
+    let mut data = TICKET_KEYS.load().as_ref().clone();
+    data.insert(name.to_string(), Arc::new(keys));
+    TICKET_KEYS.store(Arc::new(data));
+}
+
+fn register_tls_context(ssl_ctx: *mut SSL_CTX, name: &str) -> Result<()> {
+    if ssl_ctx.is_null() {
+        return Err(Error::Invalid {
+            message: "Ssl context is null".to_string(),
+        });
+    }
+    // SAFETY: the pointer is from the alive tls settings and not null,
+    // the reference count is increased before it's owned by the handle,
+    // so the context is freed only after both of them are dropped.
+    let ctx = unsafe {
+        if SSL_CTX_up_ref(ssl_ctx) != 1 {
+            return Err(Error::Invalid {
+                message: "Reference ssl context fail".to_string(),
+            });
+        }
+        SslContext::from_ptr(ssl_ctx)
+    };
+    let mut contexts = TLS_CONTEXTS.load().as_ref().clone();
+    contexts.insert(
+        ssl_ctx as usize,
+        Arc::new(TlsContext {
+            ctx,
+            name: name.to_string(),
+        }),
+    );
+    TLS_CONTEXTS.store(Arc::new(contexts));
+    Ok(())
+}
+
+/// Create the ticket keys of server from the options, the keys of config
+/// and file are loaded every time, so the updated keys can take effect.
+/// The generated keys are shared by all listeners of the server.
+fn new_ticket_keys(name: &str, opts: &TlsSessionOptions) -> Result<TicketKeys> {
+    let rotation = opts
+        .ticket_key_rotation
+        .unwrap_or(DEFAULT_TICKET_KEY_ROTATION);
+    let mut keys = parse_ticket_keys(&opts.ticket_keys)?;
+    if let Some(file) = &opts.ticket_key_file {
+        keys = load_ticket_key_file(file)?;
+    }
+    let generated = keys.is_empty();
+    if generated {
+        if let Some(current) = TICKET_KEYS.load().get(name) {
+            if current.generated {
+                return Ok(TicketKeys {
+                    keys: current.keys.clone(),
+                    file: None,
+                    generated,
+                    rotation,
+                    rotated_at: current.rotated_at,
+                });
+            }
+        }
+        keys.push(TicketKey::new_random()?);
+    }
+    Ok(TicketKeys {
+        keys,
+        file: opts.ticket_key_file.clone(),
+        generated,
+        rotation,
+        rotated_at: util::now().as_secs(),
+    })
+}
+
+type TicketKeyCallback = unsafe extern "C" fn(
+    *mut SSL,
+    *mut c_uchar,
+    *mut c_uchar,
+    *mut EVP_CIPHER_CTX,
+    *mut HMAC_CTX,
+    c_int,
+) -> c_int;
+
+unsafe extern "C" fn ticket_key_callback(
+    ssl: *mut SSL,
+    key_name: *mut c_uchar,
+    iv: *mut c_uchar,
+    ctx: *mut EVP_CIPHER_CTX,
+    hctx: *mut HMAC_CTX,
+    enc: c_int,
+) -> c_int {
+    if ssl.is_null() || key_name.is_null() || iv.is_null() || ctx.is_null() || hctx.is_null() {
+        return -1;
+    }
+    // SAFETY: the ssl is not null and alive during the callback
+    let ssl_ctx = SSL_get_SSL_CTX(ssl) as usize;
+    let keys = if let Some(keys) = TLS_CONTEXTS
+        .load()
+        .get(&ssl_ctx)
+        .and_then(|item| TICKET_KEYS.load().get(&item.name).cloned())
+    {
+        keys
+    } else {
+        return -1;
+    };
+    if keys.keys.is_empty() {
+        return -1;
+    }
+    // encrypt the new ticket with the first key
+    if enc == 1 {
+        let key = &keys.keys[0];
+        // SAFETY: openssl passes the buffers of key name(16) and iv(16)
+        if RAND_bytes(iv, 16) != 1 {
+            return -1;
+        }
+        std::ptr::copy_nonoverlapping(key.name.as_ptr(), key_name, key.name.len());
+        if EVP_EncryptInit_ex(
+            ctx,
+            EVP_aes_256_cbc(),
+            std::ptr::null_mut(),
+            key.aes_key.as_ptr(),
+            iv,
+        ) != 1
+        {
+            return -1;
+        }
+        if HMAC_Init_ex(
+            hctx,
+            key.hmac_key.as_ptr() as *const c_void,
+            key.hmac_key.len() as c_int,
+            EVP_sha256(),
+            std::ptr::null_mut(),
+        ) != 1
+        {
+            return -1;
+        }
+        return 1;
+    }
+    // SAFETY: the key name of ticket is 16 bytes and not null
+    let name = std::slice::from_raw_parts(key_name, 16);
+    for (index, key) in keys.keys.iter().enumerate() {
+        if key.name != name {
+            continue;
+        }
+        if HMAC_Init_ex(
+            hctx,
+            key.hmac_key.as_ptr() as *const c_void,
+            key.hmac_key.len() as c_int,
+            EVP_sha256(),
+            std::ptr::null_mut(),
+        ) != 1
+        {
+            return -1;
+        }
+        if EVP_DecryptInit_ex(
+            ctx,
+            EVP_aes_256_cbc(),
+            std::ptr::null_mut(),
+            key.aes_key.as_ptr(),
+            iv,
+        ) != 1
+        {
+            return -1;
+        }
+        // renew the ticket if it's encrypted by previous key
+        return if index == 0 { 1 } else { 2 };
+    }
+    // key is not found, do full handshake
+    0
+}
+
+/// Set the session cache and ticket options of tls settings.
+pub fn set_tls_session(
+    tls_settings: &mut TlsSettings,
+    name: &str,
+    opts: &TlsSessionOptions,
+) -> Result<()> {
+    let ssl_ctx = tls_settings.as_ptr();
+    if let Some(size) = opts.cache_size {
+        tls_settings.set_session_cache_size(size as i32);
+    }
+    if let Some(timeout) = opts.timeout {
+        // SAFETY: the ssl context is owned by the tls settings
+        unsafe {
+            SSL_CTX_set_timeout(ssl_ctx, timeout.as_secs() as c_long);
+        }
+    }
+    if !opts.enabled_ticket {
+        tls_settings.set_options(SslOptions::NO_TICKET);
+        return register_tls_context(ssl_ctx, name);
+    }
+    // use the default ticket keys of openssl
+    if opts.ticket_keys.is_empty()
+        && opts.ticket_key_file.is_none()
+        && opts.ticket_key_rotation.is_none()
+    {
+        return register_tls_context(ssl_ctx, name);
+    }
+    let keys = new_ticket_keys(name, opts)?;
+    // SAFETY: the callback has the signature of ticket key callback,
+    // it's transmuted as the generic callback type of openssl
+    let result = unsafe {
+        let callback =
+            std::mem::transmute::<TicketKeyCallback, unsafe extern "C" fn()>(ticket_key_callback);
+        SSL_CTX_callback_ctrl(ssl_ctx, SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB, Some(callback))
+    };
+    if result != 1 {
+        return Err(Error::Invalid {
+            message: "Set ticket key callback fail".to_string(),
+        });
+    }
+    // register after all settings are successful
+    update_ticket_keys(name, keys);
+    register_tls_context(ssl_ctx, name)
+}
+
+/// Get the count of resumed and full tls handshakes of all servers.
+pub fn get_tls_handshake_stats() -> (u64, u64) {
+    let mut resumed = 0;
+    let mut accepted = 0;
+    for item in TLS_CONTEXTS.load().values() {
+        let ssl_ctx = item.ctx.as_ptr();
+        // SAFETY: the ssl context is held by the handle of map
+        unsafe {
+            resumed += SSL_CTX_ctrl(ssl_ctx, SSL_CTRL_SESS_HIT, 0, std::ptr::null_mut()) as u64;
+            accepted +=
+                SSL_CTX_ctrl(ssl_ctx, SSL_CTRL_SESS_ACCEPT_GOOD, 0, std::ptr::null_mut()) as u64;
+        }
+    }
+    (resumed, accepted.saturating_sub(resumed))
+}
+
+fn rotate_ticket_keys(keys: &TicketKeys) -> Result<Vec<TicketKey>> {
+    if let Some(file) = &keys.file {
+        return load_ticket_key_file(file);
+    }
+    let mut new_keys = vec![TicketKey::new_random()?];
+    new_keys.extend(keys.keys.clone());
+    new_keys.truncate(MAX_TICKET_KEYS);
+    Ok(new_keys)
+}
+
+struct TicketKeyRotation {}
+
+#[async_trait]
+impl ServiceTask for TicketKeyRotation {
+    async fn run(&self) -> Option<bool> {
+        let now = util::now().as_secs();
+        for (name, keys) in TICKET_KEYS.load().iter() {
+            // the keys from config can not be rotated
+            if !keys.generated && keys.file.is_none() {
+                continue;
+            }
+            if now < keys.rotated_at + keys.rotation.as_secs() {
+                continue;
+            }
+            match rotate_ticket_keys(keys) {
+                Ok(new_keys) => {
+                    info!("Rotate tls ticket keys success, server: {name}");
+                    update_ticket_keys(
+                        name,
+                        TicketKeys {
+                            keys: new_keys,
+                            file: keys.file.clone(),
+                            generated: keys.generated,
+                            rotation: keys.rotation,
+                            rotated_at: now,
+                        },
+                    );
+                }
+                Err(e) => error!("Rotate tls ticket keys fail, server: {name}, {e}"),
+            };
+        }
+        None
+    }
+    fn description(&self) -> String {
+        let servers: Vec<String> = TICKET_KEYS.load().keys().cloned().collect();
+        format!("servers: {servers:?}")
+    }
+}
+
+pub fn new_ticket_key_rotation_service() -> CommonServiceTask {
+    CommonServiceTask::new(
+        "Tls ticket key rotation",
+        Duration::from_secs(60),
+        TicketKeyRotation {},
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        new_ticket_keys, parse_ticket_keys, rotate_ticket_keys, update_ticket_keys, TicketKey,
+        TicketKeys, TlsSessionOptions,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_ticket_keys() {
+        let keys = parse_ticket_keys(&[STANDARD.encode([1_u8; 80]), "".to_string()]).unwrap();
+        assert_eq!(1, keys.len());
+        assert_eq!([1_u8; 16], keys[0].name);
+
+        assert_eq!(
+            "Invalid Ticket key should be 80 bytes",
+            parse_ticket_keys(&[STANDARD.encode([1_u8; 48])])
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_rotate_ticket_keys() {
+        let mut keys = TicketKeys {
+            keys: vec![TicketKey::new_random().unwrap()],
+            file: None,
+            generated: true,
+            rotation: Duration::from_secs(60),
+            rotated_at: 0,
+        };
+        for _ in 0..5 {
+            let new_keys = rotate_ticket_keys(&keys).unwrap();
+            assert_eq!(keys.keys[0], new_keys[1]);
+            keys.keys = new_keys;
+        }
+        assert_eq!(3, keys.keys.len());
+    }
+
+    #[test]
+    fn test_new_ticket_keys() {
+        let opts = TlsSessionOptions {
+            ticket_key_rotation: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let keys = new_ticket_keys("ticket-test", &opts).unwrap();
+        assert_eq!(true, keys.generated);
+        assert_eq!(1, keys.keys.len());
+        let key = keys.keys[0].clone();
+        update_ticket_keys("ticket-test", keys);
+
+        // the generated keys are shared by listeners of server
+        let keys = new_ticket_keys("ticket-test", &opts).unwrap();
+        assert_eq!(key, keys.keys[0]);
+
+        // the keys of config are loaded every time
+        let keys = new_ticket_keys(
+            "ticket-test",
+            &TlsSessionOptions {
+                ticket_keys: vec![STANDARD.encode([2_u8; 80])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(false, keys.generated);
+        assert_eq!([2_u8; 16], keys.keys[0].name);
+    }
+}
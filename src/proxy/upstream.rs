@@ -465,25 +465,39 @@ impl Upstream {
                 lb.select(value.as_bytes(), 256)
             }
         };
-        upstream.map(|upstream| {
-            let mut p = HttpPeer::new(upstream, self.tls, self.sni.clone());
-            p.options.connection_timeout = self.connection_timeout;
-            p.options.total_connection_timeout = self.total_connection_timeout;
-            p.options.read_timeout = self.read_timeout;
-            p.options.idle_timeout = self.idle_timeout;
-            p.options.write_timeout = self.write_timeout;
-            p.options.alpn = self.alpn.clone();
-            if let Some(verify_cert) = self.verify_cert {
-                p.options.verify_cert = verify_cert;
-            }
-            if let Some(tcp_fast_open) = self.tcp_fast_open {
-                p.options.tcp_fast_open = tcp_fast_open;
-            }
-            p.options.tcp_recv_buf = self.tcp_recv_buf;
-            p.options.tcp_keepalive.clone_from(&self.tcp_keepalive);
-            p.options.tracer.clone_from(&self.tracer);
-            p
-        })
+        upstream.map(|upstream| self.new_peer(upstream))
+    }
+
+    /// Returns a new peer for stream proxy, the client ip is used as the key of consistent hash.
+    /// If there is no healthy backend, it will return `None`.
+    #[inline]
+    pub fn new_stream_peer(&self, client_ip: &str) -> Option<HttpPeer> {
+        let upstream = match &self.lb {
+            SelectionLb::RoundRobin(lb) => lb.select(b"", 256),
+            SelectionLb::Consistent(lb) => lb.select(client_ip.as_bytes(), 256),
+        };
+        upstream.map(|upstream| self.new_peer(upstream))
+    }
+
+    #[inline]
+    fn new_peer(&self, upstream: Backend) -> HttpPeer {
+        let mut p = HttpPeer::new(upstream, self.tls, self.sni.clone());
+        p.options.connection_timeout = self.connection_timeout;
+        p.options.total_connection_timeout = self.total_connection_timeout;
+        p.options.read_timeout = self.read_timeout;
+        p.options.idle_timeout = self.idle_timeout;
+        p.options.write_timeout = self.write_timeout;
+        p.options.alpn = self.alpn.clone();
+        if let Some(verify_cert) = self.verify_cert {
+            p.options.verify_cert = verify_cert;
+        }
+        if let Some(tcp_fast_open) = self.tcp_fast_open {
+            p.options.tcp_fast_open = tcp_fast_open;
+        }
+        p.options.tcp_recv_buf = self.tcp_recv_buf;
+        p.options.tcp_keepalive.clone_from(&self.tcp_keepalive);
+        p.options.tracer.clone_from(&self.tracer);
+        p
     }
 
     /// Get the connected count of upstream
@@ -786,5 +800,18 @@ mod tests {
             up.new_http_peer(&session, &State::default(),).is_some()
         );
         assert_eq!(true, up.as_round_robind().is_some());
+
+        let up = Upstream::new(
+            "upstreamname",
+            &UpstreamConf {
+                addrs: vec!["192.168.1.1:5432".to_string()],
+                algo: Some("hash:ip".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let peer = up.new_stream_peer("10.1.1.1").unwrap();
+        assert_eq!("192.168.1.1:5432", peer.address().to_string());
+        assert_eq!(true, up.as_consistent().is_some());
     }
 }
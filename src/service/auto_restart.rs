@@ -13,8 +13,8 @@
 // limitations under the License.
 
 use crate::config::{
-    self, get_config_path, get_current_config, load_config, PingapConf, CATEGORY_LOCATION,
-    CATEGORY_UPSTREAM,
+    self, get_config_path, get_current_config, load_config, redact_secrets, resolve_secrets,
+    PingapConf, CATEGORY_LOCATION, CATEGORY_UPSTREAM,
 };
 use crate::service::{CommonServiceTask, ServiceTask};
 use crate::state::restart;
@@ -27,22 +27,38 @@ use std::time::Duration;
 async fn hot_reload(
     hot_reload_only: bool,
 ) -> Result<(bool, Vec<String>), Box<dyn std::error::Error>> {
-    let conf = load_config(&get_config_path(), false).await?;
+    let conf = resolve_secrets(&load_config(&get_config_path(), false).await?)?;
     conf.validate()?;
     let mut current_conf: PingapConf = get_current_config().as_ref().clone();
     let mut should_reload_server_location = false;
+    let mut should_reload_certificate = false;
     for (name, server) in conf.servers.iter() {
         if let Some(old) = current_conf.servers.get_mut(name) {
             if server.locations != old.locations {
                 should_reload_server_location = true;
                 old.locations.clone_from(&server.locations);
             }
+            // the certificates of tls server can be replaced without restart,
+            // but the server should be listened on tls before
+            if server.is_tls() && old.is_tls() && server.is_tls_changed(old) {
+                should_reload_certificate = true;
+                old.tls_cert.clone_from(&server.tls_cert);
+                old.tls_key.clone_from(&server.tls_key);
+                old.tls_certificates.clone_from(&server.tls_certificates);
+                old.tls_certificate_dir
+                    .clone_from(&server.tls_certificate_dir);
+            }
         }
     }
-    let (updated_category_list, diff_result) = current_conf.diff(&conf);
-    if !should_reload_server_location && updated_category_list.is_empty() {
+    let (updated_category_list, _) = current_conf.diff(&conf);
+    if !should_reload_server_location
+        && !should_reload_certificate
+        && updated_category_list.is_empty()
+    {
         return Ok((false, vec![]));
     }
+    // the secrets should not be sent by webhook
+    let (_, diff_result) = redact_secrets(&current_conf)?.diff(&redact_secrets(&conf)?);
 
     let mut should_reload_upstream = false;
     let mut should_reload_location = false;
@@ -86,6 +102,18 @@ async fn hot_reload(
         };
     }
 
+    if should_reload_certificate {
+        let server_conf_list: Vec<proxy::ServerConf> = conf.clone().into();
+        match proxy::try_reload_certificates(&server_conf_list) {
+            Err(e) => {
+                error!("Reload certificate fail, error: {e:?}");
+            }
+            Ok(servers) => {
+                info!("Reload certificate success, servers: {servers:?}");
+            }
+        };
+    }
+
     if hot_reload_only {
         // update current config only hot reload config updated
         // the next check will not trigger hot reload
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use bytes::{Bytes, BytesMut};
-use http::StatusCode;
+use http::{StatusCode, Uri};
 use pingora_limits::inflight::Guard;
 use std::time::{Duration, Instant};
 
@@ -38,8 +38,18 @@ pub struct State {
     pub accepted: u64,
     pub location_processing: i32,
     pub location_accepted: u64,
+    pub websocket: bool,
+    pub websocket_processing: i32,
+    pub location_websocket_processing: i32,
     pub created_at: Instant,
+    // the time of receiving the first chunk of request body
+    pub request_body_started_at: Option<Instant>,
     pub tls_version: Option<String>,
+    pub client_cert_subject: Option<Vec<String>>,
+    pub client_cert_sans: Option<Vec<String>>,
+    pub client_cert_fingerprint: Option<String>,
+    pub ja3: Option<String>,
+    pub ja4: Option<String>,
     pub status: Option<StatusCode>,
     pub established: u64,
     pub response_body_size: usize,
@@ -62,6 +72,11 @@ pub struct State {
     pub compression_stat: Option<CompressionStat>,
     pub modify_response_body: Option<Box<dyn ModifyResponseBody>>,
     pub response_body: Option<BytesMut>,
+    pub error_page_location: Option<String>,
+    pub error_page_status: Option<u16>,
+    // the uri before it's rewritten by location
+    pub original_uri: Option<Uri>,
+    pub draining: bool,
 }
 
 impl Default for State {
@@ -71,10 +86,19 @@ impl Default for State {
             accepted: 0,
             location_processing: 0,
             location_accepted: 0,
+            websocket: false,
+            websocket_processing: 0,
+            location_websocket_processing: 0,
             tls_version: None,
+            client_cert_subject: None,
+            client_cert_sans: None,
+            client_cert_fingerprint: None,
+            ja3: None,
+            ja4: None,
             status: None,
             established: 0,
             created_at: Instant::now(),
+            request_body_started_at: None,
             response_body_size: 0,
             reused: false,
             location: "".to_string(),
@@ -95,6 +119,10 @@ impl Default for State {
             compression_stat: None,
             modify_response_body: None,
             response_body: None,
+            error_page_location: None,
+            error_page_status: None,
+            original_uri: None,
+            draining: false,
         }
     }
 }
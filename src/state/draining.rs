@@ -0,0 +1,118 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::webhook;
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PROCESS_DRAINING: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
+static DRAINING_SERVERS: Lazy<ArcSwap<Vec<String>>> = Lazy::new(|| ArcSwap::from_pointee(vec![]));
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct DrainingStatus {
+    pub process: bool,
+    pub servers: Vec<String>,
+}
+
+fn notify_draining(target: &str, draining: bool) {
+    let msg = if draining {
+        format!("{target} enters draining mode, pid:{}", std::process::id())
+    } else {
+        format!("{target} leaves draining mode, pid:{}", std::process::id())
+    };
+    webhook::send(webhook::SendNotificationParams {
+        level: webhook::NotificationLevel::Info,
+        category: webhook::NotificationCategory::Draining,
+        msg,
+    });
+}
+
+/// Set the draining mode of the server, the whole process will be set if the server is none.
+/// Returns true if the draining mode is changed.
+pub fn set_draining(server: Option<&str>, draining: bool) -> bool {
+    let changed = if let Some(server) = server {
+        // the update is retried if the servers are changed concurrently
+        let previous = DRAINING_SERVERS.rcu(|servers| {
+            let mut servers = servers.to_vec();
+            let exists = servers.iter().any(|item| item == server);
+            if draining && !exists {
+                servers.push(server.to_string());
+            } else if !draining {
+                servers.retain(|item| item != server);
+            }
+            servers
+        });
+        previous.iter().any(|item| item == server) != draining
+    } else {
+        PROCESS_DRAINING.swap(draining, Ordering::Relaxed) != draining
+    };
+    if changed {
+        let target = if let Some(server) = server {
+            format!("Server {server}")
+        } else {
+            "Process".to_string()
+        };
+        notify_draining(&target, draining);
+    }
+    changed
+}
+
+/// Returns true if the whole process or the server is in draining mode.
+pub fn is_draining(server: &str) -> bool {
+    if PROCESS_DRAINING.load(Ordering::Relaxed) {
+        return true;
+    }
+    DRAINING_SERVERS.load().iter().any(|item| item == server)
+}
+
+/// Get the draining status of process and servers.
+pub fn get_draining_status() -> DrainingStatus {
+    DrainingStatus {
+        process: PROCESS_DRAINING.load(Ordering::Relaxed),
+        servers: DRAINING_SERVERS.load().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_draining_status, is_draining, set_draining, DrainingStatus};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_draining() {
+        assert_eq!(false, is_draining("test"));
+
+        assert_eq!(true, set_draining(Some("test"), true));
+        assert_eq!(false, set_draining(Some("test"), true));
+        assert_eq!(true, is_draining("test"));
+        assert_eq!(false, is_draining("charts"));
+
+        assert_eq!(true, set_draining(None, true));
+        assert_eq!(true, is_draining("charts"));
+        assert_eq!(
+            DrainingStatus {
+                process: true,
+                servers: vec!["test".to_string()],
+            },
+            get_draining_status()
+        );
+
+        assert_eq!(true, set_draining(None, false));
+        assert_eq!(true, set_draining(Some("test"), false));
+        assert_eq!(false, is_draining("test"));
+        assert_eq!(DrainingStatus::default(), get_draining_status());
+    }
+}
@@ -13,6 +13,8 @@
 // limitations under the License.
 
 mod ctx;
+mod draining;
 mod process;
 pub use ctx::*;
+pub use draining::*;
 pub use process::*;
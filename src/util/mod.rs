@@ -60,6 +60,31 @@ pub static HTTP_HEADER_X_FORWARDED_FOR: Lazy<http::HeaderName> =
 pub static HTTP_HEADER_X_REAL_IP: Lazy<http::HeaderName> =
     Lazy::new(|| HeaderName::from_str("X-Real-Ip").unwrap());
 
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// The client ip of unix socket peer, it's not a valid ip,
+/// so it can't be confused with the loopback clients.
+pub const UNIX_CLIENT_IP: &str = UNIX_SOCKET_PREFIX;
+
+/// Gets the socket path of unix listen address, e.g. `unix:/run/pingap.sock`.
+/// The abstract socket is defined as `unix:@pingap` and its path starts with nul byte,
+/// it's rejected by the server config for now.
+pub fn get_unix_socket_path(addr: &str) -> Option<String> {
+    let path = addr.trim().strip_prefix(UNIX_SOCKET_PREFIX)?;
+    if let Some(name) = path.strip_prefix('@') {
+        return Some(format!("\0{name}"));
+    }
+    Some(path.to_string())
+}
+
+/// Returns true if the client is connected through unix socket.
+pub fn is_unix_peer(session: &Session) -> bool {
+    if let Some(addr) = session.client_addr() {
+        return addr.as_unix().is_some();
+    }
+    false
+}
+
 pub fn get_remote_addr(session: &Session) -> Option<String> {
     if let Some(addr) = session.client_addr() {
         if let Some(addr) = addr.as_inet() {
@@ -71,7 +96,8 @@ pub fn get_remote_addr(session: &Session) -> Option<String> {
 
 /// Gets client ip from X-Forwarded-For,
 /// If none, get from X-Real-Ip,
-/// If none, get remote addr.
+/// If none, get remote addr,
+/// If the client is connected through unix socket, returns `unix:`.
 pub fn get_client_ip(session: &Session) -> String {
     if let Some(value) = session.get_header(HTTP_HEADER_X_FORWARDED_FOR.clone()) {
         let arr: Vec<&str> = value.to_str().unwrap_or_default().split(',').collect();
@@ -85,6 +111,10 @@ pub fn get_client_ip(session: &Session) -> String {
     if let Some(addr) = get_remote_addr(session) {
         return addr;
     }
+    // the peer of unix socket has no ip
+    if is_unix_peer(session) {
+        return UNIX_CLIENT_IP.to_string();
+    }
     "".to_string()
 }
 
@@ -212,13 +242,57 @@ pub fn get_host(header: &RequestHeader) -> Option<&str> {
         return Some(host);
     }
     if let Some(host) = header.headers.get("Host") {
-        if let Ok(value) = host.to_str().map(|host| host.split(':').next()) {
-            return value;
+        if let Ok(host) = host.to_str() {
+            // ipv6 host, e.g. [::1]:8080
+            if host.starts_with('[') {
+                return host.find(']').map(|index| &host[..=index]);
+            }
+            return host.split(':').next();
         }
     }
     None
 }
 
+/// Encode the values as a list of structured field strings(RFC 8941),
+/// e.g. `"CN=svc-a", "O=pingap"`. The `%` and the characters which are not
+/// printable ascii are percent encoded, so every value is kept unambiguously.
+pub fn to_sf_string_list(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| {
+            let mut item = String::with_capacity(value.len() + 2);
+            item.push('"');
+            for ch in value.bytes() {
+                match ch {
+                    b'"' | b'\\' => {
+                        item.push('\\');
+                        item.push(ch as char);
+                    }
+                    b'%' | 0..=0x1f | 0x7f..=0xff => {
+                        item.push_str(&format!("%{ch:02X}"));
+                    }
+                    _ => item.push(ch as char),
+                }
+            }
+            item.push('"');
+            item
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Test whether or not the request is a websocket upgrade request.
+pub fn is_websocket_upgrade(header: &RequestHeader) -> bool {
+    let upgrade = get_req_header_value(header, "Upgrade").unwrap_or_default();
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return false;
+    }
+    get_req_header_value(header, "Connection")
+        .unwrap_or_default()
+        .split(',')
+        .any(|item| item.trim().eq_ignore_ascii_case("upgrade"))
+}
+
 /// Get the content length from http request header.
 pub fn get_content_length(header: &RequestHeader) -> Option<usize> {
     if let Some(content_length) = header.headers.get(http::header::CONTENT_LENGTH) {
@@ -256,8 +330,9 @@ pub fn convert_tls_version(version: &Option<String>) -> Option<SslVersion> {
 #[cfg(test)]
 mod tests {
     use super::{
-        convert_tls_version, get_latency, get_pkg_name, get_pkg_version, local_ip_list,
-        remove_query_from_header, resolve_path,
+        convert_tls_version, get_host, get_latency, get_pkg_name, get_pkg_version,
+        get_unix_socket_path, is_websocket_upgrade, local_ip_list, remove_query_from_header,
+        resolve_path, to_sf_string_list,
     };
     use pingora::{http::RequestHeader, tls::ssl::SslVersion};
     use pretty_assertions::assert_eq;
@@ -272,6 +347,57 @@ mod tests {
         assert_eq!("/?name=pingap", req.uri.to_string());
     }
 
+    #[test]
+    fn test_get_host() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(None, get_host(&req));
+        req.insert_header("Host", "pingap.io:3000").unwrap();
+        assert_eq!(Some("pingap.io"), get_host(&req));
+        req.insert_header("Host", "[::1]:3000").unwrap();
+        assert_eq!(Some("[::1]"), get_host(&req));
+        req.insert_header("Host", "[::1]").unwrap();
+        assert_eq!(Some("[::1]"), get_host(&req));
+
+        let req = RequestHeader::build("GET", b"http://pingap.io:3000/", None).unwrap();
+        assert_eq!(Some("pingap.io"), get_host(&req));
+    }
+
+    #[test]
+    fn test_to_sf_string_list() {
+        assert_eq!("", to_sf_string_list(&[]));
+        assert_eq!(
+            r#""CN=svc-a, O=pingap", "O=\"x\"\\y", "CN=%E4%B8%AD100%25""#,
+            to_sf_string_list(&[
+                "CN=svc-a, O=pingap".to_string(),
+                r#"O="x"\y"#.to_string(),
+                "CN=中100%".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_is_websocket_upgrade() {
+        let mut req = RequestHeader::build("GET", b"/ws", None).unwrap();
+        assert_eq!(false, is_websocket_upgrade(&req));
+
+        req.insert_header("Upgrade", "websocket").unwrap();
+        assert_eq!(false, is_websocket_upgrade(&req));
+
+        req.insert_header("Connection", "keep-alive, Upgrade")
+            .unwrap();
+        assert_eq!(true, is_websocket_upgrade(&req));
+    }
+
+    #[test]
+    fn test_get_unix_socket_path() {
+        assert_eq!(
+            "/run/pingap.sock",
+            get_unix_socket_path("unix:/run/pingap.sock").unwrap()
+        );
+        assert_eq!("\0pingap", get_unix_socket_path("unix:@pingap").unwrap());
+        assert_eq!(true, get_unix_socket_path("127.0.0.1:3000").is_none());
+    }
+
     #[test]
     fn test_get_pkg_info() {
         assert_eq!("pingap", get_pkg_name());
@@ -29,6 +29,7 @@ pub fn set_web_hook(url: &str, category: &str, notifications: &[String]) {
     WEBHOOK_NOTIFICATIONS.get_or_init(|| notifications.to_owned());
 }
 
+#[derive(PartialEq, Debug, Clone)]
 pub enum NotificationLevel {
     Info,
     Warn,
@@ -44,6 +45,7 @@ pub enum NotificationCategory {
     Restart,
     RestartFail,
     TlsValidity,
+    Draining,
 }
 
 impl Display for NotificationLevel {